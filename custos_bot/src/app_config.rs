@@ -1,14 +1,15 @@
-use anyhow::Result;
-use clap::{Arg, Command};
-use config::Config;
+use anyhow::{Error, Result};
+use clap::{Arg, ArgAction, Command};
+use config::{builder::DefaultState, Config, ConfigBuilder};
+use twilight_model::id::{
+    marker::{GuildMarker, UserMarker},
+    Id,
+};
 
 pub fn read_config() -> Result<Config> {
-    let mut config = Config::builder()
-        .add_source(config::File::with_name("config.toml").required(false))
-        .set_default("db_name", "custos")?
-        .set_default("mongodb_address", "mongodb://127.0.0.1:27017/")?
-        .set_default("register_global_commands", false)?
-        .set_default("register_indexes", false)?;
+    let mut config = with_defaults(
+        Config::builder().add_source(config::File::with_name("config.toml").required(false)),
+    )?;
 
     let matches = Command::new("hayat_online")
         .version("0.1")
@@ -22,12 +23,18 @@ pub fn read_config() -> Result<Config> {
                 .required(false)
                 .num_args(0),
         )
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue),
+        )
         .get_matches();
 
     let db_name = matches.get_one::<String>("db_name");
     let mongodb_address = matches.get_one::<String>("mongodb_address");
     let token = matches.get_one::<String>("token");
     let register_indexes = matches.get_one::<bool>("register_indexes");
+    let dry_run = matches.get_flag("dry_run");
 
     if let Some(db_name) = db_name {
         config = config.set_override("db_name", db_name.clone())?;
@@ -44,5 +51,118 @@ pub fn read_config() -> Result<Config> {
     if let Some(register_indexes) = register_indexes {
         config = config.set_override("register_indexes", *register_indexes)?;
     }
+
+    if dry_run {
+        config = config.set_override("dry_run", true)?;
+    }
     Ok(config.build()?)
 }
+
+/// The values of the settings that don't have to be configured.
+fn with_defaults(builder: ConfigBuilder<DefaultState>) -> Result<ConfigBuilder<DefaultState>> {
+    Ok(builder
+        .set_default("db_name", "custos")?
+        .set_default("mongodb_address", "mongodb://127.0.0.1:27017/")?
+        .set_default("register_global_commands", false)?
+        .set_default("dev_guild_ids", Vec::<String>::new())?
+        .set_default("eval_prefix", "!eval ")?
+        .set_default("eval_allowed_users", "")?
+        .set_default("tag_prefix", "!tag ")?
+        .set_default("register_indexes", false)?
+        .set_default("dry_run", false)?)
+}
+
+/// Guilds the commands are registered to on startup, they're available there instantly
+/// unlike global commands. Accepts the ids as integers or strings.
+pub fn dev_guild_ids(config: &Config) -> Result<Vec<Id<GuildMarker>>> {
+    config
+        .get_array("dev_guild_ids")?
+        .into_iter()
+        .map(|value| {
+            let id = value.into_string()?;
+            id.parse()
+                .ok()
+                .and_then(Id::new_checked)
+                .ok_or_else(|| Error::msg(format!("Invalid guild id in dev_guild_ids: {}", id)))
+        })
+        .collect()
+}
+
+/// The eval and tag settings, read once on startup so every message doesn't parse them again.
+#[derive(Debug)]
+pub struct EvalConfig {
+    pub prefix: String,
+    pub allowed_users: Vec<Id<UserMarker>>,
+    /// Messages starting with it run a stored tag, like `!tag name args`. Anyone can run
+    /// tags, an empty prefix turns the trigger off.
+    pub tag_prefix: String,
+}
+
+impl EvalConfig {
+    pub fn read(config: &Config) -> Result<Self> {
+        Ok(EvalConfig {
+            prefix: config.get_string("eval_prefix")?,
+            allowed_users: eval_allowed_users(config)?,
+            tag_prefix: config.get_string("tag_prefix")?,
+        })
+    }
+}
+
+/// Users allowed to run scripts with `eval_prefix`, a comma separated list of ids.
+/// Nobody can if it's empty.
+fn eval_allowed_users(config: &Config) -> Result<Vec<Id<UserMarker>>> {
+    parse_user_ids(&config.get_string("eval_allowed_users")?)
+}
+
+fn parse_user_ids(list: &str) -> Result<Vec<Id<UserMarker>>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .ok()
+                .and_then(Id::new_checked)
+                .ok_or_else(|| Error::msg(format!("Invalid user id in eval_allowed_users: {}", id)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_ids_are_comma_separated() {
+        assert_eq!(
+            parse_user_ids(" 123, 456 ,789").unwrap(),
+            vec![Id::new(123), Id::new(456), Id::new(789)]
+        );
+    }
+
+    #[test]
+    fn empty_user_ids_allow_nobody() {
+        assert!(parse_user_ids("").unwrap().is_empty());
+        assert!(parse_user_ids(" , ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_user_ids_are_rejected() {
+        assert!(parse_user_ids("123,abc").is_err());
+        // ids can't be zero
+        assert!(parse_user_ids("0").is_err());
+        assert!(parse_user_ids("-1").is_err());
+    }
+
+    #[test]
+    fn register_global_commands_defaults_to_false() {
+        // a config file that only sets the token
+        let file = config::File::from_str("token = \"token\"", config::FileFormat::Toml);
+        let config = with_defaults(Config::builder().add_source(file))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert!(!config.get_bool("register_global_commands").unwrap());
+        assert!(dev_guild_ids(&config).unwrap().is_empty());
+    }
+}
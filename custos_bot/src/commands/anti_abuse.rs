@@ -1,18 +1,21 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::{Error, Result};
 use async_trait::async_trait;
 use bson::{doc, to_bson};
 use lazy_static::lazy_static;
-use mongodb::options::FindOneOptions;
-use tracing::{error_span, warn_span};
+use tracing::error_span;
 use twilight_gateway::stream::ShardRef;
 use twilight_http::client::InteractionClient;
 use twilight_model::{
     application::{
         command::{CommandOptionChoice, CommandOptionChoiceValue, CommandType},
         interaction::{
-            application_command::{CommandData, CommandOptionValue},
+            application_command::{CommandData, CommandDataOption, CommandOptionValue},
             message_component::MessageComponentInteractionData,
         },
     },
@@ -23,91 +26,33 @@ use twilight_model::{
     gateway::payload::incoming::InteractionCreate,
     guild::{audit_log::AuditLogEventType, Permissions},
     http::interaction::{InteractionResponse, InteractionResponseType},
+    id::{marker::GuildMarker, Id},
 };
-use twilight_util::{
-    builder::{
-        command::{
-            CommandBuilder, IntegerBuilder, StringBuilder, SubCommandBuilder,
-            SubCommandGroupBuilder,
-        },
-        InteractionResponseDataBuilder,
+use twilight_util::builder::{
+    command::{
+        BooleanBuilder, CommandBuilder, IntegerBuilder, RoleBuilder, StringBuilder,
+        SubCommandBuilder, SubCommandGroupBuilder, UserBuilder,
     },
-    permission_calculator::PermissionCalculator,
+    InteractionResponseDataBuilder,
 };
 
 use super::CustosCommand;
 use crate::{
     ctx::Context,
-    schemas::{AntiAbuseActionBuilder, AntiAbuseEventConfig, GuildConfig},
+    plugins::anti_abuse::{action_from_label, action_label, describe_action, ACTION_LABELS},
+    schemas::{AntiAbuseActionBuilder, AntiAbuseConfig, AntiAbuseEventConfig, GuildConfig},
     util,
 };
 
 lazy_static! {
-    pub static ref ACTION_LABELS: Vec<(String, u16)> = vec![
-        ("Guild Update", 1),
-        ("Channel Create", 10),
-        ("Channel Update", 11),
-        ("Channel Delete", 12),
-        ("Channel Overwrite Create", 13),
-        ("Channel Overwrite Update", 14),
-        ("Channel Overwrite Delete", 15),
-        ("Member Kick", 20),
-        ("Member Prune", 21),
-        ("Member Ban Add", 22),
-        ("Member Ban Remove", 23),
-        ("Member Update", 24),
-        ("Member Role Update", 25),
-        ("Member Move", 26),
-        ("Member Disconnect", 17),
-        ("Bot Add", 28),
-        ("Role Create", 30),
-        ("Role Update", 31),
-        ("Role Delete", 32),
-        ("Invite Create", 40),
-        ("Invite Update", 41),
-        ("Invite Delete", 42),
-        ("Webhook Create", 50),
-        ("Webhook Update", 51),
-        ("Webhook Delete", 52),
-        ("Emoji Create", 60),
-        ("Emoji Update", 61),
-        ("Emoji Delete", 62),
-        ("Message Delete", 72),
-        ("Message BulkDelete", 73),
-        ("Message Pin", 74),
-        ("Message Unpin", 75),
-        ("Integration Create", 80),
-        ("Integration Update", 81),
-        ("Integration Delete", 82),
-        ("Stage Instance Create", 83),
-        ("Stage Instance Update", 84),
-        ("Stage Instance Delete", 85),
-        ("Sticker Create", 90),
-        ("Sticker Update", 91),
-        ("Sticker Delete", 92),
-        ("Guild Scheduled Event Create", 100),
-        ("Guild Scheduled Event Update", 101),
-        ("Guild Scheduled Event Delete", 102),
-        ("Thread Create", 110),
-        ("Thread Update", 111),
-        ("Thread Delete", 112),
-        ("Application Command Permission Update", 121),
-        ("Auto Moderation Rule Create", 140),
-        ("Auto Moderation Rule Update", 141),
-        ("Auto Moderation Rule Delete", 142),
-        ("Auto Moderation Block Message", 143),
-        ("Auto Moderation Flag To Channel", 144),
-        ("Auto Moderation User Communication Disabled", 145),
-    ]
-    .into_iter()
-    .map(|(name, code)| {
-        let mut name = name.to_lowercase();
-        if name.contains("guild") {
-            name = name.replace("guild", "server");
-        }
-        (name.to_lowercase(), code)
-    })
-    .collect::<Vec<(String, u16)>>();
+    /// Action types that can be watched. Auto moderation actions are performed by Discord
+    /// on behalf of a rule and not by a moderator, counting them per moderator is meaningless
+    /// so they are left out.
+    pub static ref SELECTABLE_ACTION_LABELS: Vec<(String, u16)> = ACTION_LABELS
+        .iter()
+        .filter(|(_, code)| is_selectable_action(*code))
+        .cloned()
+        .collect::<Vec<(String, u16)>>();
     pub static ref ACTION_MENU_OPTIONS: Vec<SelectMenuOption> = vec![
         SelectMenuOption {
             default: true,
@@ -140,9 +85,248 @@ lazy_static! {
     ];
 }
 
-fn action_label_code_to_str(code: u16) -> Option<String> {
-    let label = ACTION_LABELS.iter().find(|x| x.1 == code);
-    label.as_ref().map(|label| label.0.clone())
+lazy_static! {
+    static ref OVERSIZED_CUSTOM_IDS: Mutex<OversizedCustomIds> = Mutex::default();
+}
+
+/// Custom ids that didn't fit into a component, keyed by the short id sent instead. Nobody
+/// might ever pick from the menu, so the ids expire and only so many are kept.
+#[derive(Default)]
+struct OversizedCustomIds {
+    ids: HashMap<String, (String, Instant)>,
+}
+
+impl OversizedCustomIds {
+    /// How long a menu can be used, after that the command has to be run again.
+    const TTL: Duration = Duration::from_secs(15 * 60);
+    const MAX_IDS: usize = 1000;
+
+    fn insert(&mut self, short_id: String, custom_id: String, now: Instant) {
+        self.ids
+            .retain(|_, (_, inserted_at)| now.duration_since(*inserted_at) < Self::TTL);
+
+        if self.ids.len() >= Self::MAX_IDS {
+            let oldest = self
+                .ids
+                .iter()
+                .min_by_key(|(_, (_, inserted_at))| *inserted_at)
+                .map(|(short_id, _)| short_id.clone());
+            if let Some(oldest) = oldest {
+                self.ids.remove(&oldest);
+            }
+        }
+
+        self.ids.insert(short_id, (custom_id, now));
+    }
+
+    fn remove(&mut self, short_id: &str, now: Instant) -> Option<String> {
+        let (custom_id, inserted_at) = self.ids.remove(short_id)?;
+        (now.duration_since(inserted_at) < Self::TTL).then_some(custom_id)
+    }
+}
+
+/// Discord rejects components with a longer custom id.
+const MAX_CUSTOM_ID_LENGTH: usize = 100;
+
+/// Returns the custom id if it fits into a component, otherwise it's kept here and a short
+/// id made from the key is returned, see `resolve_custom_id`.
+fn fit_custom_id(custom_id: String, key: impl std::fmt::Display) -> String {
+    if custom_id.len() <= MAX_CUSTOM_ID_LENGTH {
+        return custom_id;
+    }
+
+    let short_id = format!("ab-s-{}", key);
+    OVERSIZED_CUSTOM_IDS
+        .lock()
+        .unwrap()
+        .insert(short_id.clone(), custom_id, Instant::now());
+    short_id
+}
+
+/// Resolves a custom id created by `fit_custom_id`, `None` if a short id is no longer known
+/// or has expired.
+fn resolve_custom_id(custom_id: &str) -> Option<String> {
+    if custom_id.starts_with("ab-s-") {
+        return OVERSIZED_CUSTOM_IDS
+            .lock()
+            .unwrap()
+            .remove(custom_id, Instant::now());
+    }
+
+    Some(custom_id.to_owned())
+}
+
+/// Auto Moderation Block Message, Flag To Channel and User Communication Disabled.
+const AUTO_MODERATION_ACTIONS: &[u16] = &[143, 144, 145];
+
+/// Parses the action type option, autocompleted values are codes but a label typed out
+/// by hand is accepted too.
+fn parse_action_type(value: &str) -> Result<u16> {
+    value
+        .parse::<u16>()
+        .ok()
+        .or_else(|| action_from_label(&value.to_lowercase()).map(u16::from))
+        .ok_or_else(|| Error::msg(format!("Unknown action type '{}'.", value)))
+}
+
+fn is_selectable_action(code: u16) -> bool {
+    !AUTO_MODERATION_ACTIONS.contains(&code)
+}
+
+/// Builds the punishment out of the selected punishment values, see `ACTION_MENU_OPTIONS`.
+fn punishment_from_values<'a>(values: impl IntoIterator<Item = &'a str>) -> AntiAbuseActionBuilder {
+    values.into_iter().fold(
+        AntiAbuseActionBuilder::new(),
+        |punishment, value| match value {
+            "action-demote" => punishment.add_demote(),
+            "action-timeout" => punishment.add_timeout(),
+            "action-kick" => punishment.add_kick(),
+            "action-ban" => punishment.add_ban(),
+            _ => punishment,
+        },
+    )
+}
+
+/// Adds the action to the watched actions, overwriting the config of an already watched
+/// action of the same type.
+async fn save_watched_action(
+    context: &Arc<Context>,
+    guild_id: Id<GuildMarker>,
+    action: AntiAbuseEventConfig,
+) -> Result<()> {
+    let guild_config = GuildConfig::get_field(context, guild_id, "anti_abuse")
+        .await?
+        .ok_or_else(|| Error::msg("get_guild did not return guild config."))?;
+
+    let existing_index = guild_config.anti_abuse.as_ref().and_then(|anti_abuse| {
+        anti_abuse
+            .watched_actions
+            .iter()
+            .position(|watched| watched.action_type == action.action_type)
+    });
+
+    let update = match existing_index {
+        Some(index) => doc! {
+            "$set": { {format!("anti_abuse.watched_actions.{index}")}: to_bson(&action)? }
+        },
+        None => doc! {
+            "$push": { "anti_abuse.watched_actions": to_bson(&action)? }
+        },
+    };
+
+    guild_config.update_data_upsert(context, update).await
+}
+
+/// Handles the `whitelist` sub command group, a role and a user can be given at once.
+async fn on_whitelist_command(
+    context: &Arc<Context>,
+    inter: Box<InteractionCreate>,
+    guild_id: Id<GuildMarker>,
+    sub_command: &CommandDataOption,
+) -> Result<()> {
+    let options = match &sub_command.value {
+        CommandOptionValue::SubCommand(sub_cmd) => sub_cmd.as_slice(),
+        _ => &[],
+    };
+
+    let role_id = options.iter().find_map(|opt| match opt.value {
+        CommandOptionValue::Role(role) => Some(role),
+        _ => None,
+    });
+    let user_id = options.iter().find_map(|opt| match opt.value {
+        CommandOptionValue::User(user) => Some(user),
+        _ => None,
+    });
+
+    let anti_abuse = GuildConfig::get_field(context, guild_id, "anti_abuse")
+        .await?
+        .and_then(|g| g.anti_abuse);
+    let (whitelisted_roles, whitelisted_users) = anti_abuse
+        .map(|a| (a.whitelisted_roles, a.whitelisted_users))
+        .unwrap_or_default();
+
+    let mut lines = Vec::new();
+    if sub_command.name == "list" {
+        lines.extend(whitelisted_roles.iter().map(|role| format!("<@&{}>", role)));
+        lines.extend(whitelisted_users.iter().map(|user| format!("<@{}>", user)));
+        if lines.is_empty() {
+            lines.push("Nobody is whitelisted.".to_owned());
+        }
+    } else if role_id.is_none() && user_id.is_none() {
+        lines.push("Please pick a role or a user.".to_owned());
+    } else if sub_command.name == "add" {
+        if let Some(role_id) = role_id {
+            lines.push(if whitelisted_roles.contains(&role_id) {
+                format!("<@&{}> is already whitelisted.", role_id)
+            } else if whitelisted_roles.len() >= AntiAbuseConfig::MAX_WHITELISTED {
+                format!(
+                    "You can't whitelist more than {} roles.",
+                    AntiAbuseConfig::MAX_WHITELISTED
+                )
+            } else {
+                GuildConfig::update_data_by_id_upsert(
+                    context,
+                    doc! { "$addToSet": { "anti_abuse.whitelisted_roles": role_id.to_string() } },
+                    guild_id,
+                )
+                .await?;
+                format!("Members with <@&{}> will no longer be sanctioned.", role_id)
+            });
+        }
+
+        if let Some(user_id) = user_id {
+            lines.push(if whitelisted_users.contains(&user_id) {
+                format!("<@{}> is already whitelisted.", user_id)
+            } else if whitelisted_users.len() >= AntiAbuseConfig::MAX_WHITELISTED {
+                format!(
+                    "You can't whitelist more than {} users.",
+                    AntiAbuseConfig::MAX_WHITELISTED
+                )
+            } else {
+                GuildConfig::update_data_by_id_upsert(
+                    context,
+                    doc! { "$addToSet": { "anti_abuse.whitelisted_users": user_id.to_string() } },
+                    guild_id,
+                )
+                .await?;
+                format!("<@{}> will no longer be sanctioned.", user_id)
+            });
+        }
+    } else if sub_command.name == "remove" {
+        if let Some(role_id) = role_id {
+            GuildConfig::update_data_by_id_upsert(
+                context,
+                doc! { "$pull": { "anti_abuse.whitelisted_roles": role_id.to_string() } },
+                guild_id,
+            )
+            .await?;
+            lines.push(format!(
+                "Members with <@&{}> will be sanctioned again.",
+                role_id
+            ));
+        }
+
+        if let Some(user_id) = user_id {
+            GuildConfig::update_data_by_id_upsert(
+                context,
+                doc! { "$pull": { "anti_abuse.whitelisted_users": user_id.to_string() } },
+                guild_id,
+            )
+            .await?;
+            lines.push(format!("<@{}> will be sanctioned again.", user_id));
+        }
+    }
+
+    let interactions = context.get_interactions();
+    util::send(
+        &interactions,
+        &inter,
+        InteractionResponseType::ChannelMessageWithSource,
+        util::embed_response(lines.join("\n")),
+    )
+    .await?;
+
+    Ok(())
 }
 
 pub struct AntiAbuseCommand {}
@@ -153,8 +337,8 @@ impl CustosCommand for AntiAbuseCommand {
         "anti-abuse".to_owned()
     }
 
-    fn get_component_tag() -> &'static str {
-        "ab"
+    fn get_component_tag() -> Option<&'static str> {
+        Some("ab")
     }
 
     fn get_command_info() -> twilight_model::application::command::Command {
@@ -188,6 +372,31 @@ impl CustosCommand for AntiAbuseCommand {
                             .min_value(60)
                             .max_value(3600)
                             .required(true)
+                    )
+                    .option(
+                        StringBuilder::new(
+                            "punishment",
+                            "The punishment, if left out you can pick one or more from a menu."
+                        )
+                            .choices(
+                                ACTION_MENU_OPTIONS
+                                    .iter()
+                                    .map(|option| (option.label.clone(), option.value.clone())),
+                            )
+                    )
+                    .option(
+                        BooleanBuilder::new(
+                            "ignore_reversed",
+                            "Don't count actions the moderator reversed in the time frame, e.g. a ban followed by an unban."
+                        )
+                    )
+                    .option(
+                        IntegerBuilder::new(
+                            "timeout_duration",
+                            "How long a timeout punishment lasts in seconds, an hour if left out."
+                        )
+                            .min_value(60)
+                            .max_value(AntiAbuseEventConfig::MAX_TIMEOUT_DURATION.into())
                     ),
                 SubCommandBuilder::new("remove", "Remove a watched action.")
                     .option(
@@ -198,83 +407,57 @@ impl CustosCommand for AntiAbuseCommand {
                 SubCommandBuilder::new("list", "Lists all the watched/monitored actions.")
             ]),
         )
+        .option(
+            SubCommandGroupBuilder::new("whitelist", "Manage the roles and users that aren't sanctioned.").subcommands([
+                SubCommandBuilder::new("add", "Stop sanctioning a role or a user.")
+                    .option(RoleBuilder::new("role", "The role to whitelist."))
+                    .option(UserBuilder::new("user", "The user to whitelist.")),
+                SubCommandBuilder::new("remove", "Sanction a whitelisted role or user again.")
+                    .option(RoleBuilder::new("role", "The role to remove from the whitelist."))
+                    .option(UserBuilder::new("user", "The user to remove from the whitelist.")),
+                SubCommandBuilder::new("list", "Lists the whitelisted roles and users.")
+            ]),
+        )
         .build()
     }
 
+    fn required_permissions() -> Permissions {
+        Permissions::MANAGE_GUILD
+    }
+
     async fn on_component_event(
         _shard: ShardRef<'_>,
         context: &Arc<Context>,
         inter: Box<InteractionCreate>,
         component_data: MessageComponentInteractionData,
     ) -> Result<()> {
-        let member = match &inter.member {
-            Some(m) => m,
-            None => return Ok(()),
-        };
-
-        let guild_id = inter.guild_id.unwrap();
-        let user_id = match &member.user {
-            Some(user) => user.id,
+        let guild_id = match inter.guild_id {
+            Some(id) => id,
             None => return Ok(()),
         };
 
-        // Guild-level @everyone role that, by default, allows everyone to view
-        // channels.
-        let everyone_role = Permissions::VIEW_CHANNEL;
-
-        let mut member_roles = Vec::with_capacity(member.roles.len());
-        for role_id in &member.roles {
-            let role = context.get_cache().role(*role_id);
-            if let Some(role) = role {
-                member_roles.push((*role_id, role.permissions));
-            }
-        }
-
-        let member_roles = member_roles.as_slice();
-
-        let calculator = PermissionCalculator::new(guild_id, user_id, everyone_role, member_roles);
-        let calculated_permissions = calculator.root();
+        // the registry already checked `required_permissions`
         let interactions = context.get_interactions();
 
-        let guild = match context.get_cache().guild(guild_id) {
-            Some(g) => g,
+        let custom_id = match resolve_custom_id(&component_data.custom_id) {
+            Some(id) => id,
             None => {
-                warn_span!("Got an interaction but no guild in the cache.");
+                util::send(
+                    &interactions,
+                    &inter,
+                    InteractionResponseType::UpdateMessage,
+                    InteractionResponseDataBuilder::new()
+                        .content("This menu has expired, please run the command again.")
+                        .components([])
+                        .build(),
+                )
+                .await?;
                 return Ok(());
             }
         };
 
-        let owner_id = guild.owner_id();
-        drop(guild);
-
-        if !calculated_permissions.contains(Permissions::MANAGE_GUILD) && user_id != owner_id {
-            util::send(
-                &interactions,
-                &inter,
-                InteractionResponseType::UpdateMessage,
-                InteractionResponseDataBuilder::new()
-                    .content(
-                        "You do not have `Manage Server` permissions to configure this plugin.",
-                    )
-                    .components([Component::ActionRow(ActionRow {
-                        components: vec![Component::SelectMenu(SelectMenu {
-                            custom_id: component_data.custom_id,
-                            disabled: true,
-                            max_values: Some(2),
-                            min_values: Some(1),
-                            options: ACTION_MENU_OPTIONS.clone(),
-                            placeholder: Some("Select a punishment".to_owned()),
-                        })],
-                    })])
-                    .build(),
-            )
-            .await?;
-            return Ok(());
-        }
-
-        if component_data.custom_id.starts_with("ab-a") {
-            let data_values = component_data
-                .custom_id
+        if custom_id.starts_with("ab-a") {
+            let data_values = custom_id
                 .strip_prefix("ab-a")
                 .unwrap()
                 .split('-')
@@ -284,62 +467,27 @@ impl CustosCommand for AntiAbuseCommand {
             let action_type = &data_values[0];
             let max_sanctions = &data_values[1];
             let sanction_cooldown = &data_values[2];
+            let ignore_reversed = data_values.get(3) == Some(&1);
+            let timeout_duration = data_values
+                .get(4)
+                .and_then(|duration| u32::try_from(*duration).ok())
+                .filter(|duration| *duration > 0);
 
-            let guild_config = GuildConfig::get_guild(
+            save_watched_action(
                 context,
                 guild_id,
-                Some(
-                    FindOneOptions::builder()
-                        .projection(doc! { "anti_abuse": 1 })
-                        .build(),
-                ),
+                AntiAbuseEventConfig {
+                    action_type: AuditLogEventType::from(*action_type as u16),
+                    max_sanctions: *max_sanctions,
+                    sanction_cooldown: *sanction_cooldown,
+                    punishment: punishment_from_values(
+                        component_data.values.iter().map(String::as_str),
+                    ),
+                    ignore_reversed,
+                    timeout_duration,
+                },
             )
-            .await?
-            .unwrap();
-
-            let mut existing_index = None;
-            let action_type = AuditLogEventType::from(*action_type as u16);
-
-            if let Some(anti_abuse) = &guild_config.anti_abuse {
-                existing_index = anti_abuse
-                    .watched_actions
-                    .iter()
-                    .position(|action| action.action_type == action_type);
-            }
-
-            if let Some(index) = existing_index {
-                guild_config
-                    .update_data_upsert(
-                        context,
-                        doc! {
-                            "$set": {
-                                {format!("anti_abuse.watched_actions.{index}")}: to_bson(&AntiAbuseEventConfig {
-                                    action_type,
-                                    max_sanctions: *max_sanctions,
-                                    sanction_cooldown: *sanction_cooldown,
-                                    punishment: AntiAbuseActionBuilder::new().add_ban()
-                                })?
-                            }
-                        },
-                    )
-                    .await?;
-            } else {
-                guild_config
-                    .update_data_upsert(
-                        context,
-                        doc! {
-                            "$push": {
-                                "anti_abuse.watched_actions": to_bson(&AntiAbuseEventConfig {
-                                    action_type,
-                                    max_sanctions: *max_sanctions,
-                                    sanction_cooldown: *sanction_cooldown,
-                                    punishment: AntiAbuseActionBuilder::new().add_ban()
-                                })?
-                            }
-                        },
-                    )
-                    .await?;
-            }
+            .await?;
 
             util::send(
                 &interactions,
@@ -376,16 +524,20 @@ impl CustosCommand for AntiAbuseCommand {
         };
 
         let sub_command_group = &data.options[0];
-        if sub_command_group.name != "action" {
-            error_span!("Getting autcomplete for anti_abuse command that is not of sub command group type action.", shard = ?shard.id());
-            return Ok(());
-        }
-
         let sub_command = match &sub_command_group.value {
             CommandOptionValue::SubCommandGroup(d) => &d[0],
             _ => unreachable!(),
         };
 
+        if sub_command_group.name == "whitelist" {
+            return on_whitelist_command(context, inter, guild_id, sub_command).await;
+        }
+
+        if sub_command_group.name != "action" {
+            error_span!("Getting autcomplete for anti_abuse command that is not of sub command group type action.", shard = ?shard.id());
+            return Ok(());
+        }
+
         if sub_command.name == "add" {
             let options = match &sub_command.value {
                 CommandOptionValue::SubCommand(sub_cmd) => sub_cmd,
@@ -393,10 +545,9 @@ impl CustosCommand for AntiAbuseCommand {
             };
 
             let action_type = match &options[0].value {
-                CommandOptionValue::String(s) => s,
+                CommandOptionValue::String(s) => parse_action_type(s)?,
                 _ => unreachable!(),
-            }
-            .parse::<u16>()?;
+            };
             let max_sanctions = match &options[1].value {
                 CommandOptionValue::Integer(s) => s,
                 _ => unreachable!(),
@@ -407,6 +558,67 @@ impl CustosCommand for AntiAbuseCommand {
             };
 
             let interactions = context.get_interactions();
+            if !is_selectable_action(action_type) {
+                util::send(
+                    &interactions,
+                    &inter,
+                    InteractionResponseType::ChannelMessageWithSource,
+                    util::embed_response(
+                        "Auto moderation actions are performed by Discord and can't be watched.",
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+
+            let punishment = options
+                .iter()
+                .find(|opt| opt.name == "punishment")
+                .and_then(|opt| match &opt.value {
+                    CommandOptionValue::String(s) => Some(s.as_str()),
+                    _ => None,
+                });
+
+            let ignore_reversed = options
+                .iter()
+                .find(|opt| opt.name == "ignore_reversed")
+                .map_or(false, |opt| {
+                    matches!(opt.value, CommandOptionValue::Boolean(true))
+                });
+
+            let timeout_duration = options
+                .iter()
+                .find(|opt| opt.name == "timeout_duration")
+                .and_then(|opt| match opt.value {
+                    CommandOptionValue::Integer(duration) => u32::try_from(duration).ok(),
+                    _ => None,
+                });
+
+            if let Some(punishment) = punishment {
+                save_watched_action(
+                    context,
+                    guild_id,
+                    AntiAbuseEventConfig {
+                        action_type: AuditLogEventType::from(action_type),
+                        max_sanctions: *max_sanctions as i32,
+                        sanction_cooldown: *sanction_cooldown as i32,
+                        punishment: punishment_from_values([punishment]),
+                        ignore_reversed,
+                        timeout_duration,
+                    },
+                )
+                .await?;
+
+                util::send(
+                    &interactions,
+                    &inter,
+                    InteractionResponseType::ChannelMessageWithSource,
+                    util::embed_response("Added a new action to watch for!"),
+                )
+                .await?;
+                return Ok(());
+            }
+
             util::send(
                 &interactions,
                 &inter,
@@ -415,10 +627,17 @@ impl CustosCommand for AntiAbuseCommand {
                     .content("Please select a punishment for that action")
                     .components([Component::ActionRow(ActionRow {
                         components: vec![Component::SelectMenu(SelectMenu {
-                            custom_id: format!(
-                                // Anti-abuse - add - action_type - max_sanctions - sanction_cooldown
-                                "ab-a-{}-{}-{}",
-                                action_type, max_sanctions, sanction_cooldown
+                            custom_id: fit_custom_id(
+                                format!(
+                                    // Anti-abuse - add - action_type - max_sanctions - sanction_cooldown - ignore_reversed - timeout_duration (0 if unset)
+                                    "ab-a-{}-{}-{}-{}-{}",
+                                    action_type,
+                                    max_sanctions,
+                                    sanction_cooldown,
+                                    ignore_reversed as u8,
+                                    timeout_duration.unwrap_or(0)
+                                ),
+                                inter.id,
                             ),
                             disabled: false,
                             max_values: Some(2),
@@ -437,12 +656,11 @@ impl CustosCommand for AntiAbuseCommand {
             };
 
             let action_type = match &options[0].value {
-                CommandOptionValue::String(s) => s,
+                CommandOptionValue::String(s) => parse_action_type(s)?,
                 _ => unreachable!(),
-            }
-            .parse::<u16>()?;
+            };
 
-            let label = action_label_code_to_str(action_type).unwrap();
+            let label = action_label(AuditLogEventType::from(action_type)).unwrap_or("unknown");
             let interactions = context.get_interactions();
 
             let guild_configs = context
@@ -467,26 +685,16 @@ impl CustosCommand for AntiAbuseCommand {
                 &interactions,
                 &inter,
                 InteractionResponseType::ChannelMessageWithSource,
-                InteractionResponseDataBuilder::new()
-                    .content(format!(
-                        "I will no longer watch/monitor the `{}` action.",
-                        label
-                    ))
-                    .build(),
+                util::embed_response(format!(
+                    "I will no longer watch/monitor the `{}` action.",
+                    label
+                )),
             )
             .await?
         } else if sub_command.name == "list" {
-            let guild_config = GuildConfig::get_guild(
-                context,
-                guild_id,
-                Some(
-                    FindOneOptions::builder()
-                        .projection(doc! { "anti_abuse": 1 })
-                        .build(),
-                ),
-            )
-            .await?
-            .unwrap();
+            let guild_config = GuildConfig::get_field(context, guild_id, "anti_abuse")
+                .await?
+                .unwrap();
 
             async fn err_no_configured_actions(
                 interactions: InteractionClient<'_>,
@@ -496,10 +704,8 @@ impl CustosCommand for AntiAbuseCommand {
                     &interactions,
                     &inter,
                     InteractionResponseType::ChannelMessageWithSource,
-                    InteractionResponseDataBuilder::new()
-                        // TODO: add mention of the command which is used to add those actions to watchlist
-                        .content("You do not have any actions watched/monitored.")
-                        .build(),
+                    // TODO: add mention of the command which is used to add those actions to watchlist
+                    util::embed_response("You do not have any actions watched/monitored."),
                 )
                 .await?;
 
@@ -523,15 +729,28 @@ impl CustosCommand for AntiAbuseCommand {
                 .watched_actions
                 .into_iter()
                 .map(|action| {
-                    let action_code = std::convert::Into::<u16>::into(action.action_type);
-                    let label = action_label_code_to_str(action_code)
-                        .unwrap_or(format!("Unknown Label code: {}", action_code));
+                    let label = describe_action(action.action_type);
+
+                    let timeout = if action.punishment.is_timeout() {
+                        format!(
+                            " - timeout: **{:.2?}**",
+                            Duration::from_secs(action.timeout_duration().into())
+                        )
+                    } else {
+                        String::new()
+                    };
 
                     format!(
-                        "`{}` - max sanctions: **{}** - cooldown: **{:.2?}**",
+                        "`{}` - max sanctions: **{}** - cooldown: **{:.2?}**{}{}",
                         label,
                         action.max_sanctions,
-                        Duration::from_secs(action.sanction_cooldown as u64)
+                        Duration::from_secs(action.sanction_cooldown as u64),
+                        timeout,
+                        if action.ignore_reversed {
+                            " - ignores reversed actions"
+                        } else {
+                            ""
+                        }
                     )
                 })
                 .collect::<Vec<String>>()
@@ -541,7 +760,7 @@ impl CustosCommand for AntiAbuseCommand {
                 &interactions,
                 &inter,
                 InteractionResponseType::ChannelMessageWithSource,
-                InteractionResponseDataBuilder::new().content(list).build(),
+                util::embed_response(list),
             )
             .await?;
         }
@@ -571,30 +790,25 @@ impl CustosCommand for AntiAbuseCommand {
             _ => unreachable!(),
         };
 
-        let actual_value = match &sub_command.value {
+        // every option filled in so far is sent along, the focused one can be anywhere
+        let focused_value = match &sub_command.value {
             CommandOptionValue::SubCommand(options) => {
-                let option = &options[0];
-                match &option.value {
-                    CommandOptionValue::Focused(value, kind) => (value, kind),
-                    _ => unreachable!(),
-                }
+                options.iter().find_map(|option| match &option.value {
+                    CommandOptionValue::Focused(value, _) => Some(value),
+                    _ => None,
+                })
             }
-            _ => unreachable!(),
+            _ => None,
         };
 
-        let query = actual_value.0.to_lowercase();
+        let query = match focused_value {
+            Some(value) => value.to_lowercase(),
+            None => return Err(Error::msg("No focused option in the autocomplete data")),
+        };
         let matching_labels = if sub_command.name == "remove" {
-            let guild_config = GuildConfig::get_guild(
-                context,
-                guild_id,
-                Some(
-                    FindOneOptions::builder()
-                        .projection(doc! { "anti_abuse": 1 })
-                        .build(),
-                ),
-            )
-            .await?
-            .unwrap();
+            let guild_config = GuildConfig::get_field(context, guild_id, "anti_abuse")
+                .await?
+                .unwrap();
 
             if let Some(anti_abuse) = guild_config.anti_abuse {
                 if !anti_abuse.watched_actions.is_empty() {
@@ -622,14 +836,15 @@ impl CustosCommand for AntiAbuseCommand {
                     .collect::<Vec<&(String, u16)>>()
             }
         } else if query.is_empty() {
-            ACTION_LABELS
+            SELECTABLE_ACTION_LABELS
                 .iter()
                 .take(25)
                 .collect::<Vec<&(String, u16)>>()
         } else {
-            ACTION_LABELS
+            SELECTABLE_ACTION_LABELS
                 .iter()
                 .filter(|(label, _)| label.contains(&query))
+                .take(25)
                 .collect::<Vec<&(String, u16)>>()
         };
 
@@ -658,3 +873,62 @@ impl CustosCommand for AntiAbuseCommand {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids_at(now: Instant, count: usize) -> OversizedCustomIds {
+        let mut ids = OversizedCustomIds::default();
+        for i in 0..count {
+            ids.insert(format!("ab-s-{}", i), format!("ab-a-{}", i), now);
+        }
+        ids
+    }
+
+    #[test]
+    fn short_ids_resolve_once() {
+        let now = Instant::now();
+        let mut ids = ids_at(now, 1);
+
+        assert_eq!(ids.remove("ab-s-0", now), Some("ab-a-0".to_owned()));
+        assert_eq!(ids.remove("ab-s-0", now), None);
+    }
+
+    #[test]
+    fn short_ids_expire() {
+        let now = Instant::now();
+        let mut ids = ids_at(now, 2);
+
+        let almost = now + OversizedCustomIds::TTL - Duration::from_secs(1);
+        assert_eq!(ids.remove("ab-s-0", almost), Some("ab-a-0".to_owned()));
+        assert_eq!(ids.remove("ab-s-1", now + OversizedCustomIds::TTL), None);
+    }
+
+    #[test]
+    fn expired_ids_are_dropped_on_insert() {
+        let now = Instant::now();
+        let mut ids = ids_at(now, 10);
+
+        let later = now + OversizedCustomIds::TTL;
+        ids.insert("ab-s-new".to_owned(), "ab-a-new".to_owned(), later);
+        assert_eq!(ids.ids.len(), 1);
+    }
+
+    #[test]
+    fn oldest_id_makes_room_at_the_cap() {
+        let now = Instant::now();
+        let mut ids = ids_at(now, OversizedCustomIds::MAX_IDS - 1);
+        ids.insert(
+            "ab-s-oldest".to_owned(),
+            "ab-a-oldest".to_owned(),
+            now - Duration::from_secs(1),
+        );
+        assert_eq!(ids.ids.len(), OversizedCustomIds::MAX_IDS);
+
+        ids.insert("ab-s-new".to_owned(), "ab-a-new".to_owned(), now);
+        assert_eq!(ids.ids.len(), OversizedCustomIds::MAX_IDS);
+        assert_eq!(ids.remove("ab-s-oldest", now), None);
+        assert_eq!(ids.remove("ab-s-new", now), Some("ab-a-new".to_owned()));
+    }
+}
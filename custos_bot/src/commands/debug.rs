@@ -1,7 +1,11 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use async_trait::async_trait;
+use mongodb::bson::doc;
 use twilight_gateway::stream::ShardRef;
 use twilight_model::{
     application::{command::CommandType, interaction::application_command::CommandData},
@@ -15,6 +19,29 @@ use crate::{ctx::Context, util};
 
 pub struct PingCommand {}
 
+const DB_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Pings MongoDB, returns the round trip time or `None` if it failed or timed out.
+async fn ping_db(context: &Arc<Context>) -> Option<Duration> {
+    let start = Instant::now();
+    let ping = context
+        .get_mongodb()
+        .database("admin")
+        .run_command(doc! { "ping": 1 }, None);
+
+    match tokio::time::timeout(DB_PING_TIMEOUT, ping).await {
+        Ok(Ok(_)) => Some(start.elapsed()),
+        _ => None,
+    }
+}
+
+fn format_db_status(ping: Option<Duration>) -> String {
+    match ping {
+        Some(dur) => format!("ok ({}ms)", dur.as_millis()),
+        None => "unreachable".to_owned(),
+    }
+}
+
 #[async_trait]
 impl CustosCommand for PingCommand {
     fn get_command_name() -> String {
@@ -36,14 +63,18 @@ impl CustosCommand for PingCommand {
         inter: Box<InteractionCreate>,
         _: Box<CommandData>,
     ) -> Result<()> {
+        let db_status = format_db_status(ping_db(context).await);
         let message = format!(
-            "`Shard`: #{}\n`Avg latency`: {}\n`Application ID`: {}\n`Version`: {}",
+            "`Shard`: #{} of {}\n`Cached guilds`: {}\n`Avg latency`: {}\n`DB`: {}\n`Application ID`: {}\n`Version`: {}",
             shard.id().number(),
+            shard.id().total(),
+            context.get_cache().stats().guilds(),
             if let Some(dur) = shard.latency().average() {
                 format!("{:.2?}", dur)
             } else {
                 "Not available.".to_owned()
             },
+            db_status,
             context.get_app().id,
             env!("CARGO_PKG_VERSION")
         );
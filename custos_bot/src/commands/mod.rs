@@ -1,33 +1,321 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
 use async_trait::async_trait;
+use futures_util::future::BoxFuture;
 use twilight_gateway::stream::ShardRef;
 use twilight_model::{
     application::{
         command::Command,
         interaction::{
             application_command::CommandData, message_component::MessageComponentInteractionData,
-            modal::ModalInteractionData,
+            modal::ModalInteractionData, InteractionType,
         },
     },
+    channel::message::MessageFlags,
     gateway::payload::incoming::InteractionCreate,
+    guild::Permissions,
+    http::interaction::InteractionResponseType,
+};
+use twilight_util::{
+    builder::InteractionResponseDataBuilder, permission_calculator::PermissionCalculator,
 };
 
-use crate::ctx::Context;
+use crate::{ctx::Context, plugins::anti_abuse::resolve_roles, util};
 
 pub mod anti_abuse;
 pub mod debug;
+pub mod purge;
+pub mod tag;
+pub mod warn;
 pub mod welcomer;
 
+/// Calls a command with the interaction, see [`call_command`].
+pub type CommandHandler = for<'a> fn(
+    ShardRef<'a>,
+    &'a Arc<Context>,
+    Box<InteractionCreate>,
+    Box<CommandData>,
+) -> BoxFuture<'a, Result<()>>;
+
+/// Handles a message component whose custom id starts with the command's component tag.
+pub type ComponentHandler = for<'a> fn(
+    ShardRef<'a>,
+    &'a Arc<Context>,
+    Box<InteractionCreate>,
+    MessageComponentInteractionData,
+) -> BoxFuture<'a, Result<()>>;
+
+/// Handles a modal whose custom id starts with the command's component tag.
+pub type ModalHandler = for<'a> fn(
+    ShardRef<'a>,
+    &'a Arc<Context>,
+    Box<InteractionCreate>,
+    ModalInteractionData,
+) -> BoxFuture<'a, Result<()>>;
+
+struct ComponentHandlers {
+    component: ComponentHandler,
+    modal: ModalHandler,
+}
+
+fn dispatch_command<'a, C: CustosCommand>(
+    shard: ShardRef<'a>,
+    context: &'a Arc<Context>,
+    inter: Box<InteractionCreate>,
+    command_data: Box<CommandData>,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(call_command::<C>(shard, context, inter, command_data))
+}
+
+fn dispatch_component<'a, C: CustosCommand + 'static>(
+    shard: ShardRef<'a>,
+    context: &'a Arc<Context>,
+    inter: Box<InteractionCreate>,
+    component_data: MessageComponentInteractionData,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        if !require_permissions(context, &inter, C::required_permissions()).await? {
+            return Ok(());
+        }
+
+        C::on_component_event(shard, context, inter, component_data).await
+    })
+}
+
+fn dispatch_modal<'a, C: CustosCommand + 'static>(
+    shard: ShardRef<'a>,
+    context: &'a Arc<Context>,
+    inter: Box<InteractionCreate>,
+    modal_data: ModalInteractionData,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        if !require_permissions(context, &inter, C::required_permissions()).await? {
+            return Ok(());
+        }
+
+        C::on_modal_submit(shard, context, inter, modal_data).await
+    })
+}
+
+/// Finds which of the registered component tags owns the custom id. Custom ids start with
+/// the tag followed by a `-`, e.g. `ab-a-...` belongs to the `ab` tag.
+fn resolve_component_handler<V>(
+    custom_id: &str,
+    tags: &HashMap<&'static str, V>,
+) -> Option<&'static str> {
+    let (tag, _) = custom_id.split_once('-')?;
+    tags.get_key_value(tag).map(|(tag, _)| *tag)
+}
+
+/// Every command of the bot by name, built once and kept on the [`Context`].
+#[derive(Default)]
+pub struct CommandRegistry {
+    handlers: HashMap<String, CommandHandler>,
+    components: HashMap<&'static str, ComponentHandlers>,
+    definitions: Vec<Command>,
+}
+
+impl CommandRegistry {
+    /// Builds the registry with every command of the bot, new commands are added here.
+    pub fn new() -> Self {
+        let mut registry = Self::default();
+        registry.register_command::<debug::PingCommand>();
+        registry.register_command::<welcomer::WelcomerCommand>();
+        registry.register_command::<anti_abuse::AntiAbuseCommand>();
+        registry.register_command::<tag::TagCommand>();
+        registry.register_command::<purge::PurgeCommand>();
+        registry.register_command::<warn::WarnCommand>();
+        registry
+    }
+
+    pub fn register_command<C: CustosCommand + 'static>(&mut self) {
+        self.handlers
+            .insert(C::get_command_name(), dispatch_command::<C>);
+        self.definitions.push(C::get_command_info());
+
+        if let Some(tag) = C::get_component_tag() {
+            self.components.insert(
+                tag,
+                ComponentHandlers {
+                    component: dispatch_component::<C>,
+                    modal: dispatch_modal::<C>,
+                },
+            );
+        }
+    }
+
+    pub fn get_handler(&self, name: &str) -> Option<CommandHandler> {
+        self.handlers.get(name).copied()
+    }
+
+    /// Finds the component tag of the command that owns the custom id, see
+    /// [`resolve_component_handler`].
+    pub fn resolve_component_handler(&self, custom_id: &str) -> Option<&'static str> {
+        resolve_component_handler(custom_id, &self.components)
+    }
+
+    pub fn get_component_handler(&self, custom_id: &str) -> Option<ComponentHandler> {
+        let tag = self.resolve_component_handler(custom_id)?;
+        Some(self.components[tag].component)
+    }
+
+    pub fn get_modal_handler(&self, custom_id: &str) -> Option<ModalHandler> {
+        let tag = self.resolve_component_handler(custom_id)?;
+        Some(self.components[tag].modal)
+    }
+
+    /// The definitions that get registered with Discord.
+    pub fn definitions(&self) -> &[Command] {
+        &self.definitions
+    }
+}
+
+/// Calculates the guild-level permissions of the member who created the interaction,
+/// returns `None` outside of guilds. When the guild or the user isn't known it falls back
+/// to the permissions Discord sent with the interaction, which include channel overwrites.
+pub async fn member_permissions(
+    context: &Arc<Context>,
+    inter: &InteractionCreate,
+) -> Result<Option<Permissions>> {
+    let (guild_id, member) = match (inter.guild_id, &inter.member) {
+        (Some(guild_id), Some(member)) => (guild_id, member),
+        _ => return Ok(None),
+    };
+
+    let user_id = match &member.user {
+        Some(user) => user.id,
+        None => return Ok(member.permissions),
+    };
+
+    let owner_id = match context.get_cache().guild(guild_id) {
+        Some(guild) => guild.owner_id(),
+        None => {
+            tracing::warn!("Got an interaction but no guild in the cache.");
+            return Ok(member.permissions);
+        }
+    };
+
+    if user_id == owner_id {
+        return Ok(Some(Permissions::all()));
+    }
+
+    // the @everyone role shares its id with the guild
+    let everyone_role_id = guild_id.cast();
+    let mut role_ids = member.roles.clone();
+    role_ids.push(everyone_role_id);
+
+    let roles = resolve_roles(context, guild_id, &role_ids).await?;
+    let everyone_role = roles
+        .iter()
+        .find(|role| role.id == everyone_role_id)
+        .map_or(Permissions::empty(), |role| role.permissions);
+    let member_roles = roles
+        .iter()
+        .filter(|role| role.id != everyone_role_id)
+        .map(|role| (role.id, role.permissions))
+        .collect::<Vec<_>>();
+
+    let calculator = PermissionCalculator::new(guild_id, user_id, everyone_role, &member_roles);
+    Ok(Some(calculator.root()))
+}
+
+/// Names the permissions like `Manage Guild`, `Ban Members` for messages.
+pub fn permission_names(permissions: Permissions) -> String {
+    (0..u64::BITS)
+        .map(|bit| Permissions::from_bits_truncate(1 << bit))
+        .filter(|flag| !flag.is_empty() && permissions.contains(*flag))
+        .map(|flag| {
+            // the debug output of a single flag is its constant name, e.g. `MANAGE_GUILD`
+            let name = format!("{:?}", flag)
+                .split('_')
+                .map(|word| word[..1].to_owned() + &word[1..].to_lowercase())
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("`{}`", name)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The permissions out of `required` the member doesn't have, `None` if they have them all.
+/// A member without resolved permissions, e.g. outside of guilds, has none of them.
+fn missing_permissions(required: Permissions, granted: Option<Permissions>) -> Option<Permissions> {
+    let missing = required - granted.unwrap_or(Permissions::empty());
+    (!missing.is_empty()).then_some(missing)
+}
+
+/// Whether the member has all of the `required` permissions, responds with an ephemeral
+/// message naming the missing ones if not. Commands, their components and their modals all
+/// go through it with the command's required permissions, subcommands that need more check
+/// their own.
+pub async fn require_permissions(
+    context: &Arc<Context>,
+    inter: &InteractionCreate,
+    required: Permissions,
+) -> Result<bool> {
+    if required.is_empty() {
+        return Ok(true);
+    }
+
+    let granted = member_permissions(context, inter).await?;
+    let missing = match missing_permissions(required, granted) {
+        Some(missing) => missing,
+        None => return Ok(true),
+    };
+
+    util::send(
+        &context.get_interactions(),
+        inter,
+        InteractionResponseType::ChannelMessageWithSource,
+        InteractionResponseDataBuilder::new()
+            .content(format!(
+                "You are missing the {} permission(s) to use this command.",
+                permission_names(missing)
+            ))
+            .flags(MessageFlags::EPHEMERAL)
+            .build(),
+    )
+    .await?;
+    Ok(false)
+}
+
+/// Routes autocomplete interactions to the command's autocomplete handler. Otherwise calls
+/// the command if the member has all of its required permissions, see `require_permissions`.
+pub async fn call_command<C: CustosCommand>(
+    shard: ShardRef<'_>,
+    context: &Arc<Context>,
+    inter: Box<InteractionCreate>,
+    command_data: Box<CommandData>,
+) -> Result<()> {
+    // suggestions can't be answered with a message and reveal nothing, no permission check
+    if inter.kind == InteractionType::ApplicationCommandAutocomplete {
+        return C::on_autocomplete_call(shard, context, inter, command_data).await;
+    }
+
+    if !require_permissions(context, &inter, C::required_permissions()).await? {
+        return Ok(());
+    }
+
+    C::on_command_call(shard, context, inter, command_data).await
+}
+
 #[async_trait]
 pub trait CustosCommand {
     fn get_command_name() -> String;
 
     fn get_command_info() -> Command;
 
-    fn get_component_tag() -> &'static str {
-        ""
+    /// Permissions the member needs to call the command or use its components and modals,
+    /// checked before any of the handlers are called.
+    fn required_permissions() -> Permissions {
+        Permissions::empty()
+    }
+
+    /// Prefix of the custom ids of the command's components and modals, see
+    /// [`CommandRegistry::resolve_component_handler`]. `None` if the command has none.
+    fn get_component_tag() -> Option<&'static str> {
+        None
     }
 
     async fn on_command_call(
@@ -48,16 +336,15 @@ pub trait CustosCommand {
         Ok(())
     }
 
-    async fn on_context_menu_call(
-        _message_component: MessageComponentInteractionData,
+    async fn on_modal_submit(
+        _shard: ShardRef<'_>,
+        _context: &Arc<Context>,
+        _inter: Box<InteractionCreate>,
+        _modal_data: ModalInteractionData,
     ) -> Result<()> {
         Ok(())
     }
 
-    async fn on_modal_submit(_modal_data: ModalInteractionData) -> Result<()> {
-        Ok(())
-    }
-
     async fn on_component_event(
         _shard: ShardRef<'_>,
         _context: &Arc<Context>,
@@ -67,3 +354,84 @@ pub trait CustosCommand {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions_are_named_readably() {
+        assert_eq!(
+            permission_names(Permissions::MANAGE_GUILD),
+            "`Manage Guild`"
+        );
+        assert_eq!(
+            permission_names(Permissions::BAN_MEMBERS | Permissions::MODERATE_MEMBERS),
+            "`Ban Members`, `Moderate Members`"
+        );
+        assert_eq!(permission_names(Permissions::empty()), "");
+    }
+
+    fn tags(tags: &[&'static str]) -> HashMap<&'static str, ()> {
+        tags.iter().map(|tag| (*tag, ())).collect()
+    }
+
+    #[test]
+    fn custom_ids_resolve_to_their_tag() {
+        let tags = tags(&["ab", "abc", "t"]);
+
+        assert_eq!(resolve_component_handler("ab-a-1", &tags), Some("ab"));
+        assert_eq!(resolve_component_handler("t-", &tags), Some("t"));
+
+        // the whole tag has to match, not just its start
+        assert_eq!(resolve_component_handler("abc-x", &tags), Some("abc"));
+        assert_eq!(resolve_component_handler("a-x", &tags), None);
+        assert_eq!(resolve_component_handler("abcd-x", &tags), None);
+    }
+
+    #[test]
+    fn unknown_or_untagged_custom_ids_resolve_to_nothing() {
+        let tags = tags(&["ab"]);
+
+        assert_eq!(resolve_component_handler("zz-a", &tags), None);
+        assert_eq!(resolve_component_handler("ab", &tags), None);
+        assert_eq!(resolve_component_handler("", &tags), None);
+        assert_eq!(
+            resolve_component_handler("ab-a", &HashMap::<_, ()>::new()),
+            None
+        );
+    }
+
+    #[test]
+    fn members_with_the_permissions_are_allowed() {
+        let required = Permissions::BAN_MEMBERS | Permissions::KICK_MEMBERS;
+
+        assert_eq!(missing_permissions(required, Some(required)), None);
+        assert_eq!(
+            missing_permissions(required, Some(Permissions::all())),
+            None
+        );
+
+        // nothing required, not even resolved permissions
+        assert_eq!(missing_permissions(Permissions::empty(), None), None);
+        assert_eq!(
+            missing_permissions(Permissions::empty(), Some(Permissions::empty())),
+            None
+        );
+    }
+
+    #[test]
+    fn members_without_the_permissions_are_denied() {
+        let required = Permissions::BAN_MEMBERS | Permissions::KICK_MEMBERS;
+
+        assert_eq!(
+            missing_permissions(required, Some(Permissions::KICK_MEMBERS)),
+            Some(Permissions::BAN_MEMBERS)
+        );
+        assert_eq!(
+            missing_permissions(required, Some(Permissions::MANAGE_GUILD)),
+            Some(required)
+        );
+        assert_eq!(missing_permissions(required, None), Some(required));
+    }
+}
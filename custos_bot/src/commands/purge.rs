@@ -0,0 +1,259 @@
+use std::sync::Arc;
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use twilight_gateway::stream::ShardRef;
+use twilight_http::{
+    error::{Error as HttpError, ErrorType},
+    Client as HttpClient,
+};
+use twilight_model::{
+    application::{
+        command::CommandType,
+        interaction::application_command::{CommandData, CommandOptionValue},
+    },
+    channel::message::MessageFlags,
+    gateway::payload::incoming::InteractionCreate,
+    guild::Permissions,
+    http::interaction::InteractionResponseType,
+    id::{
+        marker::{ChannelMarker, MessageMarker, UserMarker},
+        Id,
+    },
+};
+use twilight_util::builder::{
+    command::{CommandBuilder, IntegerBuilder, UserBuilder},
+    InteractionResponseDataBuilder,
+};
+
+use super::CustosCommand;
+use crate::{ctx::Context, util};
+
+/// Discord only bulk deletes messages younger than 14 days, a minute is left as a margin
+/// for the time between fetching and deleting.
+const BULK_DELETE_MAX_AGE_MS: i64 = 14 * 24 * 60 * 60 * 1000 - 60 * 1000;
+
+/// The first second of 2015, message ids hold their creation time relative to it.
+const DISCORD_EPOCH_MS: i64 = 1_420_070_400_000;
+
+/// How many messages are fetched at most, the user filter picks from these.
+const MAX_FETCHED_MESSAGES: u16 = 100;
+
+fn is_bulk_deletable(message_id: Id<MessageMarker>, now_ms: i64) -> bool {
+    let created_at_ms = (message_id.get() >> 22) as i64 + DISCORD_EPOCH_MS;
+    now_ms - created_at_ms < BULK_DELETE_MAX_AGE_MS
+}
+
+/// Shows at most this many distinct errors in the report, the rest are only logged.
+const MAX_REPORTED_ERRORS: usize = 3;
+
+/// What a purge did, sent back to the moderator once it's done.
+#[derive(Default)]
+struct PurgeReport {
+    deleted: usize,
+    failed: usize,
+    errors: Vec<String>,
+}
+
+impl PurgeReport {
+    /// Counts the messages of a delete request. Messages that are gone already, e.g.
+    /// deleted by someone else in the meantime, are neither deleted nor failed.
+    fn record(&mut self, result: Result<(), HttpError>, count: usize) {
+        match result {
+            Ok(()) => self.deleted += count,
+            Err(err) if is_not_found(&err) => {}
+            Err(err) => {
+                tracing::warn!(?err, count, "Failed to delete messages");
+                self.failed += count;
+
+                let err = err.to_string();
+                if !self.errors.contains(&err) {
+                    self.errors.push(err);
+                }
+            }
+        }
+    }
+
+    fn message(&self) -> String {
+        let mut message = format!("Deleted {} message(s).", self.deleted);
+        if self.failed > 0 {
+            message += &format!("\n{} message(s) couldn't be deleted:", self.failed);
+            for err in self.errors.iter().take(MAX_REPORTED_ERRORS) {
+                message += &format!("\n- {}", err);
+            }
+        }
+        message
+    }
+}
+
+fn is_not_found(err: &HttpError) -> bool {
+    std::matches!(err.kind(), ErrorType::Response { status, .. } if status.get() == 404)
+}
+
+/// Deletes the last `amount` messages of the channel, only the user's if there's one.
+/// Deleting goes on past failed messages, only fetching them fails the purge.
+async fn purge(
+    http: &HttpClient,
+    channel_id: Id<ChannelMarker>,
+    user_id: Option<Id<UserMarker>>,
+    amount: usize,
+) -> Result<PurgeReport> {
+    let message_ids = http
+        .channel_messages(channel_id)
+        .limit(MAX_FETCHED_MESSAGES)?
+        .await?
+        .models()
+        .await?
+        .into_iter()
+        .filter(|message| user_id.map_or(true, |user_id| message.author.id == user_id))
+        .take(amount)
+        .map(|message| message.id)
+        .collect::<Vec<_>>();
+
+    let now_ms = Utc::now().timestamp_millis();
+    let (recent, old): (Vec<_>, Vec<_>) = message_ids
+        .iter()
+        .copied()
+        .partition(|message_id| is_bulk_deletable(*message_id, now_ms));
+
+    let mut report = PurgeReport::default();
+
+    // a bulk delete needs at least two messages
+    if recent.len() >= 2 {
+        let result = http.delete_messages(channel_id, &recent).await;
+        report.record(result.map(|_| ()), recent.len());
+    } else if let Some(message_id) = recent.first() {
+        let result = http.delete_message(channel_id, *message_id).await;
+        report.record(result.map(|_| ()), 1);
+    }
+
+    for message_id in &old {
+        let result = http.delete_message(channel_id, *message_id).await;
+        report.record(result.map(|_| ()), 1);
+    }
+
+    Ok(report)
+}
+
+pub struct PurgeCommand {}
+
+#[async_trait]
+impl CustosCommand for PurgeCommand {
+    fn get_command_name() -> String {
+        "purge".to_owned()
+    }
+
+    fn get_command_info() -> twilight_model::application::command::Command {
+        CommandBuilder::new(
+            Self::get_command_name(),
+            "Delete the last messages in this channel.",
+            CommandType::ChatInput,
+        )
+        .option(
+            IntegerBuilder::new("amount", "How many messages to delete.")
+                .min_value(2)
+                .max_value(MAX_FETCHED_MESSAGES.into())
+                .required(true),
+        )
+        .option(UserBuilder::new(
+            "user",
+            "Only delete the messages of this user.",
+        ))
+        .default_member_permissions(Permissions::MANAGE_MESSAGES)
+        .build()
+    }
+
+    fn required_permissions() -> Permissions {
+        Permissions::MANAGE_MESSAGES
+    }
+
+    async fn on_command_call(
+        _: ShardRef<'_>,
+        context: &Arc<Context>,
+        inter: Box<InteractionCreate>,
+        data: Box<CommandData>,
+    ) -> Result<()> {
+        let channel_id = match &inter.channel {
+            Some(channel) => channel.id,
+            None => return Err(Error::msg("No channel in the interaction data")),
+        };
+
+        let amount = data
+            .options
+            .iter()
+            .find_map(|opt| match opt.value {
+                CommandOptionValue::Integer(amount) => usize::try_from(amount).ok(),
+                _ => None,
+            })
+            .ok_or_else(|| Error::msg("No 'amount' option found."))?;
+        let user_id = data.options.iter().find_map(|opt| match opt.value {
+            CommandOptionValue::User(user) => Some(user),
+            _ => None,
+        });
+
+        // deleting up to a hundred messages one by one takes longer than Discord waits
+        let interactions = context.get_interactions();
+        util::send(
+            &interactions,
+            &inter,
+            InteractionResponseType::DeferredChannelMessageWithSource,
+            InteractionResponseDataBuilder::new()
+                .flags(MessageFlags::EPHEMERAL)
+                .build(),
+        )
+        .await?;
+
+        let report = purge(context.get_http(), channel_id, user_id, amount).await;
+        let content = match &report {
+            Ok(report) => report.message(),
+            Err(err) => format!("Couldn't fetch the messages to delete: {}", err),
+        };
+
+        // the deferred response keeps showing that the bot is thinking until the followup
+        util::followup(
+            &interactions,
+            &inter,
+            InteractionResponseDataBuilder::new()
+                .content(content)
+                .build(),
+        )
+        .await?;
+
+        report?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_counts_deleted_messages() {
+        let mut report = PurgeReport::default();
+        report.record(Ok(()), 5);
+        report.record(Ok(()), 1);
+
+        assert_eq!(report.message(), "Deleted 6 message(s).");
+    }
+
+    #[test]
+    fn report_lists_distinct_errors() {
+        let mut report = PurgeReport {
+            deleted: 2,
+            failed: 3,
+            errors: vec![
+                "Missing Access".to_owned(),
+                "Missing Permissions".to_owned(),
+            ],
+        };
+        assert_eq!(
+            report.message(),
+            "Deleted 2 message(s).\n3 message(s) couldn't be deleted:\n- Missing Access\n- Missing Permissions"
+        );
+
+        report.errors.extend((0..5).map(|i| format!("error {}", i)));
+        assert_eq!(report.message().lines().count(), 2 + MAX_REPORTED_ERRORS);
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use twilight_gateway::stream::ShardRef;
+use twilight_model::{
+    application::{
+        command::CommandType,
+        interaction::application_command::{CommandData, CommandDataOption, CommandOptionValue},
+    },
+    gateway::payload::incoming::InteractionCreate,
+    guild::Permissions,
+    http::interaction::InteractionResponseType,
+};
+use twilight_util::builder::command::{CommandBuilder, StringBuilder, SubCommandBuilder};
+
+use super::{require_permissions, CustosCommand};
+use crate::{
+    ctx::Context,
+    schemas::Tag,
+    scripting,
+    util::{embed_response, send},
+};
+
+pub struct TagCommand {}
+
+fn get_string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|opt| opt.name == name)
+        .and_then(|opt| match &opt.value {
+            CommandOptionValue::String(s) => Some(s.as_str()),
+            _ => None,
+        })
+}
+
+#[async_trait]
+impl CustosCommand for TagCommand {
+    fn get_command_name() -> String {
+        "tag".to_owned()
+    }
+
+    fn get_command_info() -> twilight_model::application::command::Command {
+        CommandBuilder::new(
+            Self::get_command_name(),
+            "Store and run custom scripts.",
+            CommandType::ChatInput,
+        )
+        .option(
+            SubCommandBuilder::new("create", "Create or overwrite a tag.")
+                .option(
+                    StringBuilder::new("name", "The name of the tag.")
+                        .min_length(1)
+                        .max_length(32)
+                        .required(true),
+                )
+                .option(
+                    StringBuilder::new("code", "The script of the tag.")
+                        .min_length(1)
+                        .max_length(2000)
+                        .required(true),
+                ),
+        )
+        .option(
+            SubCommandBuilder::new("run", "Run a tag.")
+                .option(
+                    StringBuilder::new("name", "The name of the tag.")
+                        .min_length(1)
+                        .max_length(32)
+                        .required(true),
+                )
+                .option(StringBuilder::new(
+                    "args",
+                    "Space separated arguments passed to the tag.",
+                )),
+        )
+        .option(
+            SubCommandBuilder::new("delete", "Delete a tag.").option(
+                StringBuilder::new("name", "The name of the tag.")
+                    .min_length(1)
+                    .max_length(32)
+                    .required(true),
+            ),
+        )
+        .build()
+    }
+
+    async fn on_command_call(
+        _: ShardRef<'_>,
+        context: &Arc<Context>,
+        inter: Box<InteractionCreate>,
+        data: Box<CommandData>,
+    ) -> Result<()> {
+        let guild_id = match inter.guild_id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let sub_command = &data.options[0];
+        let options = match &sub_command.value {
+            CommandOptionValue::SubCommand(scommand) => scommand,
+            _ => return Ok(()),
+        };
+
+        let name = match get_string_option(options, "name") {
+            Some(name) => name.to_lowercase(),
+            None => return Err(Error::msg("No 'name' option found.")),
+        };
+
+        let interactions = context.get_interactions();
+        // anyone can run and list tags, only managers change them
+        let manages = sub_command.name == "create" || sub_command.name == "delete";
+        if manages && !require_permissions(context, &inter, Permissions::MANAGE_GUILD).await? {
+            return Ok(());
+        }
+
+        if sub_command.name == "create" {
+            let source = match get_string_option(options, "code") {
+                Some(code) => code.to_owned(),
+                None => return Err(Error::msg("No 'code' option found.")),
+            };
+
+            let author_id = match inter.author_id() {
+                Some(id) => id,
+                None => return Err(Error::msg("No author in the interaction data")),
+            };
+
+            let response = match scripting::check_tag(&source) {
+                Ok(()) => {
+                    Tag {
+                        guild_id,
+                        name: name.clone(),
+                        source,
+                        author_id,
+                    }
+                    .upsert(context)
+                    .await?;
+                    format!("Tag `{}` has been saved.", name)
+                }
+                Err(e) => format!("The tag failed to compile:\n```{}```", e),
+            };
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response(response),
+            )
+            .await?;
+        } else if sub_command.name == "run" {
+            let tag = Tag::get(context, guild_id, &name).await?;
+            let channel_id = inter.channel.as_ref().map(|channel| channel.id);
+
+            let (tag, channel_id) = match (tag, channel_id) {
+                (Some(tag), Some(channel_id)) => (tag, channel_id),
+                _ => {
+                    send(
+                        &interactions,
+                        &inter,
+                        InteractionResponseType::ChannelMessageWithSource,
+                        embed_response(format!("There is no tag named `{}`.", name)),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            let args = get_string_option(options, "args")
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<String>>();
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response(format!("Running tag `{}`.", name)),
+            )
+            .await?;
+
+            scripting::spawn_script(
+                Arc::clone(context.get_http_sync()),
+                channel_id,
+                args,
+                tag.source,
+                context.get_script_cancellation().clone(),
+            );
+        } else if sub_command.name == "delete" {
+            let response = if Tag::delete(context, guild_id, &name).await? {
+                format!("Tag `{}` has been deleted.", name)
+            } else {
+                format!("There is no tag named `{}`.", name)
+            };
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response(response),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
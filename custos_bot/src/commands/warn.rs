@@ -0,0 +1,309 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use mongodb::bson::{doc, to_bson};
+use twilight_gateway::stream::ShardRef;
+use twilight_model::{
+    application::{
+        command::CommandType,
+        interaction::application_command::{CommandData, CommandOptionValue},
+    },
+    gateway::payload::incoming::InteractionCreate,
+    guild::Permissions,
+    http::interaction::InteractionResponseType,
+};
+use twilight_util::builder::command::{
+    CommandBuilder, IntegerBuilder, StringBuilder, SubCommandBuilder, UserBuilder,
+};
+
+use super::CustosCommand;
+use crate::{
+    ctx::Context,
+    plugins::moderator,
+    schemas::{AntiAbuseEventConfig, GuildConfig, Warning, WarningsConfig},
+    util::{embed_response, send},
+};
+
+/// Reasons are cut to this many characters in the list so that `Warning::MAX_LISTED`
+/// warnings always fit into an embed, a reason itself may be up to 400.
+const LISTED_REASON_LENGTH: usize = 200;
+
+fn format_warnings(warnings: &[Warning]) -> String {
+    warnings
+        .iter()
+        .map(|warning| {
+            let mut reason = warning
+                .reason
+                .chars()
+                .take(LISTED_REASON_LENGTH)
+                .collect::<String>();
+            if reason.len() < warning.reason.len() {
+                reason.push('…');
+            }
+
+            format!(
+                "<t:{}:R> by <@{}>: {}",
+                warning.created_at.timestamp(),
+                warning.moderator_id,
+                reason
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub struct WarnCommand {}
+
+#[async_trait]
+impl CustosCommand for WarnCommand {
+    fn get_command_name() -> String {
+        "warn".to_owned()
+    }
+
+    fn get_command_info() -> twilight_model::application::command::Command {
+        CommandBuilder::new(
+            Self::get_command_name(),
+            "Warn members and keep track of their warnings.",
+            CommandType::ChatInput,
+        )
+        .option(
+            SubCommandBuilder::new("add", "Warn a member.")
+                .option(UserBuilder::new("user", "The member to warn.").required(true))
+                .option(
+                    StringBuilder::new("reason", "Why the member is warned.")
+                        .min_length(1)
+                        .max_length(400)
+                        .required(true),
+                ),
+        )
+        .option(
+            SubCommandBuilder::new("list", "List the warnings of a member.")
+                .option(UserBuilder::new("user", "The member.").required(true)),
+        )
+        .option(
+            SubCommandBuilder::new("clear", "Remove all the warnings of a member.")
+                .option(UserBuilder::new("user", "The member.").required(true)),
+        )
+        .option(
+            SubCommandBuilder::new(
+                "set-threshold",
+                "Time out members every time they reach a multiple of this many warnings.",
+            )
+            .option(
+                IntegerBuilder::new("amount", "How many warnings, 0 turns it off.")
+                    .min_value(0)
+                    .max_value(100)
+                    .required(true),
+            )
+            .option(
+                IntegerBuilder::new(
+                    "duration",
+                    "How long the timeout lasts in seconds, an hour if left out.",
+                )
+                .min_value(60)
+                .max_value(AntiAbuseEventConfig::MAX_TIMEOUT_DURATION.into()),
+            ),
+        )
+        .default_member_permissions(Permissions::MODERATE_MEMBERS)
+        .build()
+    }
+
+    fn required_permissions() -> Permissions {
+        Permissions::MODERATE_MEMBERS
+    }
+
+    async fn on_command_call(
+        _: ShardRef<'_>,
+        context: &Arc<Context>,
+        inter: Box<InteractionCreate>,
+        data: Box<CommandData>,
+    ) -> Result<()> {
+        let guild_id = match inter.guild_id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let sub_command = &data.options[0];
+        let options = match &sub_command.value {
+            CommandOptionValue::SubCommand(scommand) => scommand,
+            _ => return Ok(()),
+        };
+
+        let user_id = options.iter().find_map(|opt| match opt.value {
+            CommandOptionValue::User(user) => Some(user),
+            _ => None,
+        });
+
+        let response = if sub_command.name == "set-threshold" {
+            let integer_option = |name: &str| {
+                options
+                    .iter()
+                    .find(|opt| opt.name == name)
+                    .and_then(|opt| match opt.value {
+                        CommandOptionValue::Integer(value) => u32::try_from(value).ok(),
+                        _ => None,
+                    })
+            };
+
+            let amount = integer_option("amount").unwrap_or(0);
+            if amount == 0 {
+                GuildConfig::update_data_by_id_upsert(
+                    context,
+                    doc! { "$unset": { "warnings": "" } },
+                    guild_id,
+                )
+                .await?;
+                "Warnings will no longer time out members.".to_owned()
+            } else {
+                let config = WarningsConfig {
+                    timeout_threshold: amount,
+                    timeout_duration: integer_option("duration")
+                        .unwrap_or(AntiAbuseEventConfig::DEFAULT_TIMEOUT_DURATION),
+                };
+                GuildConfig::update_data_by_id_upsert(
+                    context,
+                    doc! { "$set": { "warnings": to_bson(&config)? } },
+                    guild_id,
+                )
+                .await?;
+                format!(
+                    "Members will be timed out for {:?} every {} warning(s).",
+                    Duration::from_secs(config.timeout_duration.into()),
+                    amount
+                )
+            }
+        } else {
+            let user_id = user_id.ok_or_else(|| Error::msg("No 'user' option found."))?;
+
+            if sub_command.name == "add" {
+                let moderator_id = match inter.author_id() {
+                    Some(id) => id,
+                    None => return Err(Error::msg("No author in the interaction data")),
+                };
+                let reason = options
+                    .iter()
+                    .find_map(|opt| match &opt.value {
+                        CommandOptionValue::String(reason) => Some(reason.clone()),
+                        _ => None,
+                    })
+                    .ok_or_else(|| Error::msg("No 'reason' option found."))?;
+
+                Warning {
+                    guild_id,
+                    user_id,
+                    moderator_id,
+                    reason: reason.clone(),
+                    created_at: Utc::now(),
+                }
+                .insert(context)
+                .await?;
+
+                let count = Warning::count_for(context, guild_id, user_id).await?;
+                let mut response = format!(
+                    "<@{}> has been warned, they have {} warning(s).",
+                    user_id, count
+                );
+
+                let config = GuildConfig::get_field(context, guild_id, "warnings")
+                    .await?
+                    .and_then(|g| g.warnings);
+                if let Some(config) = config {
+                    let threshold = u64::from(config.timeout_threshold);
+                    if threshold > 0 && count % threshold == 0 {
+                        let duration = Duration::from_secs(config.timeout_duration.into());
+                        let result = moderator::timeout(
+                            context,
+                            guild_id,
+                            user_id,
+                            config.timeout_duration,
+                            format!("Reached {} warnings, last one: {}", count, reason),
+                        )
+                        .await;
+
+                        match result {
+                            Ok(()) => {
+                                response +=
+                                    &format!("\nThey have been timed out for {:?}.", duration)
+                            }
+                            Err(err) => {
+                                tracing::warn!(?err, "Failed to time out a warned member");
+                                response +=
+                                    "\nThey couldn't be timed out, check my permissions and roles.";
+                            }
+                        }
+                    }
+                }
+
+                response
+            } else if sub_command.name == "list" {
+                let warnings = Warning::list_for(context, guild_id, user_id).await?;
+                if warnings.is_empty() {
+                    format!("<@{}> has no warnings.", user_id)
+                } else {
+                    format_warnings(&warnings)
+                }
+            } else if sub_command.name == "clear" {
+                let cleared = Warning::clear_for(context, guild_id, user_id).await?;
+                format!("Removed {} warning(s) of <@{}>.", cleared, user_id)
+            } else {
+                return Ok(());
+            }
+        };
+
+        let interactions = context.get_interactions();
+        send(
+            &interactions,
+            &inter,
+            InteractionResponseType::ChannelMessageWithSource,
+            embed_response(response),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::id::Id;
+
+    use super::*;
+    use crate::util::{embed, EMBED_DESCRIPTION_LENGTH};
+
+    fn warning(reason: String) -> Warning {
+        Warning {
+            guild_id: Id::new(1),
+            user_id: Id::new(u64::MAX),
+            moderator_id: Id::new(u64::MAX),
+            reason,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn longest_warnings_fit_into_an_embed() {
+        let warnings = (0..Warning::MAX_LISTED)
+            .map(|_| warning("é".repeat(400)))
+            .collect::<Vec<_>>();
+
+        let list = format_warnings(&warnings);
+        assert!(list.chars().count() <= EMBED_DESCRIPTION_LENGTH);
+        assert!(embed(list).is_some());
+    }
+
+    #[test]
+    fn only_long_reasons_are_cut() {
+        let list = format_warnings(&[
+            warning("spam".to_owned()),
+            warning("a".repeat(LISTED_REASON_LENGTH)),
+            warning("b".repeat(LISTED_REASON_LENGTH + 1)),
+        ]);
+        let lines = list.lines().collect::<Vec<_>>();
+
+        assert!(lines[0].ends_with(": spam"));
+        assert!(lines[1].ends_with(&"a".repeat(LISTED_REASON_LENGTH)));
+        assert!(lines[2].ends_with(&format!("{}…", "b".repeat(LISTED_REASON_LENGTH))));
+    }
+}
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use anyhow::{Error, Result};
 use async_trait::async_trait;
-use mongodb::{bson::doc, options::FindOneOptions};
+use mongodb::bson::{doc, to_bson};
 use tracing::error_span;
 use twilight_gateway::stream::ShardRef;
 use twilight_http::client::InteractionClient;
@@ -16,13 +16,16 @@ use twilight_model::{
     guild::Permissions,
     http::interaction::InteractionResponseType,
 };
-use twilight_util::builder::{
-    command::{ChannelBuilder, CommandBuilder, StringBuilder, SubCommandBuilder},
-    InteractionResponseDataBuilder,
+use twilight_util::builder::command::{
+    BooleanBuilder, ChannelBuilder, CommandBuilder, RoleBuilder, StringBuilder, SubCommandBuilder,
 };
 
 use super::CustosCommand;
-use crate::{ctx::Context, schemas::GuildConfig, util::send};
+use crate::{
+    ctx::Context,
+    schemas::{GuildConfig, WelcomerConfig, WelcomerEmbed},
+    util::{self, embed_response, send},
+};
 
 pub struct WelcomerCommand {}
 
@@ -61,10 +64,95 @@ impl CustosCommand for WelcomerCommand {
                     .required(true),
             ),
         )
+        .option(
+            SubCommandBuilder::new(
+                "add-message",
+                "Add a welcome message, a random one is picked for every join.",
+            )
+            .option(
+                StringBuilder::new("value", "The welcome message.")
+                    .min_length(1)
+                    .max_length(2000)
+                    .required(true),
+            ),
+        )
+        .option(
+            SubCommandBuilder::new(
+                "set-rejoin-message",
+                "Set a message sent instead when a member joins again.",
+            )
+            .option(
+                StringBuilder::new("value", "The rejoin message.")
+                    .min_length(1)
+                    .max_length(2000)
+                    .required(true),
+            ),
+        )
+        .option(SubCommandBuilder::new(
+            "clear-messages",
+            "Remove all of the welcome messages.",
+        ))
+        .option(
+            SubCommandBuilder::new(
+                "add-autorole",
+                "Add a role given to every member that joins.",
+            )
+            .option(RoleBuilder::new("role", "The role to give.").required(true)),
+        )
+        .option(SubCommandBuilder::new(
+            "clear-autoroles",
+            "Stop giving roles to members that join.",
+        ))
+        .option(
+            SubCommandBuilder::new(
+                "set-leave-channel",
+                "Set a channel the leave message will be sent to.",
+            )
+            .option(
+                ChannelBuilder::new("channel", "The leave channel.")
+                    .channel_types(vec![ChannelType::GuildText])
+                    .required(true),
+            ),
+        )
+        .option(
+            SubCommandBuilder::new(
+                "set-leave-message",
+                "Set a message sent when a member leaves. Using simple tags.",
+            )
+            .option(
+                StringBuilder::new("value", "The leave message.")
+                    .min_length(1)
+                    .max_length(2000)
+                    .required(true),
+            ),
+        )
+        .option(
+            SubCommandBuilder::new(
+                "set-embed",
+                "Send the welcome messages in an embed with the member's avatar.",
+            )
+            .option(
+                BooleanBuilder::new("enabled", "Whether the messages are sent in an embed.")
+                    .required(true),
+            )
+            .option(
+                StringBuilder::new("title", "The title of the embed. Using simple tags.")
+                    .min_length(1)
+                    .max_length(256),
+            )
+            .option(StringBuilder::new(
+                "color",
+                "The color of the embed as a hex code, e.g. #5865F2.",
+            )),
+        )
         .default_member_permissions(Permissions::MANAGE_GUILD)
         .build()
     }
 
+    fn required_permissions() -> Permissions {
+        Permissions::MANAGE_GUILD
+    }
+
     async fn on_command_call(
         _: ShardRef<'_>,
         context: &Arc<Context>,
@@ -106,23 +194,14 @@ impl CustosCommand for WelcomerCommand {
                 &interactions,
                 &inter,
                 InteractionResponseType::ChannelMessageWithSource,
-                InteractionResponseDataBuilder::new()
-                    .content(format!("Welcome channel set to <#{}>", channel_id))
-                    .build(),
+                embed_response(format!("Welcome channel set to <#{}>", channel_id)),
             )
             .await?;
-        } else if sub_command.name == "set-message" {
-            let guild_config = match GuildConfig::get_guild(
-                context,
-                guild_id,
-                Some(
-                    FindOneOptions::builder()
-                        .projection(doc! { "welcomer": 1 })
-                        .build(),
-                ),
-            )
-            .await?
-            {
+        } else if sub_command.name == "set-message"
+            || sub_command.name == "add-message"
+            || sub_command.name == "set-rejoin-message"
+        {
+            let guild_config = match GuildConfig::get_field(context, guild_id, "welcomer").await? {
                 Some(g) => g,
                 None => {
                     error_span!(
@@ -141,9 +220,7 @@ impl CustosCommand for WelcomerCommand {
                     &interactions,
                     &inter,
                     InteractionResponseType::ChannelMessageWithSource,
-                    InteractionResponseDataBuilder::new()
-                        .content("You have to set a welcome channel first.")
-                        .build(),
+                    embed_response("You have to set a welcome channel first."),
                 )
                 .await?;
 
@@ -174,10 +251,247 @@ impl CustosCommand for WelcomerCommand {
                 None => return Err(Error::msg("No 'channel' option found.")),
             };
 
+            let (update, response) = if sub_command.name == "set-message" {
+                (
+                    doc! { "$set": { "welcomer.message": message } },
+                    "Welcome message has been set.",
+                )
+            } else if sub_command.name == "set-rejoin-message" {
+                (
+                    doc! { "$set": { "welcomer.rejoin_message": message } },
+                    "Rejoin message has been set.",
+                )
+            } else {
+                if welcomer.templates().len() >= WelcomerConfig::MAX_MESSAGES {
+                    send(
+                        &interactions,
+                        &inter,
+                        InteractionResponseType::ChannelMessageWithSource,
+                        embed_response(format!(
+                            "You can't have more than {} welcome messages.",
+                            WelcomerConfig::MAX_MESSAGES
+                        )),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+
+                (
+                    doc! { "$push": { "welcomer.messages": message } },
+                    "Welcome message has been added.",
+                )
+            };
+
+            GuildConfig::update_data_by_id_upsert(context, update, guild_id).await?;
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response(response),
+            )
+            .await?;
+        } else if sub_command.name == "clear-messages" {
+            GuildConfig::update_data_by_id_upsert(
+                context,
+                doc! {
+                    "$unset": {
+                        "welcomer.message": "",
+                        "welcomer.messages": "",
+                        "welcomer.rejoin_message": ""
+                    }
+                },
+                guild_id,
+            )
+            .await?;
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response("All welcome messages have been removed."),
+            )
+            .await?;
+        } else if sub_command.name == "add-autorole" {
+            // TODO: use let-else blocks when rustfmt supports it.
+            let role_id = match options.iter().find(|opt| opt.name == "role") {
+                Some(c) => match c.value {
+                    CommandOptionValue::Role(role) => role,
+                    _ => {
+                        return Err(Error::msg(
+                            "Option with name 'role' is not of CommandOptionValue::Role type.",
+                        ))
+                    }
+                },
+                None => return Err(Error::msg("No 'role' option found.")),
+            };
+
+            let autorole = GuildConfig::get_field(context, guild_id, "welcomer")
+                .await?
+                .and_then(|g| g.welcomer)
+                .map(|w| w.autorole)
+                .unwrap_or_default();
+
+            let response = if autorole.contains(&role_id) {
+                format!("<@&{}> is already given to new members.", role_id)
+            } else if autorole.len() >= WelcomerConfig::MAX_AUTOROLES {
+                format!(
+                    "You can't have more than {} autoroles.",
+                    WelcomerConfig::MAX_AUTOROLES
+                )
+            } else {
+                GuildConfig::update_data_by_id_upsert(
+                    context,
+                    doc! { "$addToSet": { "welcomer.autorole": role_id.to_string() } },
+                    guild_id,
+                )
+                .await?;
+                format!("<@&{}> will be given to new members.", role_id)
+            };
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response(response),
+            )
+            .await?;
+        } else if sub_command.name == "set-leave-channel" {
+            // TODO: use let-else blocks when rustfmt supports it.
+            let channel_id = match options.iter().find(|opt| opt.name == "channel") {
+                Some(c) => match c.value {
+                    CommandOptionValue::Channel(ch) => ch,
+                    _ => return Err(Error::msg(
+                        "Option with name 'channel' is not of CommandOptionValue::Channel type.",
+                    )),
+                },
+                None => return Err(Error::msg("No 'channel' option found.")),
+            };
+
+            GuildConfig::update_data_by_id_upsert(
+                context,
+                doc! { "$set": { "welcomer.leave_channel_id": channel_id.to_string() } },
+                guild_id,
+            )
+            .await?;
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response(format!("Leave channel set to <#{}>", channel_id)),
+            )
+            .await?;
+        } else if sub_command.name == "set-leave-message" {
+            let has_leave_channel = GuildConfig::get_field(context, guild_id, "welcomer")
+                .await?
+                .and_then(|g| g.welcomer)
+                .map_or(false, |w| w.leave_channel_id.is_some());
+
+            if !has_leave_channel {
+                send(
+                    &interactions,
+                    &inter,
+                    InteractionResponseType::ChannelMessageWithSource,
+                    embed_response("You have to set a leave channel first."),
+                )
+                .await?;
+                return Ok(());
+            }
+
+            // TODO: use let-else blocks when rustfmt supports it.
+            let message =
+                match options.iter().find(|opt| opt.name == "value") {
+                    Some(c) => match &c.value {
+                        CommandOptionValue::String(message) => message,
+                        _ => return Err(Error::msg(
+                            "Option with name 'value' is not of CommandOptionValue::String type.",
+                        )),
+                    },
+                    None => return Err(Error::msg("No 'value' option found.")),
+                };
+
+            GuildConfig::update_data_by_id_upsert(
+                context,
+                doc! { "$set": { "welcomer.leave_message": message } },
+                guild_id,
+            )
+            .await?;
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response("Leave message has been set."),
+            )
+            .await?;
+        } else if sub_command.name == "set-embed" {
+            let enabled = options
+                .iter()
+                .find(|opt| opt.name == "enabled")
+                .map_or(false, |opt| {
+                    matches!(opt.value, CommandOptionValue::Boolean(true))
+                });
+            let string_option = |name: &str| {
+                options
+                    .iter()
+                    .find(|opt| opt.name == name)
+                    .and_then(|opt| match &opt.value {
+                        CommandOptionValue::String(value) => Some(value.clone()),
+                        _ => None,
+                    })
+            };
+
+            let (update, response) = if enabled {
+                let color = match string_option("color") {
+                    Some(color) => match util::parse_hex_color(&color) {
+                        Some(color) => color,
+                        None => {
+                            send(
+                                &interactions,
+                                &inter,
+                                InteractionResponseType::ChannelMessageWithSource,
+                                embed_response(format!(
+                                    "`{}` is not a hex color, use one like `#5865F2`.",
+                                    color
+                                )),
+                            )
+                            .await?;
+                            return Ok(());
+                        }
+                    },
+                    None => util::EMBED_COLOR,
+                };
+
+                let embed = WelcomerEmbed {
+                    title: string_option("title"),
+                    color,
+                };
+                (
+                    doc! { "$set": { "welcomer.embed": to_bson(&embed)? } },
+                    "Welcome messages will be sent in an embed.",
+                )
+            } else {
+                (
+                    doc! { "$unset": { "welcomer.embed": "" } },
+                    "Welcome messages will be sent as plain text.",
+                )
+            };
+
+            GuildConfig::update_data_by_id_upsert(context, update, guild_id).await?;
+
+            send(
+                &interactions,
+                &inter,
+                InteractionResponseType::ChannelMessageWithSource,
+                embed_response(response),
+            )
+            .await?;
+        } else if sub_command.name == "clear-autoroles" {
             GuildConfig::update_data_by_id_upsert(
                 context,
-                doc! { "$set": { "welcomer.message": message } },
-                inter.guild_id.unwrap(),
+                doc! { "$unset": { "welcomer.autorole": "" } },
+                guild_id,
             )
             .await?;
 
@@ -185,9 +499,7 @@ impl CustosCommand for WelcomerCommand {
                 &interactions,
                 &inter,
                 InteractionResponseType::ChannelMessageWithSource,
-                InteractionResponseDataBuilder::new()
-                    .content("Welcome message has been set.")
-                    .build(),
+                embed_response("New members will no longer be given roles."),
             )
             .await?;
         }
@@ -1,14 +1,14 @@
 use anyhow::Result;
 use config::Config;
-use futures_util::{future::join_all, StreamExt};
-use std::{iter, sync::Arc, thread};
+use futures_util::StreamExt;
+use std::{panic, sync::Arc};
 use tokio::{signal, sync::watch, task::JoinSet};
 use twilight_gateway::{
     stream::{self, ShardEventStream},
     CloseFrame, Config as TwilightConfig, Intents, Shard,
 };
 
-use crate::ctx::Context;
+use crate::{commands::CommandRegistry, ctx::Context};
 
 mod app_config;
 mod commands;
@@ -16,6 +16,7 @@ mod ctx;
 mod events;
 mod plugins;
 mod schemas;
+mod scripting;
 mod tags;
 mod util;
 mod sync_http;
@@ -24,13 +25,63 @@ mod sync_http;
 async fn main() -> Result<()> {
     let cfg = app_config::read_config()?;
 
+    if cfg.get_bool("dry_run")? {
+        return dry_run(&cfg);
+    }
+
     parallel_shards_init(cfg).await?;
     Ok(())
 }
 
+/// Validates the config and builds every command definition without connecting
+/// to Discord or MongoDB.
+fn dry_run(cfg: &Config) -> Result<()> {
+    cfg.get_string("token")?;
+    let db_name = cfg.get_string("db_name")?;
+    let mongodb_address = cfg.get_string("mongodb_address")?;
+    let dev_guild_ids = app_config::dev_guild_ids(cfg)?;
+    let eval = app_config::EvalConfig::read(cfg)?;
+
+    let registry = match panic::catch_unwind(CommandRegistry::new) {
+        Ok(registry) => registry,
+        Err(_) => {
+            return Err(anyhow::Error::msg(
+                "Failed to build the command definitions.",
+            ))
+        }
+    };
+
+    println!("Config is valid.");
+    println!("Database: {} at {}", db_name, mongodb_address);
+    println!("Dev guilds: {:?}", dev_guild_ids);
+    println!("Eval prefix: {:?}", eval.prefix);
+    println!("Eval allowed users: {:?}", eval.allowed_users);
+    println!("Tag prefix: {:?}", eval.tag_prefix);
+    let commands = registry.definitions();
+    println!("Commands ({}):", commands.len());
+    for command in commands {
+        println!("  /{} - {} option(s)", command.name, command.options.len());
+    }
+
+    Ok(())
+}
+
 async fn parallel_shards_init(app_config: Config) -> Result<()> {
     tracing_subscriber::fmt::init();
 
+    // a panicking script would abort the whole bot otherwise, scripts report their errors
+    // themselves so this is only a backstop
+    rayon::ThreadPoolBuilder::new()
+        .panic_handler(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("<no message>");
+            tracing::error!(message, "A script thread panicked");
+        })
+        .build_global()?;
+
     let token = app_config.get_string("token")?;
     let config = TwilightConfig::new(
         token.clone(),
@@ -43,32 +94,26 @@ async fn parallel_shards_init(app_config: Config) -> Result<()> {
     let context = Arc::new(Context::new(app_config).await?);
     context.register_commands().await?;
 
-    let tasks = thread::available_parallelism()?.get();
-    let init = iter::repeat_with(Vec::new)
-        .take(tasks)
-        .collect::<Vec<Vec<_>>>();
     let shards =
         stream::create_recommended(context.get_http(), config, |_, builder| builder.build())
-            .await?
-            .enumerate()
-            .fold(init, |mut fold, (idx, shard)| {
-                fold[idx % tasks].push(shard);
-                fold
-            });
+            .await?;
 
     let (tx, rx) = watch::channel(false);
     let mut set = JoinSet::new();
 
-    for mut shards in shards {
+    for mut shards in shard_tasks(shards) {
         let mut rx = rx.clone();
         let ctx = Arc::clone(&context);
         set.spawn(async move {
             tokio::select! {
-                _ = listen_to_shards(shards.iter_mut(), Arc::clone(&ctx)) => {},
+                _ = listen_to_shards(shards.iter_mut(), ctx) => {},
                 _ = rx.changed() => {
-                    join_all(shards.iter_mut().map(|shard| async move {
-                        shard.close(CloseFrame::NORMAL).await
-                    })).await;
+                    for shard in &mut shards {
+                        if let Err(source) = shard.close(CloseFrame::NORMAL).await {
+                            let shard_id = shard.id();
+                            tracing::warn!(?source, ?shard_id, "error closing the shard");
+                        }
+                    }
                 }
             }
         });
@@ -78,12 +123,24 @@ async fn parallel_shards_init(app_config: Config) -> Result<()> {
 
     tracing::debug!("shutting down");
 
+    // scripts run on the rayon pool and wouldn't notice the shards closing
+    context.get_script_cancellation().cancel();
+
     tx.send(true)?;
     while set.join_next().await.is_some() {}
 
     Ok(())
 }
 
+/// The shards each task listens to. Every shard gets a task of its own, the runtime spreads
+/// the tasks over its worker threads and steals work between them so a shard with busy
+/// guilds never holds up the events of another shard.
+fn shard_tasks<S>(shards: impl IntoIterator<Item = S>) -> Vec<Vec<S>> {
+    shards.into_iter().map(|shard| vec![shard]).collect()
+}
+
+/// Handles the events of the shards one after another, `shard_tasks` gives every shard
+/// a task of its own so only the shard's own events wait on each other.
 async fn listen_to_shards(shards: impl Iterator<Item = &mut Shard>, context: Arc<Context>) {
     let mut stream = ShardEventStream::new(shards);
     loop {
@@ -103,6 +160,8 @@ async fn listen_to_shards(shards: impl Iterator<Item = &mut Shard>, context: Arc
 
         let shard_id = shard.id();
         let event_kind = event.kind();
+        // every event goes through the cache before it's handled, handlers read the owner and
+        // roles from the cache when needed instead of keeping them around.
         context.get_cache().update(&event);
 
         let result = events::process_event(shard, event, &context).await;
@@ -112,3 +171,18 @@ async fn listen_to_shards(shards: impl Iterator<Item = &mut Shard>, context: Arc
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_shard_gets_a_task_of_its_own() {
+        assert_eq!(shard_tasks(0..3), vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn no_shards_need_no_tasks() {
+        assert!(shard_tasks(Vec::<u64>::new()).is_empty());
+    }
+}
@@ -1,12 +1,13 @@
 use anyhow::{Error, Result};
+use async_trait::async_trait;
 use bson::doc;
-use mongodb::options::FindOneOptions;
-use std::sync::Arc;
-use tracing::{debug, instrument, trace};
+use lazy_static::lazy_static;
+use std::{sync::Arc, time::Duration};
+use tracing::{debug, error, instrument, trace};
 use twilight_http::request::AuditLogReason;
 use twilight_model::{
     gateway::payload::incoming::GuildAuditLogEntryCreate,
-    guild::Permissions,
+    guild::{audit_log::AuditLogEventType, Permissions, Role},
     id::{
         marker::{GuildMarker, RoleMarker, UserMarker},
         Id,
@@ -15,12 +16,142 @@ use twilight_model::{
 
 use crate::{
     ctx::Context,
-    schemas::{AntiAbuseEventConfig, GuildConfig},
+    schemas::{AntiAbuseActionBuilder, AntiAbuseEventConfig, GuildConfig},
 };
 
 use self::schemas::AuditLogEntry;
 
-use super::moderator;
+use super::{
+    moderator::{self, ModeratorAction},
+    Plugin,
+};
+
+lazy_static! {
+    /// Labels of the audit log action types shown to users, see `action_label`.
+    pub static ref ACTION_LABELS: Vec<(String, u16)> = vec![
+        ("Guild Update", 1),
+        ("Channel Create", 10),
+        ("Channel Update", 11),
+        ("Channel Delete", 12),
+        ("Channel Overwrite Create", 13),
+        ("Channel Overwrite Update", 14),
+        ("Channel Overwrite Delete", 15),
+        ("Member Kick", 20),
+        ("Member Prune", 21),
+        ("Member Ban Add", 22),
+        ("Member Ban Remove", 23),
+        ("Member Update", 24),
+        ("Member Role Update", 25),
+        ("Member Move", 26),
+        ("Member Disconnect", 27),
+        ("Bot Add", 28),
+        ("Role Create", 30),
+        ("Role Update", 31),
+        ("Role Delete", 32),
+        ("Invite Create", 40),
+        ("Invite Update", 41),
+        ("Invite Delete", 42),
+        ("Webhook Create", 50),
+        ("Webhook Update", 51),
+        ("Webhook Delete", 52),
+        ("Emoji Create", 60),
+        ("Emoji Update", 61),
+        ("Emoji Delete", 62),
+        ("Message Delete", 72),
+        ("Message BulkDelete", 73),
+        ("Message Pin", 74),
+        ("Message Unpin", 75),
+        ("Integration Create", 80),
+        ("Integration Update", 81),
+        ("Integration Delete", 82),
+        ("Stage Instance Create", 83),
+        ("Stage Instance Update", 84),
+        ("Stage Instance Delete", 85),
+        ("Sticker Create", 90),
+        ("Sticker Update", 91),
+        ("Sticker Delete", 92),
+        ("Guild Scheduled Event Create", 100),
+        ("Guild Scheduled Event Update", 101),
+        ("Guild Scheduled Event Delete", 102),
+        ("Thread Create", 110),
+        ("Thread Update", 111),
+        ("Thread Delete", 112),
+        ("Application Command Permission Update", 121),
+        ("Auto Moderation Rule Create", 140),
+        ("Auto Moderation Rule Update", 141),
+        ("Auto Moderation Rule Delete", 142),
+        ("Auto Moderation Block Message", 143),
+        ("Auto Moderation Flag To Channel", 144),
+        ("Auto Moderation User Communication Disabled", 145),
+    ]
+    .into_iter()
+    .map(|(name, code)| {
+        let mut name = name.to_lowercase();
+        if name.contains("guild") {
+            name = name.replace("guild", "server");
+        }
+        (name.to_lowercase(), code)
+    })
+    .collect::<Vec<(String, u16)>>();
+}
+
+/// The label of the action type, `None` for action types without a label.
+pub fn action_label(action_type: AuditLogEventType) -> Option<&'static str> {
+    let code = u16::from(action_type);
+    ACTION_LABELS
+        .iter()
+        .find(|(_, c)| *c == code)
+        .map(|(label, _)| label.as_str())
+}
+
+/// The action type with the label, the inverse of `action_label`.
+pub fn action_from_label(label: &str) -> Option<AuditLogEventType> {
+    ACTION_LABELS
+        .iter()
+        .find(|(l, _)| l == label)
+        .map(|(_, code)| AuditLogEventType::from(*code))
+}
+
+/// The label of the action type, falls back to the action type's name.
+pub fn describe_action(action_type: AuditLogEventType) -> String {
+    action_label(action_type)
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("{:?}", action_type))
+}
+
+/// The action that undoes the action type, if it can be undone.
+pub fn reversed_by(action_type: AuditLogEventType) -> Option<AuditLogEventType> {
+    match action_type {
+        AuditLogEventType::MemberBanAdd => Some(AuditLogEventType::MemberBanRemove),
+        _ => None,
+    }
+}
+
+fn punishment_reason(action_log: &AntiAbuseEventConfig) -> String {
+    format!(
+        "User exceeded {} sanctions per {} seconds for the action `{}`",
+        action_log.max_sanctions,
+        action_log.sanction_cooldown,
+        describe_action(action_log.action_type)
+    )
+}
+
+pub struct AntiAbusePlugin;
+
+#[async_trait]
+impl Plugin for AntiAbusePlugin {
+    fn get_plugin_name(&self) -> &'static str {
+        "anti_abuse"
+    }
+
+    async fn on_audit_log_entry(
+        &self,
+        context: &Arc<Context>,
+        log_entry: &GuildAuditLogEntryCreate,
+    ) -> Result<()> {
+        on_audit_log_create(context, Box::new(log_entry.clone())).await
+    }
+}
 
 pub async fn on_audit_log_create(
     context: &Arc<Context>,
@@ -38,17 +169,9 @@ pub async fn on_audit_log_create(
         return Ok(());
     }
 
-    let guild_config = GuildConfig::get_guild(
-        context,
-        guild_id,
-        Some(
-            FindOneOptions::builder()
-                .projection(doc! { "anti_abuse": 1 })
-                .build(),
-        ),
-    )
-    .await?
-    .unwrap();
+    let guild_config = GuildConfig::get_field(context, guild_id, "anti_abuse")
+        .await?
+        .unwrap();
 
     // TODO: use let-else
     let anti_abuse = match guild_config.anti_abuse {
@@ -56,6 +179,37 @@ pub async fn on_audit_log_create(
         None => return Ok(()),
     };
 
+    // checked before anything is recorded so exempt actions never count
+    let moderator_roles = if anti_abuse.whitelisted_roles.is_empty() {
+        Vec::new()
+    } else {
+        member_roles(context, guild_id, moderator_id).await?
+    };
+
+    if anti_abuse.is_whitelisted(moderator_id, &moderator_roles) {
+        trace!(
+            ?moderator_id,
+            "Ignoring the action of a whitelisted moderator"
+        );
+        return Ok(());
+    }
+
+    // a reversal nets out the moderator's matching action, it's not counted anymore
+    let reversed = anti_abuse.watched_actions.iter().find(|event| {
+        event.ignore_reversed && reversed_by(event.action_type) == Some(log_entry.action_type)
+    });
+
+    if let (Some(reversed), Some(target_id)) = (reversed, log_entry.target_id) {
+        AuditLogEntry::remove_reversed(
+            context,
+            guild_id,
+            moderator_id,
+            reversed.action_type,
+            target_id,
+        )
+        .await?;
+    }
+
     let action_log = anti_abuse
         .watched_actions
         .iter()
@@ -77,41 +231,224 @@ pub async fn on_audit_log_create(
         .await?;
 
     if log_entry_count > action_log.max_sanctions.try_into()? {
-        if action_log.punishment.is_ban() {
-            moderator::ban(
-                context,
-                guild_id,
-                audit_log_entry.moderator_id,
-                0,
-                format!(
-                    "User exceeded {} sanctions per {} seconds for the action type {:?}",
-                    action_log.max_sanctions, action_log.sanction_cooldown, action_log.action_type
-                ),
-            )
-            .await?;
-        } else if action_log.punishment.is_kick() {
-            moderator::kick(
-                context,
-                guild_id,
-                audit_log_entry.moderator_id,
-                format!(
-                    "User exceeded {} sanctions per {} seconds for the action type {:?}",
-                    action_log.max_sanctions, action_log.sanction_cooldown, action_log.action_type
-                ),
-            )
-            .await?;
-        } else {
-            if action_log.punishment.is_timeout() {
-                unimplemented!()
+        punish(context, guild_id, audit_log_entry.moderator_id, action_log).await?;
+    }
+
+    Ok(())
+}
+
+/// The roles of the member from the cache, fetched over HTTP if the member isn't cached.
+async fn member_roles(
+    context: &Arc<Context>,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<Vec<Id<RoleMarker>>> {
+    let cached_roles = context
+        .get_cache()
+        .member(guild_id, user_id)
+        .map(|member| member.roles().to_vec());
+
+    Ok(match cached_roles {
+        Some(roles) => roles,
+        None => {
+            context
+                .get_http()
+                .guild_member(guild_id, user_id)
+                .await?
+                .model()
+                .await?
+                .roles
+        }
+    })
+}
+
+/// The actions of a punishment in the order they're applied, from the mildest to the harshest
+/// so the moderator is still a member for the demote and the timeout. A ban already removes
+/// the moderator so a kick isn't added next to it.
+pub fn punishment_actions(punishment: &AntiAbuseActionBuilder) -> Vec<ModeratorAction> {
+    let mut actions = Vec::with_capacity(3);
+
+    if punishment.is_demote() {
+        actions.push(ModeratorAction::Demote);
+    }
+    if punishment.is_timeout() {
+        actions.push(ModeratorAction::Timeout);
+    }
+    if punishment.is_ban() {
+        actions.push(ModeratorAction::Ban);
+    } else if punishment.is_kick() {
+        actions.push(ModeratorAction::Kick);
+    }
+
+    actions
+}
+
+/// The moderator calls a punishment is made of. `punish` makes them through Discord, the
+/// trait lets the dispatch in `apply_punishment` be tested without it.
+#[async_trait]
+pub trait Punisher {
+    async fn demote(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        action_log: &AntiAbuseEventConfig,
+    ) -> Result<()>;
+
+    async fn timeout(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        duration_seconds: u32,
+        reason: String,
+    ) -> Result<()>;
+
+    async fn kick(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        reason: String,
+    ) -> Result<()>;
+
+    async fn ban(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        reason: String,
+    ) -> Result<()>;
+}
+
+struct DiscordPunisher<'a>(&'a Arc<Context>);
+
+#[async_trait]
+impl Punisher for DiscordPunisher<'_> {
+    async fn demote(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        action_log: &AntiAbuseEventConfig,
+    ) -> Result<()> {
+        demote_abuser(self.0, guild_id, user_id, action_log).await
+    }
+
+    async fn timeout(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        duration_seconds: u32,
+        reason: String,
+    ) -> Result<()> {
+        moderator::timeout(self.0, guild_id, user_id, duration_seconds, reason).await
+    }
+
+    async fn kick(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        reason: String,
+    ) -> Result<()> {
+        moderator::kick(self.0, guild_id, user_id, reason).await
+    }
+
+    async fn ban(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        reason: String,
+    ) -> Result<()> {
+        moderator::ban(self.0, guild_id, user_id, 0, reason).await
+    }
+}
+
+/// Applies every action of the configured punishment to a moderator who went over the limit,
+/// see `apply_punishment`.
+pub async fn punish(
+    context: &Arc<Context>,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    action_log: &AntiAbuseEventConfig,
+) -> Result<()> {
+    apply_punishment(&DiscordPunisher(context), guild_id, user_id, action_log).await
+}
+
+/// Makes the calls of every action in `punishment_actions`. A failed action is logged and
+/// the rest are still applied, the last error is returned.
+async fn apply_punishment(
+    punisher: &(impl Punisher + Sync),
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    action_log: &AntiAbuseEventConfig,
+) -> Result<()> {
+    let mut result = Ok(());
+
+    for action in punishment_actions(&action_log.punishment) {
+        let applied = match action {
+            ModeratorAction::Demote => punisher.demote(guild_id, user_id, action_log).await,
+            ModeratorAction::Timeout => {
+                let duration = action_log.timeout_duration();
+                let reason = format!(
+                    "{}, timed out for {:?}",
+                    punishment_reason(action_log),
+                    Duration::from_secs(u64::from(duration))
+                );
+                punisher.timeout(guild_id, user_id, duration, reason).await
+            }
+            ModeratorAction::Kick => {
+                punisher
+                    .kick(guild_id, user_id, punishment_reason(action_log))
+                    .await
             }
+            ModeratorAction::Ban => {
+                punisher
+                    .ban(guild_id, user_id, punishment_reason(action_log))
+                    .await
+            }
+        };
+
+        if let Err(err) = applied {
+            error!(
+                ?guild_id,
+                ?user_id,
+                ?action,
+                ?err,
+                "Failed to apply a punishment"
+            );
+            result = Err(err);
+        }
+    }
+
+    result
+}
+
+/// Resolves the roles from the cache, if any of them is missing (e.g. right after startup
+/// while the cache is still cold) the guild's roles are fetched over HTTP instead.
+pub async fn resolve_roles(
+    context: &Arc<Context>,
+    guild_id: Id<GuildMarker>,
+    role_ids: &[Id<RoleMarker>],
+) -> Result<Vec<Role>> {
+    let mut roles = Vec::with_capacity(role_ids.len());
+
+    for role_id in role_ids {
+        let role = context
+            .get_cache()
+            .role(*role_id)
+            .map(|r| r.resource().clone());
 
-            if action_log.punishment.is_demote() {
-                demote_abuser(context, guild_id, audit_log_entry.moderator_id, action_log).await?;
+        match role {
+            Some(r) => roles.push(r),
+            None => {
+                trace!("Role {role_id} is not in cache, fetching the guild's roles!");
+
+                let guild_roles = context.get_http().roles(guild_id).await?.models().await?;
+                return Ok(guild_roles
+                    .into_iter()
+                    .filter(|r| role_ids.contains(&r.id))
+                    .collect());
             }
         }
     }
 
-    Ok(())
+    Ok(roles)
 }
 
 #[instrument]
@@ -121,9 +458,14 @@ pub async fn demote_abuser(
     user_id: Id<UserMarker>,
     action_log: &AntiAbuseEventConfig,
 ) -> Result<()> {
-    // TODO: use let-else
-    let mut guild_member_roles = match context.get_cache().member(guild_id, user_id) {
-        Some(g) => g.roles().to_vec(),
+    // the cache reference is released before fetching so it isn't held across the await
+    let cached_roles = context
+        .get_cache()
+        .member(guild_id, user_id)
+        .map(|member| member.roles().to_vec());
+
+    let mut guild_member_roles = match cached_roles {
+        Some(roles) => roles,
         None => {
             trace!("No guild member in cache, we'll try to fetch them!");
 
@@ -137,14 +479,7 @@ pub async fn demote_abuser(
         }
     };
 
-    let mut fetch_roles = Vec::with_capacity(guild_member_roles.len());
-
-    for role_id in &guild_member_roles {
-        let role = context.get_cache().role(*role_id);
-        if let Some(r) = role {
-            fetch_roles.push(r.to_owned());
-        }
-    }
+    let fetch_roles = resolve_roles(context, guild_id, &guild_member_roles).await?;
 
     const MOD_PERMS: &[Permissions] = &[
         Permissions::ADMINISTRATOR,
@@ -187,10 +522,7 @@ pub async fn demote_abuser(
         .get_http()
         .update_guild_member(guild_id, user_id)
         .roles(&guild_member_roles)
-        .reason(&format!(
-            "User exceeded {} sanctions per {} seconds for the action type {:?}",
-            action_log.max_sanctions, action_log.sanction_cooldown, action_log.action_type
-        ))?
+        .reason(&punishment_reason(action_log))?
         .await?;
 
     Ok(())
@@ -202,7 +534,11 @@ pub mod schemas {
     use anyhow::{Error, Result};
     use bson::to_bson;
     use chrono::{DateTime, Duration, Utc};
-    use mongodb::{bson::doc, results::InsertOneResult};
+    use mongodb::{
+        bson::{doc, Document},
+        options::FindOneAndDeleteOptions,
+        results::InsertOneResult,
+    };
     use serde::{Deserialize, Serialize};
     use twilight_model::{
         gateway::payload::incoming::GuildAuditLogEntryCreate,
@@ -241,6 +577,8 @@ pub mod schemas {
             Ok(audit_log_entries.insert_one(self, None).await?)
         }
 
+        /// Counts the entries of the moderator for the action within the sanction cooldown,
+        /// every entry expires a cooldown after it was made so the window slides.
         pub async fn count_entries_for(
             &self,
             context: &Arc<Context>,
@@ -252,19 +590,67 @@ pub mod schemas {
                 .collection::<AuditLogEntry>("audit_log_entries");
 
             let count = audit_log_entries
-                .count_documents(
-                    doc! {
-                        "guild_id": to_bson(&self.guild_id)?,
-                        "moderator_id": to_bson(&self.moderator_id)?,
-                        "action.kind": to_bson(&action)?
-                    },
-                    None,
-                )
+                .count_documents(self.count_filter(action, Utc::now())?, None)
                 .await?;
 
             Ok(count)
         }
 
+        /// Matches the entries of the moderator for the action that haven't expired at `now`.
+        fn count_filter(&self, action: AuditLogEventType, now: DateTime<Utc>) -> Result<Document> {
+            // mongodb removes expired documents only once a minute, so the expired entries
+            // that are still around are filtered out to keep the window exact
+            Ok(doc! {
+                "guild_id": to_bson(&self.guild_id)?,
+                "moderator_id": to_bson(&self.moderator_id)?,
+                "action.kind": to_bson(&action)?,
+                "expires_at": { "$gt": bson::DateTime::from_chrono(now) }
+            })
+        }
+
+        /// Removes the most recent unexpired entry of the moderator's action on the target,
+        /// returns whether there was one.
+        pub async fn remove_reversed(
+            context: &Arc<Context>,
+            guild_id: Id<GuildMarker>,
+            moderator_id: Id<UserMarker>,
+            action: AuditLogEventType,
+            target_id: Id<GenericMarker>,
+        ) -> Result<bool> {
+            let audit_log_entries = context
+                .get_mongodb()
+                .database(&context.get_config().get_string("db_name")?)
+                .collection::<AuditLogEntry>("audit_log_entries");
+
+            let removed = audit_log_entries
+                .find_one_and_delete(
+                    Self::reversed_filter(guild_id, moderator_id, action, target_id, Utc::now())?,
+                    FindOneAndDeleteOptions::builder()
+                        .sort(doc! { "expires_at": -1 })
+                        .build(),
+                )
+                .await?;
+
+            Ok(removed.is_some())
+        }
+
+        /// Matches the unexpired entries at `now` of the moderator's action on the target.
+        fn reversed_filter(
+            guild_id: Id<GuildMarker>,
+            moderator_id: Id<UserMarker>,
+            action: AuditLogEventType,
+            target_id: Id<GenericMarker>,
+            now: DateTime<Utc>,
+        ) -> Result<Document> {
+            Ok(doc! {
+                "guild_id": to_bson(&guild_id)?,
+                "moderator_id": to_bson(&moderator_id)?,
+                "action.kind": to_bson(&action)?,
+                "action.target_id": to_bson(&target_id)?,
+                "expires_at": { "$gt": bson::DateTime::from_chrono(now) }
+            })
+        }
+
         pub fn from_audit_log_entry(
             value: &GuildAuditLogEntryCreate,
             saction_cooldown: i32,
@@ -279,19 +665,404 @@ pub mod schemas {
                 None => return Err(Error::msg("No user_id field present.")),
             };
 
-            let mut expires_at = Utc::now();
-            expires_at += Duration::seconds(saction_cooldown.into());
+            let action = ActionEntry {
+                kind: value.action_type,
+                reason: value.reason.clone(),
+                target_id: value.target_id,
+            };
 
-            Ok(AuditLogEntry {
+            Ok(AuditLogEntry::new(
                 guild_id,
                 moderator_id,
-                action: ActionEntry {
-                    kind: value.action_type,
-                    reason: value.reason.clone(),
-                    target_id: value.target_id,
-                },
-                expires_at: Utc::now(),
+                action,
+                saction_cooldown,
+                Utc::now(),
+            ))
+        }
+
+        /// An entry recorded at `now`, it counts toward the limit for a sanction cooldown.
+        fn new(
+            guild_id: Id<GuildMarker>,
+            moderator_id: Id<UserMarker>,
+            action: ActionEntry,
+            sanction_cooldown: i32,
+            now: DateTime<Utc>,
+        ) -> Self {
+            AuditLogEntry {
+                guild_id,
+                moderator_id,
+                action,
+                expires_at: now + Duration::seconds(sanction_cooldown.into()),
+            }
+        }
+    }
+    #[cfg(test)]
+    mod tests {
+        use bson::Bson;
+
+        use super::*;
+
+        const COOLDOWN: i64 = 60;
+
+        fn entry(recorded_at: DateTime<Utc>) -> AuditLogEntry {
+            let action = ActionEntry {
+                kind: AuditLogEventType::MemberKick,
+                reason: None,
+                target_id: None,
+            };
+            AuditLogEntry::new(Id::new(1), Id::new(2), action, COOLDOWN as i32, recorded_at)
+        }
+
+        /// Whether mongodb would count the entry, for the equality and `$gt` conditions the
+        /// filter is made of.
+        fn counted(filter: &Document, entry: &AuditLogEntry) -> bool {
+            let entry = Bson::Document(bson::to_document(entry).unwrap());
+
+            filter.iter().all(|(key, condition)| {
+                let value = key.split('.').fold(entry.clone(), |value, field| {
+                    value
+                        .as_document()
+                        .and_then(|document| document.get(field))
+                        .cloned()
+                        .unwrap_or(Bson::Null)
+                });
+
+                match condition {
+                    Bson::Document(operator) => {
+                        let after = operator.get_datetime("$gt").unwrap();
+                        value.as_datetime().is_some_and(|value| value > after)
+                    }
+                    _ => &value == condition,
+                }
             })
         }
+
+        fn counted_at(entry: &AuditLogEntry, now: DateTime<Utc>) -> bool {
+            let filter = entry
+                .count_filter(AuditLogEventType::MemberKick, now)
+                .unwrap();
+            counted(&filter, entry)
+        }
+
+        #[test]
+        fn entries_within_the_cooldown_count() {
+            let recorded_at = Utc::now();
+            let entry = entry(recorded_at);
+            let later = |seconds| recorded_at + Duration::seconds(seconds);
+
+            assert!(counted_at(&entry, recorded_at));
+            assert!(counted_at(&entry, later(COOLDOWN - 1)));
+        }
+
+        #[test]
+        fn expired_entries_dont_count() {
+            let recorded_at = Utc::now();
+            let entry = entry(recorded_at);
+            let later = |seconds| recorded_at + Duration::seconds(seconds);
+
+            assert!(!counted_at(&entry, later(COOLDOWN)));
+            assert!(!counted_at(&entry, later(COOLDOWN + 1)));
+        }
+
+        #[test]
+        fn the_window_slides() {
+            let start = Utc::now();
+            let entries = [0, 30, 50].map(|seconds| entry(start + Duration::seconds(seconds)));
+
+            // 70 seconds in the first entry has expired but the later ones still count
+            let now = start + Duration::seconds(70);
+            let count = entries
+                .iter()
+                .filter(|entry| counted_at(entry, now))
+                .count();
+            assert_eq!(count, 2);
+        }
+
+        #[test]
+        fn other_actions_and_moderators_dont_count() {
+            let now = Utc::now();
+            let entry = entry(now);
+
+            let filter = entry
+                .count_filter(AuditLogEventType::MemberBanAdd, now)
+                .unwrap();
+            assert!(!counted(&filter, &entry));
+
+            let mut other_moderator = entry.clone();
+            other_moderator.moderator_id = Id::new(3);
+            let filter = other_moderator
+                .count_filter(AuditLogEventType::MemberKick, now)
+                .unwrap();
+            assert!(!counted(&filter, &entry));
+        }
+
+        fn ban(target_id: u64, recorded_at: DateTime<Utc>) -> AuditLogEntry {
+            let action = ActionEntry {
+                kind: AuditLogEventType::MemberBanAdd,
+                reason: None,
+                target_id: Some(Id::new(target_id)),
+            };
+            AuditLogEntry::new(Id::new(1), Id::new(2), action, COOLDOWN as i32, recorded_at)
+        }
+
+        #[test]
+        fn unbanned_bans_dont_count() {
+            let now = Utc::now();
+            let mut entries = vec![ban(5, now), ban(6, now)];
+
+            // the moderator unbans the member 5, the matching ban is removed
+            let filter = AuditLogEntry::reversed_filter(
+                Id::new(1),
+                Id::new(2),
+                AuditLogEventType::MemberBanAdd,
+                Id::new(5),
+                now,
+            )
+            .unwrap();
+            entries.retain(|entry| !counted(&filter, entry));
+
+            let filter = entries[0]
+                .count_filter(AuditLogEventType::MemberBanAdd, now)
+                .unwrap();
+            let count = entries
+                .iter()
+                .filter(|entry| counted(&filter, entry))
+                .count();
+            assert_eq!(count, 1);
+            assert_eq!(entries[0].action.target_id, Some(Id::new(6)));
+        }
+
+        #[test]
+        fn expired_bans_arent_reversed() {
+            let recorded_at = Utc::now();
+            let entry = ban(5, recorded_at);
+
+            let filter = AuditLogEntry::reversed_filter(
+                Id::new(1),
+                Id::new(2),
+                AuditLogEventType::MemberBanAdd,
+                Id::new(5),
+                recorded_at + Duration::seconds(COOLDOWN),
+            )
+            .unwrap();
+            assert!(!counted(&filter, &entry));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Records the calls instead of making them, `failing` returns an error instead.
+    #[derive(Default)]
+    struct RecordingPunisher {
+        calls: Mutex<Vec<(ModeratorAction, String)>>,
+        failing: Option<ModeratorAction>,
+    }
+
+    impl RecordingPunisher {
+        fn record(&self, action: ModeratorAction, details: String) -> Result<()> {
+            self.calls.lock().unwrap().push((action, details));
+            match self.failing {
+                Some(failing) if failing == action => Err(Error::msg("missing permissions")),
+                _ => Ok(()),
+            }
+        }
+
+        fn actions(&self) -> Vec<ModeratorAction> {
+            self.calls
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(action, _)| *action)
+                .collect()
+        }
+    }
+
+    #[async_trait]
+    impl Punisher for RecordingPunisher {
+        async fn demote(
+            &self,
+            _: Id<GuildMarker>,
+            _: Id<UserMarker>,
+            _: &AntiAbuseEventConfig,
+        ) -> Result<()> {
+            self.record(ModeratorAction::Demote, String::new())
+        }
+
+        async fn timeout(
+            &self,
+            _: Id<GuildMarker>,
+            _: Id<UserMarker>,
+            duration_seconds: u32,
+            _: String,
+        ) -> Result<()> {
+            self.record(ModeratorAction::Timeout, duration_seconds.to_string())
+        }
+
+        async fn kick(&self, _: Id<GuildMarker>, _: Id<UserMarker>, reason: String) -> Result<()> {
+            self.record(ModeratorAction::Kick, reason)
+        }
+
+        async fn ban(&self, _: Id<GuildMarker>, _: Id<UserMarker>, reason: String) -> Result<()> {
+            self.record(ModeratorAction::Ban, reason)
+        }
+    }
+
+    fn action_log(punishment: AntiAbuseActionBuilder) -> AntiAbuseEventConfig {
+        AntiAbuseEventConfig {
+            action_type: AuditLogEventType::MemberBanAdd,
+            max_sanctions: 3,
+            sanction_cooldown: 60,
+            punishment,
+            ignore_reversed: false,
+            timeout_duration: None,
+        }
+    }
+
+    async fn dispatch(
+        punisher: &RecordingPunisher,
+        action_log: &AntiAbuseEventConfig,
+    ) -> Result<()> {
+        apply_punishment(punisher, Id::new(1), Id::new(2), action_log).await
+    }
+
+    #[test]
+    fn action_labels_match_known_action_types() {
+        for (label, code) in ACTION_LABELS.iter() {
+            let action_type = AuditLogEventType::from(*code);
+            assert!(
+                !std::matches!(action_type, AuditLogEventType::Unknown(_)),
+                "{} has the unknown code {}",
+                label,
+                code
+            );
+            assert_eq!(u16::from(action_type), *code);
+            assert_eq!(action_label(action_type), Some(label.as_str()));
+            assert_eq!(action_from_label(label), Some(action_type));
+        }
+    }
+
+    #[test]
+    fn action_labels_are_unique() {
+        for (i, (label, code)) in ACTION_LABELS.iter().enumerate() {
+            for (other_label, other_code) in &ACTION_LABELS[i + 1..] {
+                assert_ne!(label, other_label);
+                assert_ne!(code, other_code, "{} and {}", label, other_label);
+            }
+        }
+    }
+
+    #[test]
+    fn bans_are_reversed_by_unbans() {
+        assert_eq!(
+            reversed_by(AuditLogEventType::MemberBanAdd),
+            Some(AuditLogEventType::MemberBanRemove)
+        );
+    }
+
+    #[test]
+    fn other_actions_arent_reversed() {
+        for action_type in [
+            AuditLogEventType::MemberBanRemove,
+            AuditLogEventType::MemberKick,
+            AuditLogEventType::ChannelDelete,
+        ] {
+            assert_eq!(reversed_by(action_type), None);
+        }
+    }
+
+    #[test]
+    fn member_disconnect_is_labelled() {
+        assert_eq!(
+            action_label(AuditLogEventType::MemberDisconnect),
+            Some("member disconnect")
+        );
+    }
+
+    #[test]
+    fn punishment_actions_escalate() {
+        let none = AntiAbuseActionBuilder::new;
+
+        // a ban already removes the moderator, kicking them too would fail
+        assert_eq!(
+            punishment_actions(&none().add_ban().add_kick()),
+            vec![ModeratorAction::Ban]
+        );
+        // the timeout goes first since a banned moderator can't be timed out
+        assert_eq!(
+            punishment_actions(&none().add_ban().add_timeout()),
+            vec![ModeratorAction::Timeout, ModeratorAction::Ban]
+        );
+        assert_eq!(
+            punishment_actions(&none().add_timeout().add_demote()),
+            vec![ModeratorAction::Demote, ModeratorAction::Timeout]
+        );
+        assert_eq!(
+            punishment_actions(&none().add_demote().add_timeout().add_kick().add_ban()),
+            vec![
+                ModeratorAction::Demote,
+                ModeratorAction::Timeout,
+                ModeratorAction::Ban
+            ]
+        );
+        assert!(punishment_actions(&none()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn every_punishment_makes_its_call() {
+        let none = AntiAbuseActionBuilder::new;
+        let punishments = [
+            (none().add_demote(), ModeratorAction::Demote),
+            (none().add_timeout(), ModeratorAction::Timeout),
+            (none().add_kick(), ModeratorAction::Kick),
+            (none().add_ban(), ModeratorAction::Ban),
+        ];
+
+        for (punishment, expected) in punishments {
+            let punisher = RecordingPunisher::default();
+            dispatch(&punisher, &action_log(punishment)).await.unwrap();
+            assert_eq!(punisher.actions(), vec![expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn punishment_carries_the_config() {
+        let punisher = RecordingPunisher::default();
+        let mut config = action_log(AntiAbuseActionBuilder::new().add_timeout().add_ban());
+        config.timeout_duration = Some(120);
+
+        dispatch(&punisher, &config).await.unwrap();
+
+        let calls = punisher.calls.lock().unwrap();
+        assert_eq!(calls[0], (ModeratorAction::Timeout, "120".to_owned()));
+        assert_eq!(calls[1], (ModeratorAction::Ban, punishment_reason(&config)));
+    }
+
+    #[tokio::test]
+    async fn failed_action_doesnt_stop_the_rest() {
+        let punisher = RecordingPunisher {
+            failing: Some(ModeratorAction::Demote),
+            ..Default::default()
+        };
+        let config = action_log(AntiAbuseActionBuilder::new().add_demote().add_kick());
+
+        assert!(dispatch(&punisher, &config).await.is_err());
+        assert_eq!(
+            punisher.actions(),
+            vec![ModeratorAction::Demote, ModeratorAction::Kick]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_punishment_makes_no_calls() {
+        let punisher = RecordingPunisher::default();
+        dispatch(&punisher, &action_log(AntiAbuseActionBuilder::new()))
+            .await
+            .unwrap();
+        assert!(punisher.actions().is_empty());
     }
 }
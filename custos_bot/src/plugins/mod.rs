@@ -1,3 +1,53 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use twilight_model::gateway::payload::incoming::{
+    GuildAuditLogEntryCreate, MemberAdd, MemberRemove, MessageCreate,
+};
+
+use crate::ctx::Context;
+
 pub mod anti_abuse;
 pub mod moderator;
 pub mod welcomer;
+
+/// Every plugin that receives events, in the order they are called.
+pub static PLUGINS: &[&dyn Plugin] = &[&welcomer::WelcomerPlugin, &anti_abuse::AntiAbusePlugin];
+
+#[async_trait]
+pub trait Plugin: Sync {
+    fn get_plugin_name(&self) -> &'static str;
+
+    async fn on_member_add(&self, _context: &Arc<Context>, _member_add: &MemberAdd) -> Result<()> {
+        Ok(())
+    }
+
+    async fn on_member_remove(
+        &self,
+        _context: &Arc<Context>,
+        _member_remove: &MemberRemove,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn on_audit_log_entry(
+        &self,
+        _context: &Arc<Context>,
+        _log_entry: &GuildAuditLogEntryCreate,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn on_message(&self, _context: &Arc<Context>, _message: &MessageCreate) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Logs the error of a plugin instead of returning it, so one failing plugin doesn't keep
+/// the rest from handling the event.
+pub fn log_plugin_result(plugin: &dyn Plugin, result: Result<()>) {
+    if let Err(e) = result {
+        tracing::error!(plugin = plugin.get_plugin_name(), error = ?e, "Plugin failed to handle an event");
+    }
+}
@@ -1,14 +1,27 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use chrono::Utc;
 use twilight_http::request::AuditLogReason;
-use twilight_model::id::{
-    marker::{GuildMarker, UserMarker},
-    Id,
+use twilight_model::{
+    id::{
+        marker::{GuildMarker, UserMarker},
+        Id,
+    },
+    util::Timestamp,
 };
 
 use crate::ctx::Context;
 
+/// The actions a moderator can be punished with, from the mildest to the harshest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeratorAction {
+    Demote,
+    Timeout,
+    Kick,
+    Ban,
+}
+
 pub async fn ban(
     context: &Arc<Context>,
     guild_id: Id<GuildMarker>,
@@ -39,3 +52,21 @@ pub async fn kick(
     Ok(())
 }
 
+/// Times the member out for `duration_seconds`, Discord caps it at 28 days.
+pub async fn timeout(
+    context: &Arc<Context>,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    duration_seconds: u32,
+    reason: String,
+) -> Result<()> {
+    let until = Timestamp::from_secs(Utc::now().timestamp() + i64::from(duration_seconds))?;
+
+    let http = context.get_http();
+    http.update_guild_member(guild_id, user_id)
+        .communication_disabled_until(Some(until))?
+        .reason(&reason)?
+        .await?;
+
+    Ok(())
+}
@@ -1,18 +1,53 @@
 use crate::tags;
-use crate::{ctx::Context, schemas::GuildConfig};
+use crate::{
+    ctx::Context,
+    schemas::{GuildConfig, SeenMember, WelcomerConfig, WelcomerEmbed},
+    util,
+};
 use anyhow::{Error, Result};
-use bson::doc;
-use mongodb::options::FindOneOptions;
+use async_trait::async_trait;
 use std::collections::BTreeMap;
 use std::sync::Arc;
-use tracing::{error, instrument};
-use twilight_model::gateway::payload::incoming::MemberAdd;
-use twilight_model::{id::marker::GuildMarker, id::Id, user::User};
+use tracing::{error, instrument, warn};
+use twilight_http::request::AuditLogReason;
+use twilight_model::channel::message::Embed;
+use twilight_model::gateway::payload::incoming::{MemberAdd, MemberRemove};
+use twilight_model::guild::Role;
+use twilight_model::{
+    id::marker::{GuildMarker, RoleMarker},
+    id::Id,
+    user::User,
+};
+use twilight_util::builder::embed::{EmbedBuilder, ImageSource};
+
+use super::{anti_abuse::resolve_roles, Plugin};
+
+pub struct WelcomerPlugin;
+
+#[async_trait]
+impl Plugin for WelcomerPlugin {
+    fn get_plugin_name(&self) -> &'static str {
+        "welcomer"
+    }
+
+    async fn on_member_add(&self, context: &Arc<Context>, member_add: &MemberAdd) -> Result<()> {
+        on_member_add(context, Box::new(member_add.clone()).into()).await
+    }
+
+    async fn on_member_remove(
+        &self,
+        context: &Arc<Context>,
+        member_remove: &MemberRemove,
+    ) -> Result<()> {
+        on_member_remove(context, member_remove).await
+    }
+}
 
 #[derive(Debug)]
 pub struct WelcomerMemberAdd {
     guild_id: Id<GuildMarker>,
     user: User,
+    roles: Vec<Id<RoleMarker>>,
 }
 
 impl From<Box<MemberAdd>> for WelcomerMemberAdd {
@@ -20,25 +55,140 @@ impl From<Box<MemberAdd>> for WelcomerMemberAdd {
         WelcomerMemberAdd {
             guild_id: value.guild_id,
             user: value.user.clone(),
+            roles: value.roles.clone(),
         }
     }
 }
 
+/// The autoroles the member doesn't have yet. Only roles below the bot's highest role can
+/// be assigned, the others are returned separately so they can be reported.
+fn autoroles_to_add(
+    member_roles: &[Id<RoleMarker>],
+    autorole: &[Id<RoleMarker>],
+    roles: &[Role],
+    bot_position: i64,
+) -> (Vec<Id<RoleMarker>>, Vec<Id<RoleMarker>>) {
+    let mut to_add = Vec::new();
+    let mut skipped = Vec::new();
+
+    for role_id in autorole {
+        if member_roles.contains(role_id) || to_add.contains(role_id) {
+            continue;
+        }
+
+        let assignable = roles
+            .iter()
+            .find(|role| role.id == *role_id)
+            .map_or(false, |role| !role.managed && role.position < bot_position);
+
+        if assignable {
+            to_add.push(*role_id);
+        } else {
+            skipped.push(*role_id);
+        }
+    }
+
+    (to_add, skipped)
+}
+
+async fn assign_autoroles(
+    context: &Arc<Context>,
+    member_add: &WelcomerMemberAdd,
+    welcomer: &WelcomerConfig,
+) -> Result<()> {
+    let bot_id = context.get_app().id.cast();
+    let cached_roles = context
+        .get_cache()
+        .member(member_add.guild_id, bot_id)
+        .map(|member| member.roles().to_vec());
+
+    let bot_roles = match cached_roles {
+        Some(roles) => roles,
+        None => {
+            context
+                .get_http()
+                .guild_member(member_add.guild_id, bot_id)
+                .await?
+                .model()
+                .await?
+                .roles
+        }
+    };
+
+    let role_ids = bot_roles
+        .iter()
+        .chain(welcomer.autorole.iter())
+        .copied()
+        .collect::<Vec<_>>();
+    let roles = resolve_roles(context, member_add.guild_id, &role_ids).await?;
+
+    let bot_position = roles
+        .iter()
+        .filter(|role| bot_roles.contains(&role.id))
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0);
+
+    let (to_add, skipped) =
+        autoroles_to_add(&member_add.roles, &welcomer.autorole, &roles, bot_position);
+
+    if !skipped.is_empty() {
+        warn!(guild_id = ?member_add.guild_id, ?skipped, "Skipped autoroles the bot can't assign");
+    }
+
+    // added one by one, replacing the whole role list would undo roles that other bots gave
+    // the member in the meantime
+    let mut result = Ok(());
+    for role_id in to_add {
+        let added = context
+            .get_http()
+            .add_guild_member_role(member_add.guild_id, member_add.user.id, role_id)
+            .reason("Autorole")?
+            .await;
+
+        if let Err(err) = added {
+            error!(guild_id = ?member_add.guild_id, ?role_id, ?err, "Failed to add an autorole");
+            result = Err(err.into());
+        }
+    }
+
+    result
+}
+
+/// Builds the embed the welcome message is sent in, `None` if the message doesn't fit into
+/// an embed so it's sent as plain content instead.
+fn welcome_embed(
+    embed: &WelcomerEmbed,
+    template: &str,
+    values: &BTreeMap<String, String>,
+    user: &User,
+) -> Option<Embed> {
+    let mut builder = EmbedBuilder::new()
+        .color(embed.color)
+        .description(tags::parse_simple_tags(template.to_owned(), values.clone()))
+        .thumbnail(ImageSource::url(util::avatar_url(user)).ok()?);
+
+    if let Some(title) = &embed.title {
+        builder = builder.title(tags::parse_simple_tags(title.clone(), values.clone()));
+    }
+
+    builder.validate().ok().map(EmbedBuilder::build)
+}
+
 #[instrument]
 pub async fn on_member_add(context: &Arc<Context>, member_add: WelcomerMemberAdd) -> Result<()> {
-    let guild_config = GuildConfig::get_guild(
-        context,
-        member_add.guild_id,
-        Some(
-            FindOneOptions::builder()
-                .projection(doc! { "welcomer": 1 })
-                .build(),
-        ),
-    )
-    .await?
-    .unwrap();
+    let guild_config = GuildConfig::get_field(context, member_add.guild_id, "welcomer")
+        .await?
+        .unwrap();
 
     if let Some(welcomer) = guild_config.welcomer {
+        if !welcomer.autorole.is_empty() {
+            // a missing permission shouldn't stop the welcome message from being sent
+            if let Err(err) = assign_autoroles(context, &member_add, &welcomer).await {
+                error!(?err, "Failed to assign the autoroles");
+            }
+        }
+
         let guild = match context.get_cache().guild(member_add.guild_id) {
             Some(g) => g,
             None => {
@@ -50,24 +200,127 @@ pub async fn on_member_add(context: &Arc<Context>, member_add: WelcomerMemberAdd
 
         drop(guild);
 
-        if welcomer.channel_id.is_some() && welcomer.message.is_some() {
-            let values = BTreeMap::from([
+        let is_rejoin =
+            SeenMember::record_join(context, member_add.guild_id, member_add.user.id).await?;
+        let template = welcomer
+            .pick_message(&mut rand::thread_rng(), is_rejoin)
+            .map(str::to_owned);
+
+        if let (Some(channel_id), Some(template)) = (welcomer.channel_id, template) {
+            let mut values = BTreeMap::from([
                 ("server_name".to_owned(), guild_name),
-                ("user_id".to_owned(), member_add.user.id.to_string()),
-                ("user_name".to_owned(), member_add.user.name),
-                (
-                    "user_discrim".to_owned(),
-                    member_add.user.discriminator.to_string(),
-                ),
+                ("is_first_join".to_owned(), (!is_rejoin).to_string()),
             ]);
+            tags::insert_user_values(&mut values, &member_add.user);
 
-            context
-                .get_http()
-                .create_message(welcomer.channel_id.unwrap())
-                .content(&tags::parse_simple_tags(welcomer.message.unwrap(), values))?
-                .await?;
+            let embed = welcomer
+                .embed
+                .as_ref()
+                .and_then(|embed| welcome_embed(embed, &template, &values, &member_add.user));
+
+            let request = context.get_http().create_message(channel_id);
+            match embed {
+                Some(embed) => request.embeds(&[embed])?.await?,
+                None => {
+                    request
+                        .content(&tags::parse_simple_tags(template, values))?
+                        .await?
+                }
+            };
         }
     }
 
     Ok(())
 }
+
+#[instrument]
+pub async fn on_member_remove(context: &Arc<Context>, member_remove: &MemberRemove) -> Result<()> {
+    let guild_config = GuildConfig::get_field(context, member_remove.guild_id, "welcomer")
+        .await?
+        .unwrap();
+
+    let (channel_id, template) = match guild_config.welcomer {
+        Some(WelcomerConfig {
+            leave_channel_id: Some(channel_id),
+            leave_message: Some(template),
+            ..
+        }) => (channel_id, template),
+        _ => return Ok(()),
+    };
+
+    let guild_name = match context.get_cache().guild(member_remove.guild_id) {
+        Some(guild) => guild.name().to_owned(),
+        None => {
+            error!("Tried to get guild by guild_id from cache and failed");
+            return Err(Error::msg("The guild is not in cache for some reason"));
+        }
+    };
+
+    // the user is usually gone from the cache by now unless they share another guild with
+    // the bot, the one in the event is complete enough for the tags
+    let user = context
+        .get_cache()
+        .user(member_remove.user.id)
+        .map(|user| user.value().clone())
+        .unwrap_or_else(|| member_remove.user.clone());
+
+    let mut values = BTreeMap::from([("server_name".to_owned(), guild_name)]);
+    tags::insert_user_values(&mut values, &user);
+
+    context
+        .get_http()
+        .create_message(channel_id)
+        .content(&tags::parse_simple_tags(template, values))?
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(id: u64, position: i64, managed: bool) -> Role {
+        serde_json::from_value(serde_json::json!({
+            "id": id.to_string(),
+            "name": "role",
+            "color": 0,
+            "hoist": false,
+            "managed": managed,
+            "mentionable": false,
+            "permissions": "0",
+            "position": position,
+            "flags": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn only_missing_assignable_autoroles_are_added() {
+        let roles = [role(1, 1, false), role(2, 2, false), role(3, 3, false)];
+        let autorole = [Id::new(1), Id::new(2), Id::new(3)];
+
+        // the member has role 1 already, the bot's highest role is at 3
+        let (to_add, skipped) = autoroles_to_add(&[Id::new(1)], &autorole, &roles, 3);
+        assert_eq!(to_add, vec![Id::new(2)]);
+        assert_eq!(skipped, vec![Id::new(3)]);
+    }
+
+    #[test]
+    fn managed_and_unknown_roles_are_skipped() {
+        let roles = [role(1, 1, true)];
+        let autorole = [Id::new(1), Id::new(2)];
+
+        let (to_add, skipped) = autoroles_to_add(&[], &autorole, &roles, 5);
+        assert!(to_add.is_empty());
+        assert_eq!(skipped, vec![Id::new(1), Id::new(2)]);
+    }
+
+    #[test]
+    fn duplicate_autoroles_are_added_once() {
+        let roles = [role(1, 1, false)];
+
+        let (to_add, _) = autoroles_to_add(&[], &[Id::new(1), Id::new(1)], &roles, 5);
+        assert_eq!(to_add, vec![Id::new(1)]);
+    }
+}
@@ -1,15 +1,18 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::TryStreamExt;
 use mongodb::{
-    bson::{doc, Document},
-    options::{FindOneOptions, UpdateOptions},
+    bson::{doc, to_bson, Document},
+    options::{FindOneOptions, FindOptions, ReplaceOptions, UpdateOptions},
 };
+use rand::{seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
 use twilight_model::{
     guild::audit_log::AuditLogEventType,
     id::{
-        marker::{ChannelMarker, GuildMarker},
+        marker::{ChannelMarker, GuildMarker, RoleMarker, UserMarker},
         Id,
     },
 };
@@ -28,6 +31,8 @@ pub struct GuildConfig {
     pub welcomer: Option<WelcomerConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub anti_abuse: Option<AntiAbuseConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warnings: Option<WarningsConfig>,
 }
 
 impl GuildConfig {
@@ -47,6 +52,7 @@ impl GuildConfig {
             id: guild_id,
             welcomer: None,
             anti_abuse: None,
+            warnings: None,
         };
 
         if guild_cfg.is_none() {
@@ -57,6 +63,24 @@ impl GuildConfig {
         Ok(guild_cfg)
     }
 
+    /// Gets the guild config with only the given top level field (e.g. `"welcomer"`) fetched.
+    pub async fn get_field(
+        ctx: &Arc<Context>,
+        guild_id: Id<GuildMarker>,
+        field: &str,
+    ) -> Result<Option<GuildConfig>> {
+        Self::get_guild(
+            ctx,
+            guild_id,
+            Some(
+                FindOneOptions::builder()
+                    .projection(doc! { field: 1 })
+                    .build(),
+            ),
+        )
+        .await
+    }
+
     pub async fn update_data_by_id_upsert(
         ctx: &Arc<Context>,
         update: Document,
@@ -158,25 +182,25 @@ impl AntiAbuseActionBuilder {
     #[inline]
     #[allow(dead_code)]
     pub fn is_ban(&self) -> bool {
-        self.flags & anti_abuse_punishment_action::BAN == 0
+        self.flags & anti_abuse_punishment_action::BAN != 0
     }
 
     #[inline]
     #[allow(dead_code)]
     pub fn is_kick(&self) -> bool {
-        self.flags & anti_abuse_punishment_action::KICK == 0
+        self.flags & anti_abuse_punishment_action::KICK != 0
     }
 
     #[inline]
     #[allow(dead_code)]
     pub fn is_timeout(&self) -> bool {
-        self.flags & anti_abuse_punishment_action::TIMEOUT == 0
+        self.flags & anti_abuse_punishment_action::TIMEOUT != 0
     }
 
     #[inline]
     #[allow(dead_code)]
     pub fn is_demote(&self) -> bool {
-        self.flags & anti_abuse_punishment_action::DEMOTE == 0
+        self.flags & anti_abuse_punishment_action::DEMOTE != 0
     }
 }
 
@@ -186,11 +210,78 @@ pub struct WelcomerConfig {
     pub channel_id: Option<Id<ChannelMarker>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejoin_message: Option<String>,
+    /// Roles given to every member that joins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub autorole: Vec<Id<RoleMarker>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leave_channel_id: Option<Id<ChannelMarker>>,
+    /// Sent to `leave_channel_id` when a member leaves, uses the same tags as the welcome
+    /// messages except `is_first_join`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leave_message: Option<String>,
+    /// Welcome messages are sent as the description of an embed if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embed: Option<WelcomerEmbed>,
+}
+
+/// How the welcome embed looks, the member's avatar is used as the thumbnail.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WelcomerEmbed {
+    /// Can use the same tags as the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub color: u32,
+}
+
+impl WelcomerConfig {
+    pub const MAX_MESSAGES: usize = 10;
+    pub const MAX_AUTOROLES: usize = 5;
+
+    /// All of the welcome message templates, the one set by `set-message` comes first.
+    pub fn templates(&self) -> Vec<&str> {
+        self.message
+            .iter()
+            .chain(self.messages.iter())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Picks one of the templates at random, `None` if there aren't any. Returning members
+    /// get the rejoin message instead if there is one.
+    pub fn pick_message<R: Rng>(&self, rng: &mut R, is_rejoin: bool) -> Option<&str> {
+        match &self.rejoin_message {
+            Some(message) if is_rejoin => Some(message),
+            _ => self.templates().choose(rng).copied(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AntiAbuseConfig {
+    #[serde(default)]
     pub watched_actions: Vec<AntiAbuseEventConfig>,
+    /// Moderators with any of these roles aren't sanctioned.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub whitelisted_roles: Vec<Id<RoleMarker>>,
+    /// Moderators that aren't sanctioned, e.g. trusted admins and other bots.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub whitelisted_users: Vec<Id<UserMarker>>,
+}
+
+impl AntiAbuseConfig {
+    pub const MAX_WHITELISTED: usize = 25;
+
+    /// Whether the actions of a moderator with the roles are exempt from sanctions.
+    pub fn is_whitelisted(&self, user_id: Id<UserMarker>, roles: &[Id<RoleMarker>]) -> bool {
+        self.whitelisted_users.contains(&user_id)
+            || roles
+                .iter()
+                .any(|role| self.whitelisted_roles.contains(role))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -199,4 +290,235 @@ pub struct AntiAbuseEventConfig {
     pub max_sanctions: i32,
     pub sanction_cooldown: i32,
     pub punishment: AntiAbuseActionBuilder,
+    /// Actions the moderator reversed within the cooldown (e.g. a ban followed by an unban
+    /// of the same user) don't count toward `max_sanctions`.
+    #[serde(default)]
+    pub ignore_reversed: bool,
+    /// How long a timeout punishment lasts in seconds, `DEFAULT_TIMEOUT_DURATION` if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_duration: Option<u32>,
+}
+
+impl AntiAbuseEventConfig {
+    pub const DEFAULT_TIMEOUT_DURATION: u32 = 60 * 60;
+    /// Discord doesn't allow timeouts longer than 28 days.
+    pub const MAX_TIMEOUT_DURATION: u32 = 28 * 24 * 60 * 60;
+
+    pub fn timeout_duration(&self) -> u32 {
+        self.timeout_duration
+            .unwrap_or(Self::DEFAULT_TIMEOUT_DURATION)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WarningsConfig {
+    /// Every time a member reaches a multiple of this many warnings they're timed out.
+    pub timeout_threshold: u32,
+    /// How long the timeout lasts in seconds.
+    pub timeout_duration: u32,
+}
+
+/// A warning a moderator gave to a member, kept until the member's warnings are cleared.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Warning {
+    pub guild_id: Id<GuildMarker>,
+    pub user_id: Id<UserMarker>,
+    pub moderator_id: Id<UserMarker>,
+    pub reason: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl Warning {
+    /// How many of the most recent warnings are listed.
+    pub const MAX_LISTED: i64 = 15;
+
+    pub async fn insert(&self, ctx: &Arc<Context>) -> Result<()> {
+        ctx.get_mongodb()
+            .database(&ctx.get_config().get_string("db_name")?)
+            .collection::<Warning>("warnings")
+            .insert_one(self, None)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn count_for(
+        ctx: &Arc<Context>,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<u64> {
+        Ok(ctx
+            .get_mongodb()
+            .database(&ctx.get_config().get_string("db_name")?)
+            .collection::<Warning>("warnings")
+            .count_documents(
+                doc! { "guild_id": to_bson(&guild_id)?, "user_id": to_bson(&user_id)? },
+                None,
+            )
+            .await?)
+    }
+
+    /// The most recent warnings of the member, newest first.
+    pub async fn list_for(
+        ctx: &Arc<Context>,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<Vec<Warning>> {
+        let cursor = ctx
+            .get_mongodb()
+            .database(&ctx.get_config().get_string("db_name")?)
+            .collection::<Warning>("warnings")
+            .find(
+                doc! { "guild_id": to_bson(&guild_id)?, "user_id": to_bson(&user_id)? },
+                FindOptions::builder()
+                    .sort(doc! { "created_at": -1 })
+                    .limit(Self::MAX_LISTED)
+                    .build(),
+            )
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Removes all the warnings of the member, returns how many there were.
+    pub async fn clear_for(
+        ctx: &Arc<Context>,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<u64> {
+        let result = ctx
+            .get_mongodb()
+            .database(&ctx.get_config().get_string("db_name")?)
+            .collection::<Warning>("warnings")
+            .delete_many(
+                doc! { "guild_id": to_bson(&guild_id)?, "user_id": to_bson(&user_id)? },
+                None,
+            )
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+/// A named script stored by a guild, it's compiled and ran on every use.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tag {
+    pub guild_id: Id<GuildMarker>,
+    pub name: String,
+    pub source: String,
+    pub author_id: Id<UserMarker>,
+}
+
+impl Tag {
+    pub async fn get(
+        ctx: &Arc<Context>,
+        guild_id: Id<GuildMarker>,
+        name: &str,
+    ) -> Result<Option<Tag>> {
+        let tags = ctx
+            .get_mongodb()
+            .database(&ctx.get_config().get_string("db_name")?)
+            .collection::<Tag>("tags");
+
+        Ok(tags
+            .find_one(doc! { "guild_id": to_bson(&guild_id)?, "name": name }, None)
+            .await?)
+    }
+
+    pub async fn upsert(&self, ctx: &Arc<Context>) -> Result<()> {
+        ctx.get_mongodb()
+            .database(&ctx.get_config().get_string("db_name")?)
+            .collection::<Tag>("tags")
+            .replace_one(
+                doc! { "guild_id": to_bson(&self.guild_id)?, "name": &self.name },
+                self,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(ctx: &Arc<Context>, guild_id: Id<GuildMarker>, name: &str) -> Result<bool> {
+        let result = ctx
+            .get_mongodb()
+            .database(&ctx.get_config().get_string("db_name")?)
+            .collection::<Tag>("tags")
+            .delete_one(doc! { "guild_id": to_bson(&guild_id)?, "name": name }, None)
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+/// A member that has joined a guild before, used to tell first joins from rejoins.
+/// The document expires after `SeenMember::TTL_DAYS` without a join so the collection
+/// doesn't keep every member that ever passed through a guild.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeenMember {
+    pub guild_id: Id<GuildMarker>,
+    pub user_id: Id<UserMarker>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SeenMember {
+    pub const TTL_DAYS: i64 = 180;
+
+    /// Records a join and returns whether the member has been seen in the guild before.
+    pub async fn record_join(
+        ctx: &Arc<Context>,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<bool> {
+        let expires_at = Utc::now() + Duration::days(Self::TTL_DAYS);
+        let result = ctx
+            .get_mongodb()
+            .database(&ctx.get_config().get_string("db_name")?)
+            .collection::<SeenMember>("seen_members")
+            .update_one(
+                doc! { "guild_id": to_bson(&guild_id)?, "user_id": to_bson(&user_id)? },
+                doc! { "$set": { "expires_at": bson::DateTime::from_chrono(expires_at) } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+
+        // an upsert only happens when there was no document for the member yet
+        Ok(result.upserted_id.is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn whitelist(users: &[u64], roles: &[u64]) -> AntiAbuseConfig {
+        AntiAbuseConfig {
+            watched_actions: Vec::new(),
+            whitelisted_roles: roles.iter().map(|id| Id::new(*id)).collect(),
+            whitelisted_users: users.iter().map(|id| Id::new(*id)).collect(),
+        }
+    }
+
+    #[test]
+    fn whitelisted_users_and_roles_are_exempt() {
+        let config = whitelist(&[1, 2], &[10, 20]);
+
+        // by user, with or without roles
+        assert!(config.is_whitelisted(Id::new(2), &[]));
+        assert!(config.is_whitelisted(Id::new(1), &[Id::new(30)]));
+
+        // by any one of the roles
+        assert!(config.is_whitelisted(Id::new(3), &[Id::new(30), Id::new(20)]));
+    }
+
+    #[test]
+    fn everyone_else_is_sanctioned() {
+        let config = whitelist(&[1], &[10]);
+        assert!(!config.is_whitelisted(Id::new(3), &[]));
+        assert!(!config.is_whitelisted(Id::new(3), &[Id::new(11), Id::new(30)]));
+
+        // role and user ids don't mix even when the numbers match
+        assert!(!config.is_whitelisted(Id::new(10), &[Id::new(1)]));
+
+        let empty = whitelist(&[], &[]);
+        assert!(!empty.is_whitelisted(Id::new(1), &[Id::new(10)]));
+    }
 }
@@ -0,0 +1,238 @@
+use std::{rc::Rc, sync::Arc};
+
+use custos_script::{
+    bytecode::{BuiltInMethod, Constant},
+    stdlib,
+    vm::{CancellationHandle, VirtualMachine},
+};
+use twilight_model::id::{marker::ChannelMarker, Id};
+
+use crate::sync_http::SyncHttpClient;
+
+/// The maximum amount of instructions a script may execute before it's halted.
+pub const INSTRUCTION_LIMIT: usize = 1_000_000;
+
+/// The maximum length of a script in characters, longer scripts aren't even tokenized,
+/// see `custos_script::compile_with_max_length`.
+pub const MAX_SOURCE_LENGTH: usize = 4000;
+
+/// How eval is used with the configured prefix, sent back when the message has no code block.
+pub fn eval_usage(prefix: &str) -> String {
+    format!(
+        "Usage: `{}[args...]` followed by the script in a code block.",
+        prefix
+    )
+}
+
+/// Splits an eval message (without the prefix) into the arguments before the code block
+/// and the code inside it. A language hint on the opening fence (e.g. ` ```rust `) is skipped.
+/// `None` if there is no complete code block or it's empty.
+pub fn split_code_block(content: &str) -> Option<(Vec<String>, &str)> {
+    let (args, rest) = content.split_once("```")?;
+    let (mut code, _) = rest.rsplit_once("```")?;
+
+    if let Some((hint, after)) = code.split_once('\n') {
+        if !hint.is_empty() && hint.chars().all(|c| c.is_ascii_alphanumeric()) {
+            code = after;
+        }
+    }
+
+    let code = code.trim();
+    if code.is_empty() {
+        return None;
+    }
+
+    let args = args.split_whitespace().map(String::from).collect();
+    Some((args, code))
+}
+
+/// Splits a message that runs a tag into the lowercased name of the tag and its arguments.
+/// `None` if the message doesn't start with the prefix or names no tag, an empty prefix
+/// turns the trigger off.
+pub fn split_tag_call(content: &str, prefix: &str) -> Option<(String, Vec<String>)> {
+    if prefix.is_empty() {
+        return None;
+    }
+
+    let mut words = content.strip_prefix(prefix)?.split_whitespace();
+    let name = words.next()?.to_lowercase();
+    Some((name, words.map(String::from).collect()))
+}
+
+/// Compiles the source of a tag before it's stored so a broken tag is rejected right away,
+/// the error is shown to its author.
+pub fn check_tag(source: &str) -> Result<(), String> {
+    custos_script::compile_with_max_length(source, Some(MAX_SOURCE_LENGTH)).map(|_| ())
+}
+
+/// Runs the script with the limits every script gets, `define` adds the built-ins on top
+/// of the standard library.
+fn run_sandboxed<F>(source: &str, cancellation: CancellationHandle, define: F) -> Result<(), String>
+where
+    F: FnOnce(&mut VirtualMachine),
+{
+    custos_script::run_with_max_length(source, Some(MAX_SOURCE_LENGTH), |vm| {
+        vm.set_instruction_limit(INSTRUCTION_LIMIT);
+        vm.set_cancellation_handle(cancellation);
+        stdlib::define_stdlib(vm);
+        define(vm);
+    })
+}
+
+/// Runs the script on the rayon thread pool, errors are sent to the channel.
+pub fn spawn_script(
+    http_client: Arc<SyncHttpClient>,
+    channel_id: Id<ChannelMarker>,
+    args: Vec<String>,
+    source: String,
+    cancellation: CancellationHandle,
+) {
+    rayon::spawn(move || {
+        let result = run_sandboxed(&source, cancellation, |vm| {
+            define_built_ins(vm, Arc::clone(&http_client), channel_id, args);
+        });
+
+        if let Err(err) = result {
+            if let Err(source) = http_client.create_message(channel_id, &format!("```{}```", err)) {
+                tracing::warn!(?source, %channel_id, "Failed to send a script error");
+            }
+        }
+    });
+}
+
+fn define_built_ins(
+    vm: &mut VirtualMachine,
+    http_client: Arc<SyncHttpClient>,
+    channel_id: Id<ChannelMarker>,
+    args: Vec<String>,
+) {
+    vm.define_built_in_fn(BuiltInMethod::new("send", 0, move |args| {
+        match args.first() {
+            Some(Constant::String(message_content)) => {
+                match http_client.create_message(channel_id, message_content) {
+                    Ok(message) => Ok(Constant::String(Rc::new(message.id))),
+                    Err(err) => Err(format!("failed to send the message: {}", err)),
+                }
+            }
+            Some(value) => Err(format!(
+                "expected a string, got: {}",
+                value.get_pretty_type()
+            )),
+            None => Err("expected a message to send".to_owned()),
+        }
+    }));
+
+    stdlib::define_log(
+        vm,
+        Rc::new(move |message| tracing::info!(%channel_id, "Script log: {}", message)),
+    );
+
+    let args = Rc::new(
+        args.into_iter()
+            .map(|arg| Constant::String(Rc::new(arg)))
+            .collect::<Vec<Constant>>(),
+    );
+
+    vm.define_built_in_fn(BuiltInMethod::new("get_args", 0, move |_| {
+        Ok(Constant::Array(Rc::clone(&args)))
+    }));
+
+    vm.define_built_in_fn(BuiltInMethod::new("channel_id", 0, move |_| {
+        Ok(Constant::String(Rc::new(channel_id.to_string())))
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_block_is_required() {
+        assert_eq!(split_code_block("a b"), None);
+        assert_eq!(split_code_block("a ```send(1)"), None);
+    }
+
+    #[test]
+    fn empty_code_block_is_none() {
+        assert_eq!(split_code_block("``````"), None);
+        assert_eq!(split_code_block("a ```\n  \n```"), None);
+        // only a language hint is still empty
+        assert_eq!(split_code_block("```rust\n```"), None);
+    }
+
+    #[test]
+    fn arguments_come_before_the_code_block() {
+        assert_eq!(
+            split_code_block(" one  two ```send(1)```"),
+            Some((vec!["one".to_owned(), "two".to_owned()], "send(1)"))
+        );
+    }
+
+    #[test]
+    fn language_hint_is_skipped() {
+        assert_eq!(
+            split_code_block("```rust\nsend(1)\n```"),
+            Some((vec![], "send(1)"))
+        );
+        // a first line that isn't a single word is code
+        assert_eq!(
+            split_code_block("```var a = 1;\nsend(a)```"),
+            Some((vec![], "var a = 1;\nsend(a)"))
+        );
+    }
+
+    #[test]
+    fn tag_calls_name_the_tag_first() {
+        assert_eq!(
+            split_tag_call("!tag Greet  one two", "!tag "),
+            Some(("greet".to_owned(), vec!["one".to_owned(), "two".to_owned()]))
+        );
+        assert_eq!(
+            split_tag_call("!tag greet", "!tag "),
+            Some(("greet".to_owned(), vec![]))
+        );
+    }
+
+    #[test]
+    fn other_messages_are_not_tag_calls() {
+        assert_eq!(split_tag_call("hello", "!tag "), None);
+        assert_eq!(split_tag_call("!tag  ", "!tag "), None);
+        // an empty prefix would run a tag for every message
+        assert_eq!(split_tag_call("greet", ""), None);
+    }
+
+    #[test]
+    fn tags_that_dont_compile_are_rejected() {
+        assert!(check_tag("var a = 1;\nsend(a)").is_ok());
+        assert!(check_tag("var = 1;").is_err());
+    }
+
+    #[test]
+    fn tags_over_the_length_limit_are_rejected() {
+        let source = format!("var a = \"{}\";", "a".repeat(MAX_SOURCE_LENGTH));
+        let err = check_tag(&source).unwrap_err();
+        assert!(err.starts_with("the script is too long"), "{}", err);
+
+        let err = run_sandboxed(&source, CancellationHandle::new(), |_| {}).unwrap_err();
+        assert!(err.starts_with("the script is too long"), "{}", err);
+    }
+
+    #[test]
+    fn tags_are_stopped_by_the_instruction_limit() {
+        let err = run_sandboxed("while true {}", CancellationHandle::new(), |_| {}).unwrap_err();
+        let expected = format!("instruction limit of {} exceeded", INSTRUCTION_LIMIT);
+        assert!(err.contains(&expected), "{}", err);
+    }
+
+    #[test]
+    fn tags_get_the_built_ins_they_are_given() {
+        let source = "if get_args()[0] != \"one\" {\n    missing()\n}";
+        let result = run_sandboxed(source, CancellationHandle::new(), |vm| {
+            vm.define_built_in_fn(BuiltInMethod::new("get_args", 0, |_| {
+                let args = vec![Constant::String(Rc::new("one".to_owned()))];
+                Ok(Constant::Array(Rc::new(args)))
+            }));
+        });
+        assert_eq!(result, Ok(()));
+    }
+}
@@ -1,9 +1,40 @@
+use std::{
+    fmt,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use twilight_model::id::{marker::ChannelMarker, Id};
 
-#[derive(Debug, Clone)]
+/// The bot token, cheap to clone and redacted from `Debug` output so it can't leak
+/// through logs.
+#[derive(Clone)]
+pub struct Token(Arc<str>);
+
+impl Token {
+    pub fn new(token: impl Into<Arc<str>>) -> Self {
+        Self(token.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token(<redacted>)")
+    }
+}
+
+/// A blocking client for the script threads, it's shared through an `Arc` so the
+/// connection pool is reused between scripts.
+#[derive(Debug)]
 pub struct SyncHttpClient {
-    token: String,
+    token: Token,
+    // created on first use from a script thread so building it never blocks the async runtime
+    client: OnceLock<reqwest::blocking::Client>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -12,19 +43,24 @@ pub struct MessageCreateResp {
 }
 
 impl SyncHttpClient {
-    pub fn new(token: &str) -> SyncHttpClient {
+    pub fn new(token: Token) -> SyncHttpClient {
         SyncHttpClient {
-            token: token.to_owned(),
+            token,
+            client: OnceLock::new(),
         }
     }
 
+    fn client(&self) -> &reqwest::blocking::Client {
+        self.client.get_or_init(reqwest::blocking::Client::new)
+    }
+
     pub fn create_message(
         &self,
         channel_id: Id<ChannelMarker>,
         content: &str,
-    ) -> MessageCreateResp {
-        let client = reqwest::blocking::Client::new();
-        let response = client
+    ) -> Result<MessageCreateResp> {
+        let response = self
+            .client()
             .post(format!(
                 "https://discord.com/api/v10/channels/{channel_id}/messages"
             ))
@@ -34,10 +70,42 @@ impl SyncHttpClient {
                 })
                 .to_string(),
             )
-            .header("Authorization", format!("Bot {}", self.token))
+            .header("Authorization", format!("Bot {}", self.token.expose()))
             .header("Content-Type", "application/json")
-            .send()
-            .unwrap();
-        response.json().unwrap()
+            .send()?
+            // Discord answers rate limits and missing permissions with an error body
+            // that isn't a message
+            .error_for_status()?;
+        Ok(response.json()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sharing_the_client_shares_the_token() {
+        let token = Token::new("secret");
+        let http_sync = Arc::new(SyncHttpClient::new(token.clone()));
+        let token_count = Arc::strong_count(&token.0);
+
+        // what every eval does with `Context::get_http_sync`
+        let for_script = Arc::clone(&http_sync);
+        assert_eq!(Arc::strong_count(&token.0), token_count);
+        assert!(Arc::ptr_eq(&for_script.token.0, &token.0));
+
+        // the reqwest client is built once and reused by every holder
+        assert!(std::ptr::eq(for_script.client(), http_sync.client()));
+        assert_eq!(format!("{:?}", for_script.token), "Token(<redacted>)");
+    }
+
+    #[test]
+    fn debug_output_leaves_out_the_token() {
+        let http_sync = SyncHttpClient::new(Token::new("secret"));
+        let debug = format!("{:?}", http_sync);
+
+        assert!(!debug.contains("secret"), "{}", debug);
+        assert!(debug.contains("Token(<redacted>)"), "{}", debug);
     }
 }
@@ -1,28 +1,178 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, iter::Peekable, str::Chars};
 
+use twilight_model::user::User;
+
+/// Replaces the `{name}` tags with their values, unknown tags are left out unless they have
+/// a default like `{name|default}`. `{{` and `}}` are a literal `{` and `}`.
+///
+/// `{if name: text}` renders the text, which can have tags of its own, only if the value of
+/// `name` isn't empty. A tag without its closing `}` is kept as it was written.
 pub fn parse_simple_tags(message: String, values: BTreeMap<String, String>) -> String {
-    let mut chars = message.chars();
     let mut result = String::with_capacity(message.len());
+    render_tags(&message, &values, &mut result);
+    result
+}
+
+fn render_tags(message: &str, values: &BTreeMap<String, String>, result: &mut String) {
+    let mut chars = message.chars().peekable();
 
     while let Some(symbol) = chars.next() {
-        if symbol == '{' {
-            let mut name = String::new();
-            for sym in chars.by_ref() {
-                if sym == '}' {
-                    break;
-                } else {
-                    name.push(sym);
+        if symbol == '{' && chars.next_if_eq(&'{').is_some() {
+            result.push('{');
+        } else if symbol == '}' && chars.next_if_eq(&'}').is_some() {
+            result.push('}');
+        } else if symbol == '{' {
+            let tag = match take_tag(&mut chars) {
+                Ok(tag) => tag,
+                Err(unterminated) => {
+                    result.push('{');
+                    result.push_str(&unterminated);
+                    continue;
+                }
+            };
+
+            if let Some((name, text)) = tag.strip_prefix("if ").and_then(|c| c.split_once(':')) {
+                if values.get(name.trim()).map_or(false, |val| !val.is_empty()) {
+                    render_tags(text, values, result);
                 }
+                continue;
             }
 
-            let value = values.get(&name);
-            if let Some(val) = value {
+            let (name, default) = match tag.split_once('|') {
+                Some((name, default)) => (name, Some(default)),
+                None => (tag.as_str(), None),
+            };
+
+            if let Some(val) = values.get(name).map(String::as_str).or(default) {
                 result.push_str(val);
             }
         } else {
             result.push(symbol);
         }
     }
+}
 
-    result
+/// Takes the inside of a tag whose `{` was just read, up to the matching `}`. The tags
+/// nested in it are kept as they are and so are the escaped braces, a `}}` right in the text
+/// of an `if` is an escaped brace and not the end. The text read so far is the error if
+/// there's no matching `}`.
+fn take_tag(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut tag = String::new();
+    let mut depth = 0;
+
+    while let Some(sym) = chars.next() {
+        if sym == '{' && chars.next_if_eq(&'{').is_some() {
+            tag.push_str("{{");
+        } else if sym == '}'
+            && depth == 0
+            && tag.starts_with("if ")
+            && chars.next_if_eq(&'}').is_some()
+        {
+            tag.push_str("}}");
+        } else if sym == '{' {
+            depth += 1;
+            tag.push(sym);
+        } else if sym == '}' && depth == 0 {
+            return Ok(tag);
+        } else {
+            if sym == '}' {
+                depth -= 1;
+            }
+            tag.push(sym);
+        }
+    }
+
+    Err(tag)
+}
+
+/// Adds the `user_*` values of a user, accounts migrated to the new username system
+/// have a discriminator of 0 which is left out of `user_discrim` and `user_tag`.
+pub fn insert_user_values(values: &mut BTreeMap<String, String>, user: &User) {
+    let (discrim, tag) = if user.discriminator == 0 {
+        (String::new(), user.name.clone())
+    } else {
+        let discrim = user.discriminator().to_string();
+        let tag = format!("{}#{}", user.name, discrim);
+        (discrim, tag)
+    };
+
+    values.insert("user_id".to_owned(), user.id.to_string());
+    values.insert("user_name".to_owned(), user.name.clone());
+    values.insert(
+        "user_global_name".to_owned(),
+        user.global_name
+            .clone()
+            .unwrap_or_else(|| user.name.clone()),
+    );
+    values.insert("user_discrim".to_owned(), discrim);
+    values.insert("user_tag".to_owned(), tag);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(message: &str, values: &[(&str, &str)]) -> String {
+        let values = values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        parse_simple_tags(message.to_owned(), values)
+    }
+
+    #[test]
+    fn missing_tags_use_their_default() {
+        assert_eq!(render("hi {name|there}", &[]), "hi there");
+        assert_eq!(render("hi {name}!", &[]), "hi !");
+    }
+
+    #[test]
+    fn present_tags_ignore_their_default() {
+        let values = [("name", "bob")];
+        assert_eq!(render("hi {name|there}", &values), "hi bob");
+        assert_eq!(render("hi {name}", &values), "hi bob");
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() {
+        assert_eq!(render("{{name}}", &[("name", "bob")]), "{name}");
+        assert_eq!(render("{{{name}}}", &[("name", "bob")]), "{bob}");
+    }
+
+    #[test]
+    fn conditions_render_when_the_value_is_present() {
+        let values = [("name", "bob")];
+        assert_eq!(
+            render("hi{if name: there {name}}!", &values),
+            "hi there bob!"
+        );
+    }
+
+    #[test]
+    fn conditions_are_skipped_when_the_value_is_absent_or_empty() {
+        assert_eq!(render("hi{if name: there {name}}!", &[]), "hi!");
+        assert_eq!(render("hi{if name: there}!", &[("name", "")]), "hi!");
+    }
+
+    #[test]
+    fn conditions_nest() {
+        let message = "{if a:A{if b:B}}";
+        assert_eq!(render(message, &[("a", "1"), ("b", "1")]), "AB");
+        assert_eq!(render(message, &[("a", "1")]), "A");
+        assert_eq!(render(message, &[("b", "1")]), "");
+    }
+
+    #[test]
+    fn conditions_keep_escaped_braces() {
+        assert_eq!(render("{if a: {{x}}}", &[("a", "1")]), " {x}");
+    }
+
+    #[test]
+    fn unterminated_tags_are_kept() {
+        assert_eq!(
+            render("hi {if name: there", &[("name", "bob")]),
+            "hi {if name: there"
+        );
+        assert_eq!(render("hi {name", &[("name", "bob")]), "hi {name");
+    }
 }
@@ -1,9 +1,20 @@
 use anyhow::Result;
 use twilight_http::client::InteractionClient;
 use twilight_model::{
+    channel::message::Embed,
     gateway::payload::incoming::InteractionCreate,
     http::interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType},
+    user::User,
 };
+use twilight_util::builder::{
+    embed::{EmbedBuilder, EmbedFooterBuilder},
+    InteractionResponseDataBuilder,
+};
+
+pub const EMBED_COLOR: u32 = 0x5865F2;
+
+/// How many characters Discord allows in an embed description.
+pub const EMBED_DESCRIPTION_LENGTH: usize = 4096;
 
 pub async fn send(
     interactions: &InteractionClient<'_>,
@@ -23,3 +34,94 @@ pub async fn send(
         .await?;
     Ok(())
 }
+
+/// Sends a followup message, used to answer after the response was deferred or to send
+/// more than one message. Takes the same data as `send`, only the fields a message can have
+/// are used.
+pub async fn followup(
+    interactions: &InteractionClient<'_>,
+    inter: &InteractionCreate,
+    data: InteractionResponseData,
+) -> Result<()> {
+    let mut request = interactions.create_followup(&inter.token);
+
+    if let Some(content) = &data.content {
+        request = request.content(content)?;
+    }
+    if let Some(embeds) = &data.embeds {
+        request = request.embeds(embeds)?;
+    }
+    if let Some(components) = &data.components {
+        request = request.components(components)?;
+    }
+    if let Some(flags) = data.flags {
+        request = request.flags(flags);
+    }
+    if let Some(tts) = data.tts {
+        request = request.tts(tts);
+    }
+
+    request
+        .allowed_mentions(data.allowed_mentions.as_ref())
+        .await?;
+    Ok(())
+}
+
+fn embed_footer_text() -> String {
+    format!("Custos v{}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Builds an embed with the Custos color and version footer, returns `None` if the
+/// description doesn't fit into an embed.
+pub fn embed(description: impl Into<String>) -> Option<Embed> {
+    EmbedBuilder::new()
+        .color(EMBED_COLOR)
+        .description(description)
+        .footer(EmbedFooterBuilder::new(embed_footer_text()))
+        .validate()
+        .ok()
+        .map(EmbedBuilder::build)
+}
+
+/// Response data with the message inside of a branded embed, falls back to plain
+/// content when the embed can't be built.
+pub fn embed_response(message: impl Into<String>) -> InteractionResponseData {
+    let message = message.into();
+    match embed(message.clone()) {
+        Some(embed) => InteractionResponseDataBuilder::new()
+            .embeds([embed])
+            .build(),
+        None => InteractionResponseDataBuilder::new()
+            .content(message)
+            .build(),
+    }
+}
+
+/// The url of the user's avatar, or of the default avatar Discord shows if they have none.
+pub fn avatar_url(user: &User) -> String {
+    match &user.avatar {
+        Some(hash) => format!(
+            "https://cdn.discordapp.com/avatars/{}/{}.png",
+            user.id, hash
+        ),
+        None => {
+            // accounts on the new username system have a discriminator of 0
+            let index = if user.discriminator == 0 {
+                (user.id.get() >> 22) % 6
+            } else {
+                u64::from(user.discriminator) % 5
+            };
+            format!("https://cdn.discordapp.com/embed/avatars/{}.png", index)
+        }
+    }
+}
+
+/// Parses a hex color like `#5865F2` or `5865F2`, `None` if it isn't one.
+pub fn parse_hex_color(value: &str) -> Option<u32> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u32::from_str_radix(hex, 16).ok()
+}
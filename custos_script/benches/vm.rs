@@ -0,0 +1,108 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use custos_script::stdlib;
+
+const FIBONACCI: &str = "
+func fib(n) {
+    if n < 2 {
+        ret n;
+    }
+    ret fib(n - 1) + fib(n - 2);
+}
+
+fib(15);
+";
+
+const ARRAY_BUILDING: &str = "
+func build(n, acc) {
+    if n == 0 {
+        ret acc;
+    }
+    ret build(n - 1, [acc, n, n * 2]);
+}
+
+build(500, [0]);
+";
+
+const STRING_CONCAT: &str = "
+func concat(n, acc) {
+    if n == 0 {
+        ret acc;
+    }
+    ret concat(n - 1, acc + \"ab\" + n);
+}
+
+concat(500, \"\");
+";
+
+const STRING_INDEXING: &str = "
+var s = \"\";
+var i = 0;
+while i < 500 {
+    s = s + \"é\";
+    i = i + 1;
+}
+
+var count = 0;
+i = 0;
+while i < len(s) {
+    if s[i] == \"é\" {
+        count = count + 1;
+    }
+    i = i + 1;
+}
+";
+
+const LOCAL_STRING_READS: &str = "
+func read {
+    var s = \"\";
+    var i = 0;
+    while i < 1000 {
+        s = s + \"abcdefghij\";
+        i = i + 1;
+    }
+
+    var last = none;
+    i = 0;
+    while i < 5000 {
+        last = s;
+        i = i + 1;
+    }
+    ret last;
+}
+
+read();
+";
+
+fn run(source: &str) {
+    custos_script::run(black_box(source), stdlib::define_stdlib).unwrap();
+}
+
+fn fibonacci(c: &mut Criterion) {
+    c.bench_function("recursive fibonacci", |b| b.iter(|| run(FIBONACCI)));
+}
+
+fn array_building(c: &mut Criterion) {
+    c.bench_function("array building", |b| b.iter(|| run(ARRAY_BUILDING)));
+}
+
+fn string_concat(c: &mut Criterion) {
+    c.bench_function("string concatenation", |b| b.iter(|| run(STRING_CONCAT)));
+}
+
+fn string_indexing(c: &mut Criterion) {
+    c.bench_function("string indexing", |b| b.iter(|| run(STRING_INDEXING)));
+}
+
+fn local_string_reads(c: &mut Criterion) {
+    c.bench_function("local string reads", |b| b.iter(|| run(LOCAL_STRING_READS)));
+}
+
+criterion_group!(
+    benches,
+    fibonacci,
+    array_building,
+    string_concat,
+    string_indexing,
+    local_string_reads
+);
+criterion_main!(benches);
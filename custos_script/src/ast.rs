@@ -25,7 +25,9 @@ pub enum Node {
     Unary(Unary),
     Logical(Logical),
     Assign(Assign),
+    IndexAssign(IndexAssign),
     For(For),
+    While(While),
     If(If),
     // Use(Use),
     Ret(Ret),
@@ -41,6 +43,7 @@ pub enum BinaryOp {
     Sub,
     Mul,
     Div,
+    Mod,
     Greater,
     GreaterEq,
     Less,
@@ -56,6 +59,7 @@ impl std::fmt::Display for BinaryOp {
             Self::Sub => write!(f, "-"),
             Self::Mul => write!(f, "*"),
             Self::Div => write!(f, "/"),
+            Self::Mod => write!(f, "%"),
             Self::Greater => write!(f, ">"),
             Self::GreaterEq => write!(f, ">="),
             Self::Less => write!(f, "<"),
@@ -103,11 +107,22 @@ pub struct Binary {
     pub lhs: Box<Node>,
     pub rhs: Box<Node>,
     pub op: BinaryOp,
+    pub op_loc: (usize, usize),
 }
 
 impl Binary {
-    pub fn new_node(lhs: Box<Node>, rhs: Box<Node>, op: BinaryOp) -> Box<Node> {
-        Box::new(Node::Binary(Binary { lhs, rhs, op }))
+    pub fn new_node(
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+        op: BinaryOp,
+        op_loc: (usize, usize),
+    ) -> Box<Node> {
+        Box::new(Node::Binary(Binary {
+            lhs,
+            rhs,
+            op,
+            op_loc,
+        }))
     }
 }
 
@@ -139,11 +154,12 @@ impl Function {
 pub struct Call {
     pub args: Vec<Node>,
     pub callee: Box<Node>,
+    pub loc: (usize, usize),
 }
 
 impl Call {
-    pub fn new_node(args: Vec<Node>, callee: Box<Node>) -> Box<Node> {
-        Box::new(Node::Call(Call { args, callee }))
+    pub fn new_node(args: Vec<Node>, callee: Box<Node>, loc: (usize, usize)) -> Box<Node> {
+        Box::new(Node::Call(Call { args, callee, loc }))
     }
 }
 
@@ -151,11 +167,12 @@ impl Call {
 pub struct Subscript {
     pub index: Box<Node>,
     pub value: Box<Node>,
+    pub loc: (usize, usize),
 }
 
 impl Subscript {
-    pub fn new_node(index: Box<Node>, value: Box<Node>) -> Box<Node> {
-        Box::new(Node::Subscript(Subscript { index, value }))
+    pub fn new_node(index: Box<Node>, value: Box<Node>, loc: (usize, usize)) -> Box<Node> {
+        Box::new(Node::Subscript(Subscript { index, value, loc }))
     }
 }
 
@@ -205,11 +222,22 @@ pub struct Logical {
     pub lhs: Box<Node>,
     pub rhs: Box<Node>,
     pub op: LogicalOp,
+    pub op_loc: (usize, usize),
 }
 
 impl Logical {
-    pub fn new_node(lhs: Box<Node>, rhs: Box<Node>, op: LogicalOp) -> Box<Node> {
-        Box::new(Node::Logical(Logical { lhs, rhs, op }))
+    pub fn new_node(
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+        op: LogicalOp,
+        op_loc: (usize, usize),
+    ) -> Box<Node> {
+        Box::new(Node::Logical(Logical {
+            lhs,
+            rhs,
+            op,
+            op_loc,
+        }))
     }
 }
 
@@ -230,6 +258,31 @@ impl Assign {
     }
 }
 
+/// `name[index] = value`, only elements of variables can be assigned.
+#[derive(Debug, Clone)]
+pub struct IndexAssign {
+    pub name: String,
+    pub name_loc: (usize, usize),
+    pub index: Box<Node>,
+    pub value: Box<Node>,
+}
+
+impl IndexAssign {
+    pub fn new_node(
+        name: String,
+        name_loc: (usize, usize),
+        index: Box<Node>,
+        value: Box<Node>,
+    ) -> Box<Node> {
+        Box::new(Node::IndexAssign(IndexAssign {
+            name,
+            name_loc,
+            index,
+            value,
+        }))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct For {
     pub name: String,
@@ -254,11 +307,29 @@ impl For {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct While {
+    pub condition: Box<Node>,
+    pub body: Box<Node>,
+    pub loc: (usize, usize),
+}
+
+impl While {
+    pub fn new_node(condition: Box<Node>, body: Box<Node>, loc: (usize, usize)) -> Box<Node> {
+        Box::new(Node::While(While {
+            condition,
+            body,
+            loc,
+        }))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct If {
     pub condition: Box<Node>,
     pub then_block: Box<Node>,
     pub else_block: Option<Box<Node>>,
+    pub loc: (usize, usize),
 }
 
 impl If {
@@ -266,11 +337,13 @@ impl If {
         condition: Box<Node>,
         then_block: Box<Node>,
         else_block: Option<Box<Node>>,
+        loc: (usize, usize),
     ) -> Box<Node> {
         Box::new(Node::If(If {
             condition,
             then_block,
             else_block,
+            loc,
         }))
     }
 }
@@ -4,13 +4,17 @@ use std::{collections::VecDeque, rc::Rc};
 pub enum Constant {
     Number(f64),
     Bool(bool),
-    String(String),
-    Function(Function),
+    String(Rc<String>),
+    /// Shared so calls don't copy the chunk, two functions are equal if they're the same one.
+    Function(Rc<Function>),
     BuiltInMethod(BuiltInMethod),
     Array(Rc<Vec<Constant>>),
     None,
 }
 
+/// How many decimals numbers are formatted with, see `Constant::format_number`.
+pub const NUMBER_PRECISION: usize = 10;
+
 impl Constant {
     pub fn get_pretty_type(&self) -> String {
         match self {
@@ -24,6 +28,46 @@ impl Constant {
         }
     }
 
+    /// Length of a string or an array. Strings are measured in chars (unicode scalar values)
+    /// to stay consistent with indexing, which is also char based.
+    pub fn get_len(&self) -> Option<usize> {
+        match self {
+            Constant::String(s) => Some(s.chars().count()),
+            Constant::Array(arr) => Some(arr.len()),
+            _ => None,
+        }
+    }
+
+    /// Renders a whole, non-negative number as a plain integer (`1234` rather than `1234.5` or
+    /// `1.2e3`), the form Discord expects for IDs. `None` for any other value.
+    /// Numbers above 2^53 have already lost precision and can't represent every ID.
+    pub fn to_int_string(&self) -> Option<String> {
+        match self {
+            Constant::Number(n) if n.is_finite() && *n >= 0.0 && n.fract() == 0.0 => {
+                Some(format!("{:.0}", n))
+            }
+            _ => None,
+        }
+    }
+
+    /// Formats a number with at most `NUMBER_PRECISION` decimals and without trailing zeros,
+    /// so `3.0` is `3` and float noise like `2.9999999999996` rounds to `3`.
+    pub fn format_number(n: f64) -> String {
+        if !n.is_finite() {
+            return n.to_string();
+        }
+
+        let formatted = format!("{:.*}", NUMBER_PRECISION, n);
+        let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+
+        // a negative number that rounds to zero would be "-0"
+        if trimmed == "-0" {
+            "0".to_owned()
+        } else {
+            trimmed.to_owned()
+        }
+    }
+
     pub fn is_falsey(&self) -> bool {
         match &self {
             Self::Bool(value) => !value,
@@ -35,14 +79,36 @@ impl Constant {
     pub fn get_string(&self) -> String {
         match self {
             Constant::Bool(b) => b.to_string(),
-            Constant::Number(n) => n.to_string(),
-            Constant::String(s) => s.to_owned(),
+            Constant::Number(n) => Constant::format_number(*n),
+            Constant::String(s) => s.to_string(),
             Constant::None => "none".to_string(),
             Constant::Function(f) => format!("fn <'{}' {}>", f.name, f.arity),
             Constant::BuiltInMethod(f) => format!("fn <built-in '{}' {}>", f.name, f.arity),
-            Constant::Array(arr) => format!("array <{}>", arr.len()),
+            Constant::Array(_) => self.to_string(),
+        }
+    }
+}
+
+/// How deep nested arrays are rendered, anything deeper is shown as `[...]`.
+const MAX_DISPLAY_DEPTH: usize = 8;
+
+fn fmt_array(arr: &[Constant], depth: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if depth >= MAX_DISPLAY_DEPTH {
+        return write!(f, "[...]");
+    }
+
+    write!(f, "[")?;
+    for (i, element) in arr.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+
+        match element {
+            Constant::Array(nested) => fmt_array(nested, depth + 1, f)?,
+            _ => write!(f, "{}", element)?,
         }
     }
+    write!(f, "]")
 }
 
 impl std::fmt::Display for Constant {
@@ -50,13 +116,13 @@ impl std::fmt::Display for Constant {
         match &self {
             Constant::Bool(v) => write!(f, "{}", v),
             Constant::String(s) => write!(f, "\"{}\"", s),
-            Constant::Number(n) => write!(f, "{}", n),
+            Constant::Number(n) => write!(f, "{}", Constant::format_number(*n)),
             Constant::None => write!(f, "none"),
             Constant::Function(func) => write!(f, "fn <'{}' {}>", func.name, func.arity),
             Constant::BuiltInMethod(func) => {
                 write!(f, "fn <built-in '{}' {}>", func.name, func.arity)
             }
-            Constant::Array(arr) => write!(f, "array <{}>", arr.len()),
+            Constant::Array(arr) => fmt_array(arr, 0, f),
         }
     }
 }
@@ -88,84 +154,67 @@ impl PartialEq for Constant {
             Constant::None => {
                 matches!(other, Constant::None)
             }
-            _ => false,
-        }
-    }
-}
-
-impl PartialOrd for Constant {
-    fn ge(&self, other: &Self) -> bool {
-        match &self {
-            Constant::Number(lhs) => {
-                if let Constant::Number(rhs) = &other {
-                    lhs >= rhs
+            // arrays are compared element by element
+            Constant::Array(lhs) => {
+                if let Constant::Array(rhs) = &other {
+                    Rc::ptr_eq(lhs, rhs) || lhs == rhs
                 } else {
-                    panic!("Cannot compare non-numbers with '>=' operator")
-                }
-            }
-            _ => panic!("Cannot compare non-numbers with '>=' operator"),
-        }
-    }
-
-    fn le(&self, other: &Self) -> bool {
-        match &self {
-            Constant::Number(lhs) => {
-                if let Constant::Number(rhs) = &other {
-                    lhs <= rhs
-                } else {
-                    panic!("Cannot compare non-numbers with '>=' operator")
+                    false
                 }
             }
-            _ => panic!("Cannot compare non-numbers with '>=' operator"),
-        }
-    }
-
-    fn gt(&self, other: &Self) -> bool {
-        match &self {
-            Constant::Number(lhs) => {
-                if let Constant::Number(rhs) = &other {
-                    lhs > rhs
+            // functions are compared by identity, a script function is the same one only if it
+            // comes from the same declaration and a built-in if it wraps the same closure
+            Constant::Function(lhs) => {
+                if let Constant::Function(rhs) = &other {
+                    Rc::ptr_eq(lhs, rhs)
                 } else {
-                    panic!("Cannot compare non-numbers with '>=' operator")
+                    false
                 }
             }
-            _ => panic!("Cannot compare non-numbers with '>=' operator"),
-        }
-    }
-
-    fn lt(&self, other: &Self) -> bool {
-        match &self {
-            Constant::Number(lhs) => {
-                if let Constant::Number(rhs) = &other {
-                    lhs >= rhs
+            Constant::BuiltInMethod(lhs) => {
+                if let Constant::BuiltInMethod(rhs) = &other {
+                    Rc::ptr_eq(&lhs.func, &rhs.func)
                 } else {
-                    panic!("Cannot compare non-numbers with '>=' operator")
+                    false
                 }
             }
-            _ => panic!("Cannot compare non-numbers with '>=' operator"),
         }
     }
+}
 
+/// Only numbers and strings are ordered, the comparison operators are all derived from
+/// `partial_cmp`.
+impl PartialOrd for Constant {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (&self, &other) {
-            (Constant::Number(lhs), Constant::Number(rhs)) => Some(lhs.partial_cmp(rhs).unwrap()),
+            (Constant::Number(lhs), Constant::Number(rhs)) => lhs.partial_cmp(rhs),
+            // lexicographic by unicode scalar value, the same order `sort` uses
+            (Constant::String(lhs), Constant::String(rhs)) => lhs.partial_cmp(rhs),
             _ => None,
         }
     }
 }
 
+/// The value a built-in method returns, an error halts the VM. Built-ins without a
+/// meaningful return value return `Constant::None`.
+pub type BuiltInResult = Result<Constant, String>;
+
 #[derive(Clone)]
 pub struct BuiltInMethod {
     pub name: String,
-    pub func: Rc<dyn Fn(Vec<Constant>) -> Constant>,
+    pub func: Rc<dyn Fn(Vec<Constant>) -> BuiltInResult>,
     pub arity: u8,
 }
 
 impl BuiltInMethod {
-    pub fn new(name: String, function: Rc<dyn Fn(Vec<Constant>) -> Constant>, arity: u8) -> Self {
+    /// Creates a built-in method, an arity of 0 accepts any amount of arguments.
+    pub fn new<F>(name: impl Into<String>, arity: u8, function: F) -> Self
+    where
+        F: Fn(Vec<Constant>) -> BuiltInResult + 'static,
+    {
         Self {
-            name,
-            func: function,
+            name: name.into(),
+            func: Rc::new(function),
             arity,
         }
     }
@@ -185,12 +234,16 @@ pub enum Instruction {
     Subtract,
     Multiply,
     Divide,
+    /// The remainder of a division, takes the sign of the left-hand side like Rust's `%`.
+    Modulo,
     DefineGlobal(String),
     SetGlobal(String),
     GetGlobal(String),
     GetLocal(usize),
     SetLocal(usize),
     Call(u8),
+    /// A `Call` directly followed by a `Return`, the callee reuses the caller's frame.
+    TailCall(u8),
     Pop,
     Equal,
     NotEqual,
@@ -202,7 +255,20 @@ pub enum Instruction {
     Not,
     JumpIfFalse(u16),
     Jump(u16),
+    /// Jumps backwards by the offset, used to go back to the start of a loop.
+    Loop(u16),
+    /// Indexes into an array by element or into a string by char (not by byte).
     IndexInto,
+    /// Pops a value and an index and sets the element of the array in the local's slot,
+    /// the value stays on the stack. Arrays are copied on write when they're shared, so
+    /// assigning an element never changes another variable holding the same array.
+    IndexSetLocal(usize),
+    /// Like `IndexSetLocal` but for the array in a global.
+    IndexSetGlobal(String),
+    /// Advances a `for` loop. The local at the slot holds the iterated array or string and
+    /// the next slot holds the index of the next element (the byte offset for strings).
+    /// Pushes the next element or jumps by the offset once the target is exhausted.
+    ForIter(usize, u16),
     ArrayLiteral(usize),
     Return,
 }
@@ -249,6 +315,8 @@ impl Chunk {
         }
     }
 
+    /// Adds a jump instruction and returns its index so the offset can be patched
+    /// once the jump target is known.
     pub fn emit_jump(&mut self, instruction: Instruction, line: usize) -> usize {
         self.add_instruction(instruction, line);
         self.code.len() - 1
@@ -296,7 +364,7 @@ impl Function {
 
 #[derive(Debug, Clone)]
 pub struct CallFrame {
-    pub function: Function,
+    pub function: Rc<Function>,
     pub ip: usize,
     pub slot_offset: usize,
 }
@@ -310,6 +378,9 @@ pub struct LocalVariable {
 #[derive(Debug, Clone, Default)]
 pub struct VariableManager {
     locals: Vec<LocalVariable>,
+    /// Where the locals of each function being compiled start, there are no closures so
+    /// a function only sees its own locals.
+    function_starts: Vec<usize>,
     pub scope_depth: usize,
 }
 
@@ -323,6 +394,7 @@ impl VariableManager {
 
         VariableManager {
             locals,
+            function_starts: Vec::new(),
             scope_depth: 0,
         }
     }
@@ -331,11 +403,29 @@ impl VariableManager {
         self.scope_depth += 1;
     }
 
-    pub fn end_scope(&mut self, chunk: &mut Chunk) {
+    pub fn end_scope(&mut self, chunk: &mut Chunk, line: usize) {
         self.scope_depth -= 1;
 
         while !self.locals.is_empty() && self.locals.last().unwrap().depth > self.scope_depth {
-            chunk.add_instruction(Instruction::Pop, 0);
+            chunk.add_instruction(Instruction::Pop, line);
+            self.locals.pop();
+        }
+    }
+
+    /// Starts the scope of a function's parameters, the locals declared before it belong to
+    /// the enclosing function and can't be resolved until `end_function_scope`.
+    pub fn start_function_scope(&mut self) {
+        self.start_scope();
+        self.function_starts.push(self.locals.len());
+    }
+
+    /// Ends the scope of a function's parameters. No `Pop`s are emitted because `Return`
+    /// already discards the whole stack window of the frame.
+    pub fn end_function_scope(&mut self) {
+        self.scope_depth -= 1;
+        self.function_starts.pop();
+
+        while !self.locals.is_empty() && self.locals.last().unwrap().depth > self.scope_depth {
             self.locals.pop();
         }
     }
@@ -346,9 +436,26 @@ impl VariableManager {
         }
     }
 
-    /// you MUST add the bytecode value of the variable before calling this function
-    pub fn add_variable(&mut self, chunk: &mut Chunk, name: &str) {
+    /// you MUST add the bytecode value of the variable before calling this function.
+    /// Errors if a local with the name was already declared in the same scope, shadowing
+    /// one of an outer scope is fine.
+    pub fn add_variable(
+        &mut self,
+        chunk: &mut Chunk,
+        name: &str,
+        line: usize,
+    ) -> Result<(), String> {
         if self.scope_depth > 0 {
+            let declared = self
+                .locals
+                .iter()
+                .rev()
+                .take_while(|local| local.depth == self.scope_depth)
+                .any(|local| local.name == name);
+            if declared {
+                return Err(format!("'{}' is already declared in this scope", name));
+            }
+
             // we are in a scope.
             // we don't add anything to the chunk because the value itself is already on the stack,
             // locals do not have names at runtime, they are retrieved by their index.
@@ -357,36 +464,190 @@ impl VariableManager {
                 depth: self.scope_depth,
             });
         } else {
-            // TODO: line tracking
-            chunk.add_instruction(Instruction::DefineGlobal(name.to_owned()), 0);
+            chunk.add_instruction(Instruction::DefineGlobal(name.to_owned()), line);
         }
+
+        Ok(())
     }
 
-    pub fn named_variable(&self, name: &str, is_set: bool, chunk: &mut Chunk) {
+    /// Emits the instruction that sets an element of the array in the variable, the index
+    /// and the value must be on the stack. Errors like `named_variable`.
+    pub fn index_set_variable(
+        &self,
+        name: &str,
+        chunk: &mut Chunk,
+        line: usize,
+    ) -> Result<(), String> {
+        match self.resolve_local(name) {
+            Some(stack_idx) => chunk.add_instruction(Instruction::IndexSetLocal(stack_idx), line),
+            None if self.is_enclosing_local(name) => {
+                return Err(format!(
+                    "'{}' is a local of an enclosing function, functions can only use their own locals and globals",
+                    name
+                ))
+            }
+            None => chunk.add_instruction(Instruction::IndexSetGlobal(name.to_owned()), line),
+        }
+
+        Ok(())
+    }
+
+    /// Errors if `name` is a local of an enclosing function, those aren't on the stack
+    /// window of the function being compiled.
+    pub fn named_variable(
+        &self,
+        name: &str,
+        is_set: bool,
+        chunk: &mut Chunk,
+        line: usize,
+    ) -> Result<(), String> {
         let local_index = self.resolve_local(name);
+        if local_index.is_none() && self.is_enclosing_local(name) {
+            return Err(format!(
+                "'{}' is a local of an enclosing function, functions can only use their own locals and globals",
+                name
+            ));
+        }
 
         // Ugly but it's better than copying name 2 times using to_owned and defining
         // the instructions in separate variables
 
         if let Some(stack_idx) = local_index {
             if is_set {
-                chunk.add_instruction(Instruction::SetLocal(stack_idx), 0);
+                chunk.add_instruction(Instruction::SetLocal(stack_idx), line);
             } else {
-                chunk.add_instruction(Instruction::GetLocal(stack_idx), 0);
+                chunk.add_instruction(Instruction::GetLocal(stack_idx), line);
             }
         } else if is_set {
-            chunk.add_instruction(Instruction::SetGlobal(name.to_owned()), 0);
+            chunk.add_instruction(Instruction::SetGlobal(name.to_owned()), line);
         } else {
-            chunk.add_instruction(Instruction::GetGlobal(name.to_owned()), 0);
+            chunk.add_instruction(Instruction::GetGlobal(name.to_owned()), line);
         }
+
+        Ok(())
     }
 
     pub fn resolve_local(&self, name: &str) -> Option<usize> {
-        for (index, local) in self.locals.iter().enumerate().rev() {
+        let start = self.function_start();
+        for (index, local) in self.locals[start..].iter().enumerate().rev() {
             if local.name == name {
                 return Some(index + 1); // + 1 cuz we have a default value on the stack;
             }
         }
         None
     }
+
+    fn is_enclosing_local(&self, name: &str) -> bool {
+        self.locals[..self.function_start()]
+            .iter()
+            .any(|local| local.name == name)
+    }
+
+    fn function_start(&self) -> usize {
+        self.function_starts.last().copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn array(elements: Vec<Constant>) -> Constant {
+        Constant::Array(Rc::new(elements))
+    }
+
+    fn function(name: &str) -> Constant {
+        Constant::Function(Rc::new(Function::new(
+            1,
+            Chunk::default(),
+            name.to_owned(),
+            FunctionType::Function,
+        )))
+    }
+
+    #[test]
+    fn nested_arrays_compare_by_element() {
+        let nested = || {
+            array(vec![
+                Constant::Number(1.0),
+                array(vec![
+                    Constant::String(Rc::new("a".to_owned())),
+                    Constant::None,
+                ]),
+            ])
+        };
+        assert_eq!(nested(), nested());
+
+        let different = array(vec![
+            Constant::Number(1.0),
+            array(vec![
+                Constant::String(Rc::new("b".to_owned())),
+                Constant::None,
+            ]),
+        ]);
+        assert_ne!(nested(), different);
+
+        let shorter = array(vec![Constant::Number(1.0)]);
+        assert_ne!(nested(), shorter);
+        assert_ne!(array(vec![]), Constant::None);
+    }
+
+    #[test]
+    fn functions_compare_by_identity() {
+        let f = function("f");
+        assert_eq!(f, f.clone());
+
+        // same name and arity but a different declaration
+        assert_ne!(f, function("f"));
+    }
+
+    #[test]
+    fn built_ins_compare_by_identity() {
+        let built_in = Constant::BuiltInMethod(BuiltInMethod::new("b", 0, |_| Ok(Constant::None)));
+        assert_eq!(built_in, built_in.clone());
+
+        let other = Constant::BuiltInMethod(BuiltInMethod::new("b", 0, |_| Ok(Constant::None)));
+        assert_ne!(built_in, other);
+    }
+
+    #[test]
+    fn emit_jump_returns_the_placeholder_index() {
+        let mut chunk = Chunk::default();
+        chunk.add_instruction(Instruction::Constant(Constant::None), 1);
+
+        assert_eq!(chunk.emit_jump(Instruction::JumpIfFalse(0), 2), 1);
+        assert_eq!(chunk.emit_jump(Instruction::Jump(0), 3), 2);
+        assert!(matches!(
+            chunk.code[..],
+            [
+                Instruction::Constant(Constant::None),
+                Instruction::JumpIfFalse(0),
+                Instruction::Jump(0)
+            ]
+        ));
+        assert_eq!(chunk.lines, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn numbers_and_strings_are_ordered() {
+        use std::cmp::Ordering;
+
+        let number = |n: f64| Constant::Number(n);
+        assert!(number(3.0) < number(5.0));
+        assert!(number(5.0) >= number(5.0));
+        assert!(number(5.0) > number(3.0));
+        assert_eq!(
+            number(5.0).partial_cmp(&number(3.0)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(number(5.0).partial_cmp(&number(5.0)), Some(Ordering::Equal));
+        assert_eq!(number(f64::NAN).partial_cmp(&number(1.0)), None);
+
+        let string = |s: &str| Constant::String(Rc::new(s.to_owned()));
+        assert!(string("a") < string("ab"));
+        assert!(string("b") > string("ab"));
+
+        assert_eq!(number(1.0).partial_cmp(&string("1")), None);
+        assert_eq!(array(vec![]).partial_cmp(&array(vec![])), None);
+    }
 }
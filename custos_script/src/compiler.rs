@@ -1,10 +1,12 @@
 use std::{cell::RefCell, fmt::Arguments, rc::Rc};
 
 use crate::{
-    ast::{BinaryOp, Node, UnaryOp},
+    ast::{self, BinaryOp, LogicalOp, Node, UnaryOp},
     prelude::{Chunk, Constant, Function, FunctionType, Instruction, VariableManager},
 };
 
+pub type CompileResult<T> = Result<T, String>;
+
 #[derive(Default)]
 pub struct Compiler {
     chunk: Chunk,
@@ -19,7 +21,9 @@ impl Compiler {
         }
     }
 
-    pub fn compile_node(&mut self, node: Node) {
+    /// Compiles a node and everything under it. All but the literals are compiled in their own
+    /// methods so this frame stays small, it's on the stack once for every level of nesting.
+    pub fn compile_node(&mut self, node: Node) -> CompileResult<()> {
         match node {
             Node::Number(number, line, _) => self.chunk.add_instruction(
                 Instruction::Constant(Constant::Number(number.parse::<f64>().unwrap())),
@@ -28,166 +32,337 @@ impl Compiler {
             Node::NoneLiteral(line, _) => self
                 .chunk
                 .add_instruction(Instruction::Constant(Constant::None), line),
-            Node::ArrayLiteral(values, line, _) => {
-                let value_size = values.len();
-                for val in values {
-                    self.compile_node(val);
-                }
+            Node::ArrayLiteral(values, line, _) => self.compile_array(values, line)?,
+            Node::Function(func) => self.compile_function(func)?,
+            Node::Block(block) => self.compile_block(block)?,
+            Node::Binary(binary) => self.compile_binary(binary)?,
+            Node::ExprStmt(stmt) => self.compile_expr_stmt(stmt)?,
+            Node::Call(call) => self.compile_call(call)?,
+            Node::Ret(ret) => self.compile_ret(ret)?,
+            Node::VarGet(name, line, column) => self.compile_var_get(&name, line, column)?,
+            Node::VarDecl(decl) => self.compile_var_decl(decl)?,
+            Node::StringLiteral(s, line, _) => self
+                .chunk
+                .add_instruction(Instruction::Constant(Constant::String(Rc::new(s))), line),
+            Node::Subscript(susbcript) => self.compile_subscript(susbcript)?,
+            Node::BoolLiteral(value, line, _) => self
+                .chunk
+                .add_instruction(Instruction::Constant(Constant::Bool(value)), line),
+            Node::Grouping(group) => self.compile_node(*group.expr)?,
+            Node::Unary(unary) => self.compile_unary(unary)?,
+            Node::Logical(logical) => self.compile_logical(logical)?,
+            Node::Assign(assign) => self.compile_assign(assign)?,
+            Node::IndexAssign(assign) => self.compile_index_assign(assign)?,
+            Node::For(for_stmt) => self.compile_for(for_stmt)?,
+            Node::While(while_stmt) => self.compile_while(while_stmt)?,
+            Node::If(if_stmt) => self.compile_if(if_stmt)?,
+            // _ => {
+            //     println!("{node:#?}");
+            //     unimplemented!()
+            // }
+        }
+
+        Ok(())
+    }
+
+    fn compile_array(&mut self, values: Vec<Node>, line: usize) -> CompileResult<()> {
+        let value_size = values.len();
+        for val in values {
+            self.compile_node(val)?;
+        }
+
+        self.chunk
+            .add_instruction(Instruction::ArrayLiteral(value_size), line);
+
+        Ok(())
+    }
+
+    fn compile_expr_stmt(&mut self, stmt: ast::ExprStmt) -> CompileResult<()> {
+        self.compile_node(*stmt.expr)?;
+        let line = self.last_line();
+        self.chunk.add_instruction(Instruction::Pop, line);
+
+        Ok(())
+    }
+
+    fn compile_call(&mut self, call: ast::Call) -> CompileResult<()> {
+        self.compile_node(*call.callee)?;
 
+        let arg_count = call.args.len();
+        for arg in call.args {
+            self.compile_node(arg)?;
+        }
+
+        self.chunk
+            .add_instruction(Instruction::Call(arg_count as u8), call.loc.0);
+
+        Ok(())
+    }
+
+    fn compile_var_get(&mut self, name: &str, line: usize, column: usize) -> CompileResult<()> {
+        self.var_manager
+            .borrow_mut()
+            .named_variable(name, false, &mut self.chunk, line)
+            .map_err(|e| format!("{}:{} {}", line, column, e))?;
+
+        Ok(())
+    }
+
+    fn compile_var_decl(&mut self, decl: ast::VarDecl) -> CompileResult<()> {
+        self.compile_node(*decl.value)?;
+        self.var_manager
+            .borrow_mut()
+            .add_variable(&mut self.chunk, &decl.name, decl.name_loc.0)
+            .map_err(|e| format!("{}:{} {}", decl.name_loc.0, decl.name_loc.1, e))?;
+
+        Ok(())
+    }
+
+    fn compile_subscript(&mut self, susbcript: ast::Subscript) -> CompileResult<()> {
+        self.compile_node(*susbcript.value)?;
+        self.compile_node(*susbcript.index)?;
+        self.chunk
+            .add_instruction(Instruction::IndexInto, susbcript.loc.0);
+
+        Ok(())
+    }
+
+    fn compile_unary(&mut self, unary: ast::Unary) -> CompileResult<()> {
+        self.compile_node(*unary.expr)?;
+        match unary.op {
+            UnaryOp::Negate => {
                 self.chunk
-                    .add_instruction(Instruction::ArrayLiteral(value_size), line);
+                    .add_instruction(Instruction::Negate, unary.op_loc.0);
             }
-            Node::Function(func) => {
-                self.var_manager.borrow_mut().start_scope();
-                let compiler = Compiler::new_with_manager(Rc::clone(&self.var_manager));
-                for arg in &func.args {
-                    self.var_manager
-                        .borrow_mut()
-                        .add_variable(&mut self.chunk, &arg.name);
-                }
-
-                let chunk = compiler.compile(vec![func.body]);
-
-                self.var_manager.borrow_mut().end_scope(&mut self.chunk);
-                self.chunk.add_instruction(
-                    Instruction::Constant(Constant::Function(Function {
-                        arity: func.args.len() as u8,
-                        chunk,
-                        name: func.name.to_owned(),
-                        kind: FunctionType::Function,
-                    })),
-                    func.loc.0,
-                );
-
-                self.var_manager
-                    .borrow_mut()
-                    .add_variable(&mut self.chunk, &func.name);
+            UnaryOp::Not => {
+                self.chunk.add_instruction(Instruction::Not, unary.op_loc.0);
             }
-            Node::Block(block) => {
-                self.var_manager.borrow_mut().start_scope();
-                for decl in block.statements {
-                    self.compile_node(decl);
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn compile_assign(&mut self, assign: ast::Assign) -> CompileResult<()> {
+        self.compile_node(*assign.value)?;
+        self.var_manager
+            .borrow_mut()
+            .named_variable(&assign.name, true, &mut self.chunk, assign.name_loc.0)
+            .map_err(|e| format!("{}:{} {}", assign.name_loc.0, assign.name_loc.1, e))?;
+
+        Ok(())
+    }
+
+    fn compile_index_assign(&mut self, assign: ast::IndexAssign) -> CompileResult<()> {
+        self.compile_node(*assign.index)?;
+        self.compile_node(*assign.value)?;
+        self.var_manager
+            .borrow_mut()
+            .index_set_variable(&assign.name, &mut self.chunk, assign.name_loc.0)
+            .map_err(|e| format!("{}:{} {}", assign.name_loc.0, assign.name_loc.1, e))?;
+
+        Ok(())
+    }
+
+    fn compile_function(&mut self, func: ast::Function) -> CompileResult<()> {
+        self.var_manager.borrow_mut().start_function_scope();
+        let compiler = Compiler::new_with_manager(Rc::clone(&self.var_manager));
+        for arg in &func.args {
+            self.var_manager
+                .borrow_mut()
+                .add_variable(&mut self.chunk, &arg.name, arg.name_loc.0)
+                .map_err(|e| format!("{}:{} {}", arg.name_loc.0, arg.name_loc.1, e))?;
+        }
+
+        let chunk = compiler.compile(vec![func.body])?;
+
+        self.var_manager.borrow_mut().end_function_scope();
+        self.chunk.add_instruction(
+            Instruction::Constant(Constant::Function(Rc::new(Function {
+                arity: func.args.len() as u8,
+                chunk,
+                name: func.name.to_owned(),
+                kind: FunctionType::Function,
+            }))),
+            func.loc.0,
+        );
+
+        self.var_manager
+            .borrow_mut()
+            .add_variable(&mut self.chunk, &func.name, func.loc.0)
+            .map_err(|e| format!("{}:{} {}", func.loc.0, func.loc.1, e))?;
+
+        Ok(())
+    }
+
+    fn compile_block(&mut self, block: ast::Block) -> CompileResult<()> {
+        self.var_manager.borrow_mut().start_scope();
+        for decl in block.statements {
+            self.compile_node(decl)?;
+        }
+        let line = self.last_line();
+        self.var_manager
+            .borrow_mut()
+            .end_scope(&mut self.chunk, line);
+
+        Ok(())
+    }
+
+    fn compile_binary(&mut self, binary: ast::Binary) -> CompileResult<()> {
+        self.compile_node(*binary.lhs)?;
+        self.compile_node(*binary.rhs)?;
+
+        let instruction = match &binary.op {
+            BinaryOp::Add => Instruction::Add,
+            BinaryOp::Sub => Instruction::Subtract,
+            BinaryOp::Mul => Instruction::Multiply,
+            BinaryOp::Div => Instruction::Divide,
+            BinaryOp::Mod => Instruction::Modulo,
+            BinaryOp::Equal => Instruction::Equal,
+            BinaryOp::NotEqual => Instruction::NotEqual,
+            BinaryOp::Greater => Instruction::Greater,
+            BinaryOp::GreaterEq => Instruction::GreaterEq,
+            BinaryOp::Less => Instruction::Lesser,
+            BinaryOp::LessEq => Instruction::LesserEq,
+        };
+
+        self.chunk.add_instruction(instruction, binary.op_loc.0);
+
+        Ok(())
+    }
+
+    fn compile_ret(&mut self, ret: ast::Ret) -> CompileResult<()> {
+        if let Some(value) = ret.value {
+            let is_tail_call = matches!(*value, Node::Call(_));
+            self.compile_node(*value)?;
+
+            if is_tail_call {
+                if let Some(ins) = self.chunk.code.last_mut() {
+                    if let Instruction::Call(arg_count) = ins {
+                        *ins = Instruction::TailCall(*arg_count);
+                    }
                 }
-                self.var_manager.borrow_mut().end_scope(&mut self.chunk);
-            }
-            Node::Binary(binary) => {
-                self.compile_node(*binary.lhs);
-                self.compile_node(*binary.rhs);
-
-                let instruction = match &binary.op {
-                    BinaryOp::Add => Instruction::Add,
-                    BinaryOp::Sub => Instruction::Subtract,
-                    BinaryOp::Mul => Instruction::Multiply,
-                    BinaryOp::Div => Instruction::Divide,
-                    BinaryOp::Equal => Instruction::Equal,
-                    BinaryOp::NotEqual => Instruction::NotEqual,
-                    BinaryOp::Greater => Instruction::Greater,
-                    BinaryOp::GreaterEq => Instruction::GreaterEq,
-                    BinaryOp::Less => Instruction::Lesser,
-                    BinaryOp::LessEq => Instruction::LesserEq,
-                };
-
-                self.chunk.add_instruction(instruction, 1); // TODO: fix line location
-            }
-            Node::ExprStmt(stmt) => {
-                self.compile_node(*stmt.expr);
-                self.chunk.add_instruction(Instruction::Pop, 1); // TODO: fix line location
             }
-            Node::Call(call) => {
-                self.compile_node(*call.callee);
+        } else {
+            self.chunk
+                .add_instruction(Instruction::Constant(Constant::None), ret.loc.0);
+        }
 
-                let arg_count = call.args.len();
-                for arg in call.args {
-                    self.compile_node(arg);
-                }
+        self.chunk.add_instruction(Instruction::Return, ret.loc.0);
 
-                // TODO: fix line location
-                self.chunk
-                    .add_instruction(Instruction::Call(arg_count as u8), 1);
-            }
-            Node::Ret(ret) => {
-                if let Some(value) = ret.value {
-                    self.compile_node(*value);
-                } else {
-                    self.chunk
-                        .add_instruction(Instruction::Constant(Constant::None), 1);
-                    // TODO: fix location
-                }
+        Ok(())
+    }
 
-                self.chunk.add_instruction(Instruction::Return, 1); // TODO: fix location
-            }
-            Node::VarGet(name, _, _) => {
-                self.var_manager
-                    .borrow_mut()
-                    .named_variable(&name, false, &mut self.chunk);
-            }
-            Node::VarDecl(decl) => {
-                self.compile_node(*decl.value);
-                self.var_manager
-                    .borrow_mut()
-                    .add_variable(&mut self.chunk, &decl.name);
-            }
-            Node::StringLiteral(s, line, _) => self
-                .chunk
-                .add_instruction(Instruction::Constant(Constant::String(s)), line),
-            Node::Subscript(susbcript) => {
-                self.compile_node(*susbcript.value);
-                self.compile_node(*susbcript.index);
-                self.chunk.add_instruction(Instruction::IndexInto, 0);
+    fn compile_logical(&mut self, logical: ast::Logical) -> CompileResult<()> {
+        let line = logical.op_loc.0;
+        self.compile_node(*logical.lhs)?;
+
+        // the operand that decides the result is left on the stack
+        match logical.op {
+            LogicalOp::And => {
+                let end_jump = self.chunk.emit_jump(Instruction::JumpIfFalse(0), line);
+                self.chunk.add_instruction(Instruction::Pop, line);
+                self.compile_node(*logical.rhs)?;
+                self.patch_jump(end_jump)?;
             }
-            Node::BoolLiteral(value, line, _) => self
-                .chunk
-                .add_instruction(Instruction::Constant(Constant::Bool(value)), line),
-            Node::Grouping(group) => self.compile_node(*group.expr),
-            Node::Unary(unary) => {
-                self.compile_node(*unary.expr);
-                match unary.op {
-                    UnaryOp::Negate => {
-                        self.chunk
-                            .add_instruction(Instruction::Negate, unary.op_loc.0);
-                    }
-                    UnaryOp::Not => {
-                        self.chunk.add_instruction(Instruction::Not, unary.op_loc.0);
-                    }
-                    _ => unreachable!(),
-                }
+            LogicalOp::Or => {
+                let else_jump = self.chunk.emit_jump(Instruction::JumpIfFalse(0), line);
+                let end_jump = self.chunk.emit_jump(Instruction::Jump(0), line);
+                self.patch_jump(else_jump)?;
+                self.chunk.add_instruction(Instruction::Pop, line);
+                self.compile_node(*logical.rhs)?;
+                self.patch_jump(end_jump)?;
             }
-            Node::Logical(_) => todo!(),
-            Node::Assign(_) => todo!(),
-            Node::For(for_stmt) => {
-                unimplemented!();
-                let loop_start = self.chunk.code.len();
-                let exit_jump = -1;
-
-                self.compile_node(*for_stmt.body);
-                self.emit_loop(loop_start);
-            },
-            Node::If(if_stmt) => {
-                self.compile_node(*if_stmt.condition);
-
-                // TODO: actual lines
-                let then_jump = self.chunk.emit_jump(Instruction::JumpIfFalse(0), 1);
-                self.chunk.add_instruction(Instruction::Pop, 1);
-                self.compile_node(*if_stmt.then_block);
-
-                let else_jump = self.chunk.emit_jump(Instruction::Jump(0), 1);
-                self.patch_jump(then_jump);
-                self.chunk.add_instruction(Instruction::Pop, 1);
-
-                if let Some(else_cond) = if_stmt.else_block {
-                    self.compile_node(*else_cond);
-                }
-                self.patch_jump(else_jump);
-            } // _ => {
-              //     println!("{node:#?}");
-              //     unimplemented!()
-              // }
         }
+
+        Ok(())
+    }
+
+    fn compile_for(&mut self, for_stmt: ast::For) -> CompileResult<()> {
+        let line = for_stmt.name_loc.0;
+
+        // the target and the index live in hidden locals for the whole loop, their
+        // names contain a space so they can't be shadowed by or collide with the script's.
+        self.var_manager.borrow_mut().start_scope();
+        self.compile_node(*for_stmt.target)?;
+        self.var_manager
+            .borrow_mut()
+            .add_variable(&mut self.chunk, " for target", line)?;
+        self.chunk
+            .add_instruction(Instruction::Constant(Constant::Number(0.0)), line);
+        self.var_manager
+            .borrow_mut()
+            .add_variable(&mut self.chunk, " for index", line)?;
+
+        let slot = self
+            .var_manager
+            .borrow()
+            .resolve_local(" for target")
+            .unwrap();
+
+        let loop_start = self.chunk.code.len();
+        let exit_jump = self.chunk.emit_jump(Instruction::ForIter(slot, 0), line);
+
+        // the element pushed by `ForIter` becomes the loop variable
+        self.var_manager.borrow_mut().start_scope();
+        self.var_manager
+            .borrow_mut()
+            .add_variable(&mut self.chunk, &for_stmt.name, line)
+            .map_err(|e| format!("{}:{} {}", for_stmt.name_loc.0, for_stmt.name_loc.1, e))?;
+        self.compile_node(*for_stmt.body)?;
+        self.var_manager
+            .borrow_mut()
+            .end_scope(&mut self.chunk, line);
+
+        self.emit_loop(loop_start, line)?;
+        self.patch_jump(exit_jump)?;
+        self.var_manager
+            .borrow_mut()
+            .end_scope(&mut self.chunk, line);
+
+        Ok(())
     }
 
-    pub fn compile(mut self, declarations: Vec<Box<Node>>) -> Chunk {
+    fn compile_while(&mut self, while_stmt: ast::While) -> CompileResult<()> {
+        let line = while_stmt.loc.0;
+
+        let loop_start = self.chunk.code.len();
+        self.compile_node(*while_stmt.condition)?;
+        let exit_jump = self.chunk.emit_jump(Instruction::JumpIfFalse(0), line);
+        self.chunk.add_instruction(Instruction::Pop, line);
+        self.compile_node(*while_stmt.body)?;
+        self.emit_loop(loop_start, line)?;
+
+        // the condition is left on the stack when leaving through the exit jump
+        self.patch_jump(exit_jump)?;
+        self.chunk.add_instruction(Instruction::Pop, line);
+
+        Ok(())
+    }
+
+    fn compile_if(&mut self, if_stmt: ast::If) -> CompileResult<()> {
+        let line = if_stmt.loc.0;
+        self.compile_node(*if_stmt.condition)?;
+
+        let then_jump = self.chunk.emit_jump(Instruction::JumpIfFalse(0), line);
+        self.chunk.add_instruction(Instruction::Pop, line);
+        self.compile_node(*if_stmt.then_block)?;
+
+        let else_jump = self.chunk.emit_jump(Instruction::Jump(0), line);
+        self.patch_jump(then_jump)?;
+        self.chunk.add_instruction(Instruction::Pop, line);
+
+        if let Some(else_cond) = if_stmt.else_block {
+            self.compile_node(*else_cond)?;
+        }
+        self.patch_jump(else_jump)?;
+
+        Ok(())
+    }
+
+    pub fn compile(mut self, declarations: Vec<Box<Node>>) -> CompileResult<Chunk> {
         for decl in declarations {
-            self.compile_node(*decl);
+            self.compile_node(*decl)?;
         }
 
         let last = self.chunk.code.last();
@@ -195,27 +370,224 @@ impl Compiler {
         match last {
             Some(Instruction::Return) => (),
             _ => {
+                let line = self.last_line();
                 self.chunk
-                    .add_instruction(Instruction::Constant(Constant::None), 1);
-                self.chunk.add_instruction(Instruction::Return, 1);
+                    .add_instruction(Instruction::Constant(Constant::None), line);
+                self.chunk.add_instruction(Instruction::Return, line);
             }
         };
-        self.chunk
+        Ok(self.chunk)
     }
 
-    fn patch_jump(&mut self, offset: usize) {
-        let jump = self.chunk.code.len() - offset;
-        self.chunk.code[offset] = Instruction::JumpIfFalse(jump as u16);
+    /// The line of the last emitted instruction, for the instructions that don't come from
+    /// a node of their own like the `Pop` after an expression statement.
+    fn last_line(&self) -> usize {
+        self.chunk.lines.last().copied().unwrap_or(1)
     }
 
-    fn emit_loop(&mut self, offset: usize) {
-        
+    fn patch_jump(&mut self, offset: usize) -> CompileResult<()> {
+        // the vm steps past the jump instruction after applying the offset
+        let jump = self.chunk.code.len() - offset - 1;
+        let jump = match u16::try_from(jump) {
+            Ok(jump) => jump,
+            Err(_) => return Err(format!("jump too large, {} exceeds {}", jump, u16::MAX)),
+        };
+
+        self.chunk.code[offset] = match self.chunk.code[offset] {
+            Instruction::Jump(_) => Instruction::Jump(jump),
+            Instruction::ForIter(slot, _) => Instruction::ForIter(slot, jump),
+            _ => Instruction::JumpIfFalse(jump),
+        };
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, line: usize) -> CompileResult<()> {
+        // the vm steps past the loop instruction after jumping back, so the offset
+        // includes the loop instruction itself to land exactly on `loop_start`
+        let offset = self.chunk.code.len() + 1 - loop_start;
+        let offset = match u16::try_from(offset) {
+            Ok(offset) => offset,
+            Err(_) => return Err(format!("loop too large, {} exceeds {}", offset, u16::MAX)),
+        };
+
+        self.chunk.add_instruction(Instruction::Loop(offset), line);
+        Ok(())
     }
 
-    pub fn compile_non_boxed(mut self, declarations: Vec<Node>) -> Chunk {
+    pub fn compile_non_boxed(mut self, declarations: Vec<Node>) -> CompileResult<Chunk> {
         for decl in declarations {
-            self.compile_node(decl);
+            self.compile_node(decl)?;
         }
-        self.chunk
+        Ok(self.chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compiler;
+    use crate::{
+        ast::{Block, If, Node, While},
+        bytecode::{Constant, Instruction},
+        compile,
+    };
+
+    fn count_pops(code: &[Instruction]) -> usize {
+        code.iter()
+            .filter(|ins| matches!(ins, Instruction::Pop))
+            .count()
+    }
+
+    #[test]
+    fn block_pops_each_local() {
+        let chunk = compile("{\nvar a = 1\nvar b = 2\nvar c = 3\n}").unwrap();
+        assert_eq!(count_pops(&chunk.code), 3);
+    }
+
+    #[test]
+    fn nested_blocks_pop_their_own_locals() {
+        let chunk = compile("{\nvar a = 1\n{\nvar b = 2\nvar c = 3\n}\nvar d = 4\n}").unwrap();
+        assert_eq!(count_pops(&chunk.code), 4);
+
+        // the inner block's locals are popped before `d` is declared
+        let inner_end = chunk
+            .code
+            .iter()
+            .position(|ins| matches!(ins, Instruction::Pop))
+            .unwrap();
+        assert!(matches!(chunk.code[inner_end + 1], Instruction::Pop));
+        assert!(matches!(
+            chunk.code[inner_end + 2],
+            Instruction::Constant(_)
+        ));
+    }
+
+    #[test]
+    fn globals_are_not_popped() {
+        let chunk = compile("var a = 1\nvar b = 2").unwrap();
+        assert_eq!(count_pops(&chunk.code), 0);
+    }
+
+    #[test]
+    fn parameters_are_not_popped() {
+        let chunk = compile("func f(a, b) {\nvar c = 1\nret c\n}").unwrap();
+        let function = chunk
+            .code
+            .iter()
+            .find_map(|ins| match ins {
+                Instruction::Constant(Constant::Function(f)) => Some(f),
+                _ => None,
+            })
+            .unwrap();
+
+        // only `c`, `Return` discards the parameters with the frame
+        assert_eq!(count_pops(&function.chunk.code), 1);
+    }
+
+    #[test]
+    fn for_loop_pops_hidden_locals() {
+        // `y` and the loop variable every iteration, then the hidden target and index
+        let chunk = compile("{\nfor x in [1, 2] {\nvar y = x\n}\n}").unwrap();
+        assert_eq!(count_pops(&chunk.code), 4);
+    }
+
+    #[test]
+    fn if_else_keeps_the_jump_variants() {
+        let chunk = compile("if true {\nvar a = 1\n} else {\nvar b = 2\n}").unwrap();
+        let jumps = chunk
+            .code
+            .iter()
+            .filter(|ins| matches!(ins, Instruction::Jump(_) | Instruction::JumpIfFalse(_)))
+            .collect::<Vec<_>>();
+
+        // the then branch skips the else branch with a plain jump once both are patched
+        assert!(matches!(
+            jumps[..],
+            [Instruction::JumpIfFalse(_), Instruction::Jump(_)]
+        ));
+    }
+
+    #[test]
+    fn jumps_are_patched_to_their_targets() {
+        let chunk = compile("var a = 1\nif a {\nvar b = 2\n} else {\nvar c = 3\n}").unwrap();
+        let code = &chunk.code;
+
+        // the vm steps past a jump after applying its offset
+        let (then_jump, offset) = code
+            .iter()
+            .enumerate()
+            .find_map(|(i, ins)| match ins {
+                Instruction::JumpIfFalse(offset) => Some((i, *offset as usize)),
+                _ => None,
+            })
+            .unwrap();
+        let (else_jump, else_offset) = code
+            .iter()
+            .enumerate()
+            .find_map(|(i, ins)| match ins {
+                Instruction::Jump(offset) => Some((i, *offset as usize)),
+                _ => None,
+            })
+            .unwrap();
+
+        // a false condition lands on the else branch, which pops the condition first
+        assert_eq!(then_jump + offset + 1, else_jump + 1);
+        assert!(matches!(code[else_jump + 1], Instruction::Pop));
+        assert!(matches!(
+            code[else_jump + 2],
+            Instruction::Constant(Constant::Number(n)) if n == 3.0
+        ));
+
+        // the end of the then branch skips past the else branch to the script's return value
+        assert_eq!(else_jump + else_offset + 1, code.len() - 2);
+    }
+
+    #[test]
+    fn oversized_jumps_are_compile_errors() {
+        // built by hand, a script this long takes a while to tokenize
+        let body = |len: usize| {
+            let statements = (0..len)
+                .map(|_| Node::Number("1".to_owned(), 2, 1))
+                .collect();
+            Block::new_node(statements)
+        };
+        let condition = || Box::new(Node::BoolLiteral(true, 1, 1));
+
+        // every number is one instruction, the jump offset has to fit in a `u16`
+        let too_long = If::new_node(condition(), body(u16::MAX as usize + 1), None, (1, 1));
+        let err = Compiler::default()
+            .compile_non_boxed(vec![*too_long])
+            .unwrap_err();
+        assert!(err.starts_with("jump too large"), "{}", err);
+
+        let too_long = While::new_node(condition(), body(u16::MAX as usize + 1), (1, 1));
+        let err = Compiler::default()
+            .compile_non_boxed(vec![*too_long])
+            .unwrap_err();
+        assert!(err.starts_with("loop too large"), "{}", err);
+
+        let long = If::new_node(condition(), body(u16::MAX as usize - 10), None, (1, 1));
+        assert!(Compiler::default().compile_non_boxed(vec![*long]).is_ok());
+    }
+
+    #[test]
+    fn redeclaring_in_the_same_block_is_an_error() {
+        let err = compile("{\nvar a = 1\nvar a = 2\n}").unwrap_err();
+        assert_eq!(err, "3:6 'a' is already declared in this scope");
+
+        let err = compile("func f(a) {\nvar b = a\nvar b = 2\n}").unwrap_err();
+        assert_eq!(err, "3:6 'b' is already declared in this scope");
+    }
+
+    #[test]
+    fn assigning_and_shadowing_are_not_redeclaring() {
+        assert!(compile("{\nvar a = 1\na = 2\n}").is_ok());
+        assert!(compile("{\nvar a = 1\n{\nvar a = 2\n}\n}").is_ok());
+        assert!(compile("{\nvar a = 1\n}\n{\nvar a = 2\n}").is_ok());
+
+        // the body is a block of its own inside the parameters' scope
+        assert!(compile("func f(a) {\nvar a = 1\n}").is_ok());
+
+        // globals can be redefined
+        assert!(compile("var a = 1\nvar a = 2").is_ok());
     }
 }
@@ -1,12 +1,257 @@
 pub mod ast;
 pub mod bytecode;
+pub mod compiler;
 pub mod parser;
+pub mod stdlib;
 pub mod tokenizer;
 pub mod vm;
-pub mod compiler;
 
+/// Everything needed to embed the language, `use custos_script::prelude::*;` covers
+/// compiling, running and defining built-ins.
+///
+/// ```
+/// use custos_script::prelude::*;
+///
+/// let chunk = compile("func greet(name) {\n    ret greeting() + name\n}").unwrap();
+/// let mut vm = VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+/// stdlib::define_stdlib(&mut vm);
+/// vm.define_built_in_fn(BuiltInMethod::new("greeting", 0, |_| {
+///     Ok(Constant::String("hello ".to_owned().into()))
+/// }));
+///
+/// assert_eq!(vm.interpret(), None);
+/// assert_eq!(
+///     vm.run_function("greet", vec![Constant::String("there".to_owned().into())]),
+///     Ok(Constant::String("hello there".to_owned().into()))
+/// );
+///
+/// // or all at once when the script only has to run
+/// run("var sorted = sort([3, 1, 2])", stdlib::define_stdlib).unwrap();
+/// ```
 pub mod prelude {
     pub use crate::bytecode::*;
+    pub use crate::compiler::Compiler;
+    pub use crate::parser::Parser;
+    pub use crate::stdlib;
     pub use crate::tokenizer::*;
     pub use crate::vm::*;
+    pub use crate::{compile, compile_with_max_length, run, run_with_max_length};
+}
+
+use ast::Node;
+use bytecode::{Chunk, Constant, Function, FunctionType, Instruction};
+use compiler::Compiler;
+use parser::Parser;
+use tokenizer::Tokenizer;
+use vm::VirtualMachine;
+
+/// Parses and compiles the source into a chunk. Top-level statements run in the script
+/// itself, if the script defines a `main` function it's called after them.
+pub fn compile(source: &str) -> Result<Chunk, String> {
+    compile_with_max_length(source, None)
+}
+
+/// Like `compile` but a source longer than `max_length` chars is rejected before it's
+/// tokenized, so untrusted input can't make the host spend time on megabytes of code.
+pub fn compile_with_max_length(source: &str, max_length: Option<usize>) -> Result<Chunk, String> {
+    if let Some(max_length) = max_length {
+        let length = source.chars().count();
+        if length > max_length {
+            return Err(format!(
+                "the script is too long, {} characters exceed the limit of {}",
+                length, max_length
+            ));
+        }
+    }
+
+    let source = source.to_owned();
+    let tokenizer = Tokenizer::new(&source);
+    let mut parser = Parser::new(tokenizer, &source)?;
+    parser.parse()?;
+
+    let has_main = parser
+        .declarations
+        .iter()
+        .any(|decl| matches!(decl, Node::Function(func) if func.name == "main"));
+
+    let compiler = Compiler::default();
+    let mut chunk = compiler.compile_non_boxed(parser.declarations)?;
+
+    if has_main {
+        chunk.add_instruction(Instruction::GetGlobal("main".to_string()), 1);
+        chunk.add_instruction(Instruction::Call(0), 1);
+    } else {
+        chunk.add_instruction(Instruction::Constant(Constant::None), 1);
+    }
+    chunk.add_instruction(Instruction::Return, 1);
+    Ok(chunk)
+}
+
+/// Compiles and runs the source, `setup` is called before interpreting so the host
+/// can define its built-in methods and limits.
+pub fn run<F>(source: &str, setup: F) -> Result<(), String>
+where
+    F: FnOnce(&mut VirtualMachine),
+{
+    run_with_max_length(source, None, setup)
+}
+
+/// Like `run` with the source length limit of `compile_with_max_length`.
+pub fn run_with_max_length<F>(
+    source: &str,
+    max_length: Option<usize>,
+    setup: F,
+) -> Result<(), String>
+where
+    F: FnOnce(&mut VirtualMachine),
+{
+    let chunk = compile_with_max_length(source, max_length)?;
+    let mut vm = VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+
+    setup(&mut vm);
+
+    match vm.interpret() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::DEFAULT_MAX_DEPTH;
+
+    #[test]
+    fn over_the_limit_is_rejected_before_parsing() {
+        // not even valid syntax, a parse error would mean it was tokenized
+        let source = "é".repeat(11) + "(((";
+
+        let err = compile_with_max_length(&source, Some(10)).unwrap_err();
+        assert_eq!(
+            err,
+            "the script is too long, 14 characters exceed the limit of 10"
+        );
+
+        let err =
+            run_with_max_length(&source, Some(10), |_| panic!("the VM was set up")).unwrap_err();
+        assert!(err.starts_with("the script is too long"));
+    }
+
+    #[test]
+    fn length_is_counted_in_chars() {
+        // 11 chars but 12 bytes
+        let source = "var a = \"é\"";
+
+        assert!(compile_with_max_length(source, Some(11)).is_ok());
+        assert!(compile_with_max_length(source, Some(10)).is_err());
+        assert!(compile_with_max_length(source, None).is_ok());
+    }
+
+    #[test]
+    fn long_operator_chains_are_too_deep() {
+        // each operator nests the tree one level, compiling these used to overflow the stack
+        for chain in [
+            vec!["1"; 2000].join(" + "),
+            vec!["true"; 2000].join(" && "),
+            format!("a{}", "[0]".repeat(2000)),
+            format!("f{}", "()".repeat(2000)),
+        ] {
+            let source = format!("var x = {};", chain);
+            let err = compile(&source).unwrap_err();
+            assert!(err.contains("expression too deeply nested"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn operator_chains_under_the_limit_compile() {
+        let source = format!("var x = {};", vec!["1"; 100].join(" + "));
+        assert!(compile(&source).is_ok());
+
+        // the depth is given back after a chain so the next one starts from scratch
+        let source = format!("var x = {0};\nvar y = {0};", vec!["1"; 100].join(" * "));
+        assert!(compile(&source).is_ok());
+    }
+
+    /// The stack a thread gets by default, the scripts have to fit in it in debug builds too.
+    const SMALL_STACK: usize = 2 * 1024 * 1024;
+
+    fn compile_on_small_stack(source: String) -> Result<(), String> {
+        // chunks hold `Rc`s and can't leave the thread, only whether it compiled does
+        std::thread::Builder::new()
+            .stack_size(SMALL_STACK)
+            .spawn(move || compile(&source).map(|_| ()))
+            .unwrap()
+            .join()
+            .expect("compiling overflowed the stack")
+    }
+
+    fn nested_statements(depth: usize) -> Vec<String> {
+        vec![
+            format!(
+                "var a = 1\n{}{}",
+                "if a {\n".repeat(depth),
+                "}\n".repeat(depth)
+            ),
+            format!("var a = 1\nif a {{}}{}", " else if a {}".repeat(depth)),
+            format!(
+                "var a = 1\n{}{}",
+                "while a {\n".repeat(depth),
+                "}\n".repeat(depth)
+            ),
+            format!(
+                "var a = [1]\n{}{}",
+                "for x in a {\n".repeat(depth),
+                "}\n".repeat(depth)
+            ),
+            format!("{}{}", "func f {\n".repeat(depth), "}\n".repeat(depth)),
+            format!("{}{}", "{\n".repeat(depth), "}\n".repeat(depth)),
+        ]
+    }
+
+    #[test]
+    fn deeply_nested_statements_are_too_deep() {
+        for source in nested_statements(200) {
+            let err = compile_on_small_stack(source).unwrap_err();
+            assert!(err.contains("too deeply nested"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn long_else_if_chains_are_too_deep() {
+        // every `else if` nests the next if in the else block
+        let source = format!("var a = 1\nif a {{}}{}", " else if a {}".repeat(200));
+        let err = compile_with_max_length(&source, Some(4000)).unwrap_err();
+        assert!(err.contains("too deeply nested"), "{}", err);
+
+        let source = format!("var a = 1\nif a {{}}{}", " else if a {}".repeat(20));
+        assert!(compile(&source).is_ok());
+    }
+
+    #[test]
+    fn the_deepest_scripts_fit_the_stack() {
+        let depth = DEFAULT_MAX_DEPTH - 1;
+        let mut sources = nested_statements(depth);
+        sources.push(format!(
+            "var a = {}1{}",
+            "(".repeat(depth),
+            ")".repeat(depth)
+        ));
+        sources.push(format!(
+            "var a = {}1{}",
+            "[".repeat(depth),
+            "]".repeat(depth)
+        ));
+        sources.push(format!("var a = {}1", "-".repeat(depth)));
+        sources.push(format!(
+            "var a = 1\n{}var b = {}1{}\n{}",
+            "if a {\n".repeat(depth / 2),
+            "(".repeat(depth / 2),
+            ")".repeat(depth / 2),
+            "}\n".repeat(depth / 2)
+        ));
+
+        for source in sources {
+            assert!(compile_on_small_stack(source).is_ok());
+        }
+    }
 }
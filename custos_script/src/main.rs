@@ -1,11 +1,7 @@
-use std::rc::Rc;
-
 use custos_script::{
     bytecode,
-    compiler::Compiler,
-    parser::Parser,
-    prelude::{BuiltInMethod, Constant, FunctionType, Instruction},
-    tokenizer::Tokenizer,
+    prelude::{BuiltInMethod, Constant, FunctionType},
+    stdlib,
     vm::VirtualMachine,
 };
 
@@ -28,39 +24,26 @@ fn main() {
         ",
     );
 
-    let tokenizer = Tokenizer::new(&content);
-    let mut parser = match Parser::new(tokenizer, &content) {
-        Ok(p) => p,
-        Err(e) => {
-            panic!("{e}");
-        }
-    };
-    match parser.parse() {
-        Ok(_) => (),
+    let chunk = match custos_script::compile(&content) {
+        Ok(c) => c,
         Err(e) => {
             panic!("{e}");
         }
     };
 
-    let compiler = Compiler::default();
-    let mut chunk = compiler.compile_non_boxed(parser.declarations);
-
-    chunk.add_instruction(Instruction::GetGlobal("main".to_string()), 1);
-    chunk.add_instruction(Instruction::Call(0), 1);
-    chunk.add_instruction(Instruction::Return, 1);
-
-    let mut vm = VirtualMachine::new(bytecode::Function {
-        arity: 0,
-        chunk,
-        name: "".to_owned(),
-        kind: FunctionType::Script,
-    });
+    let mut vm = VirtualMachine::with_tracing(
+        bytecode::Function {
+            arity: 0,
+            chunk,
+            name: "".to_owned(),
+            kind: FunctionType::Script,
+        },
+        true,
+    );
 
-    vm.define_built_in_fn(BuiltInMethod::new(
-        "send".to_owned(),
-        Rc::new(move |_| Constant::None),
-        0,
-    ));
+    stdlib::define_stdlib(&mut vm);
+    vm.define_std_io();
+    vm.define_built_in_fn(BuiltInMethod::new("send", 0, |_| Ok(Constant::None)));
 
     if let Some(err) = vm.interpret() {
         panic!("{}", err)
@@ -1,7 +1,7 @@
 use crate::{
     ast::{
         Assign, Binary, BinaryOp, Block, Call, ExprStmt, For, Function, FunctionArg, Grouping, If,
-        Logical, LogicalOp, Node, Ret, Subscript, Unary, UnaryOp, VarDecl,
+        IndexAssign, Logical, LogicalOp, Node, Ret, Subscript, Unary, UnaryOp, VarDecl, While,
     },
     tokenizer::{get_tok_len, get_tok_loc, TokenKind, Tokenizer},
 };
@@ -52,26 +52,85 @@ macro_rules! consume {
     }};
 }
 
+/// Like `consume!` but skips the delimiters before the token, for a `{` on its own line.
+macro_rules! consume_nows {
+    ($self: ident, $msg: expr, $($tts:tt)*) => {{
+        if !matches_nows!($self, $($tts)*) {
+            return Err($self.error($msg, &$self.current))
+        }
+    }};
+}
+
 type ParseResult<T> = Result<T, String>;
 
+/// How deep expressions and statements may nest before parsing stops, each level is a few
+/// stack frames of recursion so untrusted scripts could overflow the stack otherwise. The
+/// deepest scripts fit in a 2 MB thread stack in debug builds.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
 pub struct Parser<'a> {
     tokenizer: Tokenizer<'a>,
     current: TokenKind,
     source: &'a String,
+    depth: usize,
+    max_depth: usize,
+    too_deep: bool,
     pub declarations: Vec<Node>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(mut tokenizer: Tokenizer<'a>, source: &'a String) -> ParseResult<Parser<'a>> {
-        let current = tokenizer.next().unwrap()?;
+        let current = tokenizer.next().unwrap_or(Ok(TokenKind::Eof))?;
         Ok(Parser {
             tokenizer,
             current,
             source,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            too_deep: false,
             declarations: Vec::new(),
         })
     }
 
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Runs `parse` one nesting level deeper, failing with `message` past the maximum depth.
+    fn nested<T>(
+        &mut self,
+        message: &str,
+        parse: impl FnOnce(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<T> {
+        self.deeper(message)?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn deeper(&mut self, message: &str) -> ParseResult<()> {
+        if self.depth >= self.max_depth {
+            self.too_deep = true;
+            return Err(self.error(message, &self.current));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Runs a left-associative `parse` loop that calls `link` for every operator it chains,
+    /// the parser loops over them but they nest in the tree just like parentheses do.
+    fn chained<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        let depth = self.depth;
+        let result = parse(self);
+        self.depth = depth;
+        result
+    }
+
+    fn link(&mut self) -> ParseResult<()> {
+        self.deeper("expression too deeply nested")
+    }
+
     pub fn parse(&mut self) -> ParseResult<()> {
         let mut errors = Vec::new();
         while !self.is_at_end() {
@@ -84,6 +143,8 @@ impl<'a> Parser<'a> {
                         self.declarations.push(declaration);
                     }
                 }
+                // recovering would report the same error again at every level of nesting
+                Err(e) if self.too_deep => return Err(e),
                 Err(e) => {
                     errors.push(format!("{}", e));
                     self.synchronize()?;
@@ -109,6 +170,7 @@ impl<'a> Parser<'a> {
             match self.current {
                 TokenKind::Func(_, _)
                 | TokenKind::For(_, _)
+                | TokenKind::While(_, _)
                 | TokenKind::If(_, _)
                 | TokenKind::Ret(_, _)
                 | TokenKind::RightBrace(_, _)
@@ -176,8 +238,12 @@ impl<'a> Parser<'a> {
             let stmt = self.for_stmt()?;
             return Ok(Some(stmt));
         }
+        if matches!(self, self.current, TokenKind::While(_, _)) {
+            let stmt = self.while_stmt(loc?)?;
+            return Ok(Some(stmt));
+        }
         if matches!(self, self.current, TokenKind::If(_, _)) {
-            let stmt = self.if_stmt()?;
+            let stmt = self.if_stmt(loc?)?;
             return Ok(Some(stmt));
         }
 
@@ -245,7 +311,7 @@ impl<'a> Parser<'a> {
             );
         }
 
-        consume!(
+        consume_nows!(
             self,
             "expected a '{'",
             self.current,
@@ -261,9 +327,9 @@ impl<'a> Parser<'a> {
         ))
     }
 
-    fn if_stmt(&mut self) -> ParseResult<Box<Node>> {
+    fn if_stmt(&mut self, loc: (usize, usize)) -> ParseResult<Box<Node>> {
         let cond = self.expr()?;
-        consume!(
+        consume_nows!(
             self,
             "expected a '{'",
             self.current,
@@ -273,19 +339,30 @@ impl<'a> Parser<'a> {
         let then_branch = self.block()?;
         let mut else_branch = None;
         if matches_nows!(self, self.current, TokenKind::Else(_, _)) {
-            consume!(
-                self,
-                "expected a '{'",
-                self.current,
-                TokenKind::LeftBrace(_, _)
-            );
-            else_branch = Some(Block::new_node(self.block()?));
+            // `else if` is an else block holding just the nested if
+            let if_loc = get_tok_loc(&self.current);
+            if matches!(self, self.current, TokenKind::If(_, _)) {
+                let if_loc = if_loc?;
+                let else_if = self.nested("statement too deeply nested", |parser| {
+                    parser.if_stmt(if_loc)
+                })?;
+                else_branch = Some(Block::new_node(vec![*else_if]));
+            } else {
+                consume_nows!(
+                    self,
+                    "expected a '{' or 'if'",
+                    self.current,
+                    TokenKind::LeftBrace(_, _)
+                );
+                else_branch = Some(Block::new_node(self.block()?));
+            }
         }
 
         Ok(If::new_node(
             cond,
             Block::new_node(then_branch),
             else_branch,
+            loc,
         ))
     }
 
@@ -318,16 +395,34 @@ impl<'a> Parser<'a> {
         consume!(self, "expected 'in'", self.current, TokenKind::In(_, _));
         let target = self.expr()?;
 
-        consume!(self, "expected a '{'", self.current, TokenKind::LeftBrace(_, _));
+        consume_nows!(
+            self,
+            "expected a '{'",
+            self.current,
+            TokenKind::LeftBrace(_, _)
+        );
         let body = self.block()?;
         Ok(For::new_node(name, name_loc, target, Block::new_node(body)))
     }
 
+    fn while_stmt(&mut self, loc: (usize, usize)) -> ParseResult<Box<Node>> {
+        let condition = self.expr()?;
+        consume_nows!(
+            self,
+            "expected a '{'",
+            self.current,
+            TokenKind::LeftBrace(_, _)
+        );
+        let body = self.block()?;
+        Ok(While::new_node(condition, Block::new_node(body), loc))
+    }
+
     fn block(&mut self) -> ParseResult<Vec<Node>> {
         let mut errors = Vec::new();
         let mut statements: Vec<Node> = Vec::with_capacity(10);
         while !std::matches!(self.current, TokenKind::RightBrace(_, _)) && !self.is_at_end() {
-            let declaration = self.declaration();
+            let declaration =
+                self.nested("statement too deeply nested", |parser| parser.declaration());
             match declaration {
                 Ok(declaration) => {
                     if let Some(decl) = declaration {
@@ -335,6 +430,7 @@ impl<'a> Parser<'a> {
                         statements.push(decl);
                     }
                 }
+                Err(e) if self.too_deep => return Err(e),
                 Err(e) => {
                     errors.push(e);
                     self.synchronize()?;
@@ -369,18 +465,33 @@ impl<'a> Parser<'a> {
     }
 
     fn expr(&mut self) -> ParseResult<Box<Node>> {
-        self.assignment()
+        self.nested("expression too deeply nested", |parser| parser.assignment())
     }
 
     fn assignment(&mut self) -> ParseResult<Box<Node>> {
         let expr = self.or()?;
         if matches!(self, self.current, TokenKind::Equal(_, _)) {
-            let value = self.assignment()?;
+            let value =
+                self.nested("expression too deeply nested", |parser| parser.assignment())?;
 
-            match expr.as_ref() {
+            match *expr {
                 Node::VarGet(name, line, column) => {
-                    return Ok(Assign::new_node(name.to_string(), (*line, *column), value));
+                    return Ok(Assign::new_node(name, (line, column), value));
                 }
+                Node::Subscript(subscript) => match *subscript.value {
+                    Node::VarGet(name, line, column) => {
+                        return Ok(IndexAssign::new_node(
+                            name,
+                            (line, column),
+                            subscript.index,
+                            value,
+                        ));
+                    }
+                    _ => return Err(
+                        "Invalid target for assignment, only elements of variables can be assigned"
+                            .to_string(),
+                    ),
+                },
                 _ => return Err("Invalid target for assignment".to_string()),
             }
         }
@@ -389,119 +500,145 @@ impl<'a> Parser<'a> {
     }
 
     fn or(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.and()?;
-        loop {
-            #[allow(clippy::needless_late_init)]
-            let lop;
+        self.chained(|parser| {
+            let mut expr = parser.and()?;
+            loop {
+                #[allow(clippy::needless_late_init)]
+                let lop;
+                let op_loc = get_tok_loc(&parser.current);
 
-            if matches!(self, self.current, TokenKind::Or(_, _)) {
-                lop = LogicalOp::Or;
-            } else {
-                break;
-            }
+                if matches!(parser, parser.current, TokenKind::Or(_, _)) {
+                    lop = LogicalOp::Or;
+                } else {
+                    break;
+                }
 
-            let right = self.and()?;
-            expr = Logical::new_node(expr, right, lop);
-        }
-        Ok(expr)
+                parser.link()?;
+                let right = parser.and()?;
+                expr = Logical::new_node(expr, right, lop, op_loc?);
+            }
+            Ok(expr)
+        })
     }
 
     fn and(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.equality()?;
-        loop {
-            #[allow(clippy::needless_late_init)]
-            let lop;
+        self.chained(|parser| {
+            let mut expr = parser.equality()?;
+            loop {
+                #[allow(clippy::needless_late_init)]
+                let lop;
+                let op_loc = get_tok_loc(&parser.current);
 
-            if matches!(self, self.current, TokenKind::And(_, _)) {
-                lop = LogicalOp::And;
-            } else {
-                break;
-            }
+                if matches!(parser, parser.current, TokenKind::And(_, _)) {
+                    lop = LogicalOp::And;
+                } else {
+                    break;
+                }
 
-            let right = self.equality()?;
-            expr = Logical::new_node(expr, right, lop);
-        }
-        Ok(expr)
+                parser.link()?;
+                let right = parser.equality()?;
+                expr = Logical::new_node(expr, right, lop, op_loc?);
+            }
+            Ok(expr)
+        })
     }
 
     fn equality(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.comparison()?;
-        loop {
-            let bop;
-
-            if matches!(self, self.current, TokenKind::NotEqual(_, _)) {
-                bop = BinaryOp::NotEqual;
-            } else if matches!(self, self.current, TokenKind::EqualEqual(_, _)) {
-                bop = BinaryOp::Equal;
-            } else {
-                break;
-            }
+        self.chained(|parser| {
+            let mut expr = parser.comparison()?;
+            loop {
+                let bop;
+                let op_loc = get_tok_loc(&parser.current);
 
-            let right = self.comparison()?;
-            expr = Binary::new_node(expr, right, bop);
-        }
-        Ok(expr)
+                if matches!(parser, parser.current, TokenKind::NotEqual(_, _)) {
+                    bop = BinaryOp::NotEqual;
+                } else if matches!(parser, parser.current, TokenKind::EqualEqual(_, _)) {
+                    bop = BinaryOp::Equal;
+                } else {
+                    break;
+                }
+
+                parser.link()?;
+                let right = parser.comparison()?;
+                expr = Binary::new_node(expr, right, bop, op_loc?);
+            }
+            Ok(expr)
+        })
     }
 
     fn comparison(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.term()?;
-        loop {
-            let bop;
-
-            if matches!(self, self.current, TokenKind::Greater(_, _)) {
-                bop = BinaryOp::Greater;
-            } else if matches!(self, self.current, TokenKind::GreaterEq(_, _)) {
-                bop = BinaryOp::GreaterEq;
-            } else if matches!(self, self.current, TokenKind::Less(_, _)) {
-                bop = BinaryOp::Less;
-            } else if matches!(self, self.current, TokenKind::LessEq(_, _)) {
-                bop = BinaryOp::LessEq;
-            } else {
-                break;
-            }
+        self.chained(|parser| {
+            let mut expr = parser.term()?;
+            loop {
+                let bop;
+                let op_loc = get_tok_loc(&parser.current);
+
+                if matches!(parser, parser.current, TokenKind::Greater(_, _)) {
+                    bop = BinaryOp::Greater;
+                } else if matches!(parser, parser.current, TokenKind::GreaterEq(_, _)) {
+                    bop = BinaryOp::GreaterEq;
+                } else if matches!(parser, parser.current, TokenKind::Less(_, _)) {
+                    bop = BinaryOp::Less;
+                } else if matches!(parser, parser.current, TokenKind::LessEq(_, _)) {
+                    bop = BinaryOp::LessEq;
+                } else {
+                    break;
+                }
 
-            let right = self.term()?;
-            expr = Binary::new_node(expr, right, bop);
-        }
-        Ok(expr)
+                parser.link()?;
+                let right = parser.term()?;
+                expr = Binary::new_node(expr, right, bop, op_loc?);
+            }
+            Ok(expr)
+        })
     }
 
     fn term(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.factor()?;
-        loop {
-            let bop;
-
-            if matches!(self, self.current, TokenKind::Plus(_, _)) {
-                bop = BinaryOp::Add;
-            } else if matches!(self, self.current, TokenKind::Minus(_, _)) {
-                bop = BinaryOp::Sub;
-            } else {
-                break;
-            }
+        self.chained(|parser| {
+            let mut expr = parser.factor()?;
+            loop {
+                let bop;
+                let op_loc = get_tok_loc(&parser.current);
 
-            let right = self.factor()?;
-            expr = Binary::new_node(expr, right, bop);
-        }
-        Ok(expr)
+                if matches!(parser, parser.current, TokenKind::Plus(_, _)) {
+                    bop = BinaryOp::Add;
+                } else if matches!(parser, parser.current, TokenKind::Minus(_, _)) {
+                    bop = BinaryOp::Sub;
+                } else {
+                    break;
+                }
+
+                parser.link()?;
+                let right = parser.factor()?;
+                expr = Binary::new_node(expr, right, bop, op_loc?);
+            }
+            Ok(expr)
+        })
     }
 
     fn factor(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.unary()?;
-        loop {
-            let bop;
-
-            if matches!(self, self.current, TokenKind::Slash(_, _)) {
-                bop = BinaryOp::Div;
-            } else if matches!(self, self.current, TokenKind::Star(_, _)) {
-                bop = BinaryOp::Mul;
-            } else {
-                break;
-            }
+        self.chained(|parser| {
+            let mut expr = parser.unary()?;
+            loop {
+                let bop;
+                let op_loc = get_tok_loc(&parser.current);
+
+                if matches!(parser, parser.current, TokenKind::Slash(_, _)) {
+                    bop = BinaryOp::Div;
+                } else if matches!(parser, parser.current, TokenKind::Star(_, _)) {
+                    bop = BinaryOp::Mul;
+                } else if matches!(parser, parser.current, TokenKind::Percent(_, _)) {
+                    bop = BinaryOp::Mod;
+                } else {
+                    break;
+                }
 
-            let right = self.unary()?;
-            expr = Binary::new_node(expr, right, bop);
-        }
-        Ok(expr)
+                parser.link()?;
+                let right = parser.unary()?;
+                expr = Binary::new_node(expr, right, bop, op_loc?);
+            }
+            Ok(expr)
+        })
     }
 
     fn unary(&mut self) -> ParseResult<Box<Node>> {
@@ -517,7 +654,7 @@ impl<'a> Parser<'a> {
         }
 
         if uop != UnaryOp::None {
-            let expr = self.unary()?;
+            let expr = self.nested("expression too deeply nested", |parser| parser.unary())?;
             return Ok(Unary::new_node(uop, loc, expr));
         }
 
@@ -525,32 +662,46 @@ impl<'a> Parser<'a> {
     }
 
     fn subscript(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.call()?;
-        loop {
-            if matches!(self, self.current, TokenKind::LeftBracket(_, _)) {
-                expr = self.finish_bracket(expr)?;
-            } else {
-                break;
+        self.chained(|parser| {
+            let mut expr = parser.call()?;
+            loop {
+                let loc = get_tok_loc(&parser.current);
+                if matches!(parser, parser.current, TokenKind::LeftBracket(_, _)) {
+                    parser.link()?;
+                    expr = parser.finish_bracket(expr, loc?)?;
+                } else if std::matches!(parser.current, TokenKind::Dot(_, _)) {
+                    // there's no map type to have fields, values are only ever indexed
+                    return Err(parser.error(
+                        "there is no field access, index with '[]' instead",
+                        &parser.current,
+                    ));
+                } else {
+                    break;
+                }
             }
-        }
 
-        Ok(expr)
+            Ok(expr)
+        })
     }
 
     fn call(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.primary()?;
-        loop {
-            if matches!(self, self.current, TokenKind::LeftParen(_, _)) {
-                expr = self.finish_call(expr)?;
-            } else {
-                break;
+        self.chained(|parser| {
+            let mut expr = parser.primary()?;
+            loop {
+                let loc = get_tok_loc(&parser.current);
+                if matches!(parser, parser.current, TokenKind::LeftParen(_, _)) {
+                    parser.link()?;
+                    expr = parser.finish_call(expr, loc?)?;
+                } else {
+                    break;
+                }
             }
-        }
 
-        Ok(expr)
+            Ok(expr)
+        })
     }
 
-    fn finish_call(&mut self, callee: Box<Node>) -> ParseResult<Box<Node>> {
+    fn finish_call(&mut self, callee: Box<Node>, loc: (usize, usize)) -> ParseResult<Box<Node>> {
         let mut arguments = Vec::with_capacity(12);
         if !std::matches!(self.current, TokenKind::RightParen(_, _)) {
             loop {
@@ -563,7 +714,6 @@ impl<'a> Parser<'a> {
         }
 
         // println!("args: {:#?}", arguments);
-        println!("Current={:?}", self.current);
         consume!(
             self,
             "Expected ')'",
@@ -571,10 +721,10 @@ impl<'a> Parser<'a> {
             TokenKind::RightParen(_, _)
         );
 
-        Ok(Call::new_node(arguments, callee))
+        Ok(Call::new_node(arguments, callee, loc))
     }
 
-    fn finish_bracket(&mut self, value: Box<Node>) -> ParseResult<Box<Node>> {
+    fn finish_bracket(&mut self, value: Box<Node>, loc: (usize, usize)) -> ParseResult<Box<Node>> {
         let index = self.expr()?;
         consume!(
             self,
@@ -583,7 +733,7 @@ impl<'a> Parser<'a> {
             TokenKind::RightBracket(_, _)
         );
 
-        Ok(Subscript::new_node(index, value))
+        Ok(Subscript::new_node(index, value, loc))
     }
 
     fn primary(&mut self) -> ParseResult<Box<Node>> {
@@ -641,3 +791,93 @@ impl<'a> Parser<'a> {
         std::matches!(self.current, TokenKind::Eof)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> ParseResult<Vec<Node>> {
+        let source = source.to_owned();
+        let mut parser = Parser::new(Tokenizer::new(&source), &source)?;
+        parser.parse()?;
+        Ok(parser.declarations)
+    }
+
+    #[test]
+    fn dot_access_is_not_parsed() {
+        // there are no maps to access fields of, `a["b"]` is the only subscript
+        let err = parse("var a = [1];\nvar x = a.b;").unwrap_err();
+        assert!(
+            err.contains("2:11 there is no field access, index with '[]' instead"),
+            "{}",
+            err
+        );
+
+        let err = parse("var x = a[0].b;").unwrap_err();
+        assert!(err.contains("there is no field access"), "{}", err);
+
+        let declarations = parse("var x = a[\"b\"];").unwrap();
+        assert!(std::matches!(&declarations[..], [Node::VarDecl(_)]));
+    }
+
+    #[test]
+    fn else_if_nests_in_the_else_block() {
+        let declarations = parse("if a {\n} else if b {\n} else {\n}").unwrap();
+        let [Node::If(if_stmt)] = &declarations[..] else {
+            panic!("{:?}", declarations);
+        };
+
+        let Some(Node::Block(else_block)) = if_stmt.else_block.as_deref() else {
+            panic!("{:?}", if_stmt.else_block);
+        };
+        let [Node::If(else_if)] = &else_block.statements[..] else {
+            panic!("{:?}", else_block.statements);
+        };
+        assert!(std::matches!(
+            else_if.else_block.as_deref(),
+            Some(Node::Block(_))
+        ));
+    }
+
+    #[test]
+    fn long_else_if_chains_fail_cleanly() {
+        let source = format!("if a {{}}{}", " else if a {}".repeat(20));
+        let mut parser = Parser::new(Tokenizer::new(&source), &source).unwrap();
+        parser.set_max_depth(10);
+
+        let err = parser.parse().unwrap_err();
+        assert!(err.contains("too deeply nested"), "{}", err);
+    }
+
+    #[test]
+    fn blank_lines_add_no_statements() {
+        let declarations = parse("\n\n\nvar x = 1\n\n\n\nx = 2;\n\n;\n").unwrap();
+        assert!(std::matches!(
+            &declarations[..],
+            [Node::VarDecl(_), Node::ExprStmt(_)]
+        ));
+    }
+
+    #[test]
+    fn expressions_continue_on_the_next_line() {
+        let declarations = parse("var x = 1 +\n2\nvar y = [1,\n2]\nvar z = (1\n+ 2)").unwrap();
+        let [Node::VarDecl(x), Node::VarDecl(_), Node::VarDecl(_)] = &declarations[..] else {
+            panic!("{:?}", declarations);
+        };
+        assert!(std::matches!(*x.value, Node::Binary(_)));
+    }
+
+    #[test]
+    fn last_statement_needs_no_newline() {
+        for source in [
+            "var x = 1",
+            "x = 1",
+            "print(x)",
+            "if x {\n}",
+            "func f {\nret\n}",
+        ] {
+            let declarations = parse(source).unwrap();
+            assert_eq!(declarations.len(), 1, "{}", source);
+        }
+    }
+}
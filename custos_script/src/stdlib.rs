@@ -0,0 +1,271 @@
+use std::{fmt::Write, rc::Rc};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    bytecode::{BuiltInMethod, Constant},
+    vm::VirtualMachine,
+};
+
+/// Source of the current time for the time related built-ins, can be swapped out
+/// for a fixed clock to keep scripts deterministic.
+pub type Clock = Rc<dyn Fn() -> DateTime<Utc>>;
+
+/// Receives the messages scripts pass to `log`, provided by the host.
+pub type LogSink = Rc<dyn Fn(&str)>;
+
+/// Defines all the built-in methods that don't depend on the host.
+pub fn define_stdlib(vm: &mut VirtualMachine) {
+    vm.define_std_collections();
+    define_time(vm, Rc::new(Utc::now));
+    define_arrays(vm);
+    define_ids(vm);
+}
+
+/// Defines `now()` which returns the unix timestamp in seconds and
+/// `format_time(timestamp, format)` which formats a timestamp using the strftime syntax.
+pub fn define_time(vm: &mut VirtualMachine, clock: Clock) {
+    vm.define_built_in_fn(BuiltInMethod::new("now", 0, move |_| {
+        Ok(Constant::Number(clock().timestamp() as f64))
+    }));
+
+    vm.define_built_in_fn(BuiltInMethod::new("format_time", 2, |args| {
+        match (args.first(), args.get(1)) {
+            (Some(Constant::Number(timestamp)), Some(Constant::String(format))) => {
+                format_time(*timestamp, format)
+                    .map(|formatted| Constant::String(Rc::new(formatted)))
+                    .ok_or_else(|| {
+                        format!("invalid timestamp {} or format '{}'", timestamp, format)
+                    })
+            }
+            _ => Err("expected a number and a string".to_owned()),
+        }
+    }));
+}
+
+/// Defines `log(message)` which hands the message to the sink instead of any output the
+/// script is allowed to write to.
+pub fn define_log(vm: &mut VirtualMachine, sink: LogSink) {
+    vm.define_built_in_fn(BuiltInMethod::new("log", 1, move |args| {
+        if let Some(message) = args.first() {
+            sink(&message.get_string());
+        }
+
+        Ok(Constant::None)
+    }));
+}
+
+/// Defines `sort(array)` which returns a sorted copy of an array of numbers or strings,
+/// arrays with mixed or unorderable values are an error, and `unique(array)` which returns
+/// a copy without the duplicates, keeping the first occurrence of each value.
+pub fn define_arrays(vm: &mut VirtualMachine) {
+    vm.define_built_in_fn(BuiltInMethod::new("sort", 1, |args| match args.first() {
+        Some(Constant::Array(array)) => match sort(array) {
+            Some(sorted) => Ok(Constant::Array(Rc::new(sorted))),
+            None => Err("can only sort an array of numbers or an array of strings".to_owned()),
+        },
+        Some(value) => Err(format!(
+            "expected an array, got: {}",
+            value.get_pretty_type()
+        )),
+        None => Err("expected an array".to_owned()),
+    }));
+
+    vm.define_built_in_fn(BuiltInMethod::new("unique", 1, |args| match args.first() {
+        Some(Constant::Array(array)) => Ok(Constant::Array(Rc::new(unique(array)))),
+        Some(value) => Err(format!(
+            "expected an array, got: {}",
+            value.get_pretty_type()
+        )),
+        None => Err("expected an array".to_owned()),
+    }));
+}
+
+/// Defines `format_id(n)` which renders a number as an integer string usable in mentions,
+/// e.g. `"<@" + format_id(id) + ">"`.
+pub fn define_ids(vm: &mut VirtualMachine) {
+    vm.define_built_in_fn(BuiltInMethod::new("format_id", 1, |args| {
+        match args.first() {
+            Some(value) => value
+                .to_int_string()
+                .map(|id| Constant::String(Rc::new(id)))
+                .ok_or_else(|| format!("expected a whole non-negative number, got: {}", value)),
+            None => Err("expected a number".to_owned()),
+        }
+    }));
+}
+
+fn sort(array: &[Constant]) -> Option<Vec<Constant>> {
+    let mut sorted = array.to_vec();
+
+    if sorted.iter().all(|c| matches!(c, Constant::Number(_))) {
+        sorted.sort_by(|a, b| match (a, b) {
+            (Constant::Number(a), Constant::Number(b)) => a.total_cmp(b),
+            _ => unreachable!(),
+        });
+    } else if sorted.iter().all(|c| matches!(c, Constant::String(_))) {
+        sorted.sort_by(|a, b| match (a, b) {
+            (Constant::String(a), Constant::String(b)) => a.cmp(b),
+            _ => unreachable!(),
+        });
+    } else {
+        return None;
+    }
+
+    Some(sorted)
+}
+
+fn unique(array: &[Constant]) -> Vec<Constant> {
+    let mut unique: Vec<Constant> = Vec::with_capacity(array.len());
+
+    for value in array {
+        if !unique.contains(value) {
+            unique.push(value.clone());
+        }
+    }
+
+    unique
+}
+
+fn format_time(timestamp: f64, format: &str) -> Option<String> {
+    let time = DateTime::<Utc>::from_timestamp(timestamp as i64, 0)?;
+
+    // writing instead of `to_string()` because an invalid format panics in `Display`
+    let mut formatted = String::new();
+    write!(formatted, "{}", time.format(format)).ok()?;
+    Some(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        bytecode::{Function, FunctionType},
+        compile,
+    };
+
+    fn interpreted(source: &str, define: impl FnOnce(&mut VirtualMachine)) -> VirtualMachine {
+        let chunk = compile(source).unwrap();
+        let mut vm =
+            VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+        define(&mut vm);
+        assert_eq!(vm.interpret(), None);
+        vm
+    }
+
+    fn string(s: &str) -> Constant {
+        Constant::String(Rc::new(s.to_owned()))
+    }
+
+    #[test]
+    fn now_reads_the_clock() {
+        let fixed = Utc.with_ymd_and_hms(2023, 4, 5, 6, 7, 8).unwrap();
+        let mut vm = interpreted(
+            "func stamp {\nret now()\n}\nfunc day {\nret format_time(now(), \"%Y-%m-%d %H:%M\")\n}",
+            |vm| define_time(vm, Rc::new(move || fixed)),
+        );
+
+        assert_eq!(
+            vm.run_function("stamp", vec![]),
+            Ok(Constant::Number(fixed.timestamp() as f64))
+        );
+        assert_eq!(
+            vm.run_function("day", vec![]),
+            Ok(string("2023-04-05 06:07"))
+        );
+    }
+
+    #[test]
+    fn format_time_rejects_bad_input() {
+        let mut vm = interpreted("func fmt(t, f) {\nret format_time(t, f)\n}", |vm| {
+            define_time(vm, Rc::new(Utc::now))
+        });
+
+        let err = vm
+            .run_function("fmt", vec![Constant::Number(0.0), string("%Q")])
+            .unwrap_err();
+        assert!(
+            err.contains("invalid timestamp 0 or format '%Q'"),
+            "{}",
+            err
+        );
+
+        let err = vm
+            .run_function("fmt", vec![string("0"), string("%Y")])
+            .unwrap_err();
+        assert!(err.contains("expected a number and a string"), "{}", err);
+    }
+
+    fn array(values: Vec<Constant>) -> Constant {
+        Constant::Array(Rc::new(values))
+    }
+
+    fn numbers(values: &[f64]) -> Constant {
+        array(values.iter().map(|n| Constant::Number(*n)).collect())
+    }
+
+    const SORTED: &str = "func sorted(a) {\nret sort(a)\n}\nfunc deduped(a) {\nret unique(a)\n}";
+
+    #[test]
+    fn sort_orders_numbers_and_strings() {
+        let mut vm = interpreted(SORTED, define_arrays);
+
+        assert_eq!(
+            vm.run_function("sorted", vec![numbers(&[3.0, -1.0, 2.5, 2.0])]),
+            Ok(numbers(&[-1.0, 2.0, 2.5, 3.0]))
+        );
+        assert_eq!(
+            vm.run_function(
+                "sorted",
+                vec![array(vec![string("b"), string("ab"), string("a")])]
+            ),
+            Ok(array(vec![string("a"), string("ab"), string("b")]))
+        );
+        assert_eq!(
+            vm.run_function("sorted", vec![array(vec![])]),
+            Ok(array(vec![]))
+        );
+    }
+
+    #[test]
+    fn sort_rejects_mixed_and_unorderable_arrays() {
+        let mut vm = interpreted(SORTED, define_arrays);
+
+        for unsortable in [
+            array(vec![Constant::Number(1.0), string("a")]),
+            array(vec![Constant::Bool(true), Constant::Bool(false)]),
+            array(vec![numbers(&[1.0]), numbers(&[0.0])]),
+        ] {
+            let err = vm.run_function("sorted", vec![unsortable]).unwrap_err();
+            assert!(
+                err.contains("can only sort an array of numbers or an array of strings"),
+                "{}",
+                err
+            );
+        }
+
+        let err = vm
+            .run_function("sorted", vec![Constant::Number(1.0)])
+            .unwrap_err();
+        assert!(err.contains("expected an array, got:"), "{}", err);
+    }
+
+    #[test]
+    fn unique_keeps_the_first_occurrences() {
+        let mut vm = interpreted(SORTED, define_arrays);
+
+        assert_eq!(
+            vm.run_function("deduped", vec![numbers(&[3.0, 1.0, 3.0, 2.0, 1.0])]),
+            Ok(numbers(&[3.0, 1.0, 2.0]))
+        );
+
+        // unlike sorting, mixed arrays are fine
+        let mixed = array(vec![string("1"), Constant::Number(1.0), string("1")]);
+        assert_eq!(
+            vm.run_function("deduped", vec![mixed]),
+            Ok(array(vec![string("1"), Constant::Number(1.0)]))
+        );
+    }
+}
@@ -24,6 +24,7 @@ pub enum TokenKind {
     Minus(usize, usize),
     Star(usize, usize),
     Slash(usize, usize),
+    Percent(usize, usize),
     True(usize, usize),
     False(usize, usize),
     Bang(usize, usize),
@@ -37,6 +38,7 @@ pub enum TokenKind {
     Or(usize, usize),
     Equal(usize, usize),
     For(usize, usize),
+    While(usize, usize),
     In(usize, usize),
     If(usize, usize),
     Else(usize, usize),
@@ -71,6 +73,7 @@ pub fn get_tok_loc(token: &TokenKind) -> Result<(usize, usize), String> {
         TokenKind::Minus(a, b) => (*a, *b),
         TokenKind::Star(a, b) => (*a, *b),
         TokenKind::Slash(a, b) => (*a, *b),
+        TokenKind::Percent(a, b) => (*a, *b),
         TokenKind::True(a, b) => (*a, *b),
         TokenKind::False(a, b) => (*a, *b),
         TokenKind::Bang(a, b) => (*a, *b),
@@ -84,6 +87,7 @@ pub fn get_tok_loc(token: &TokenKind) -> Result<(usize, usize), String> {
         TokenKind::And(a, b) => (*a, *b),
         TokenKind::Or(a, b) => (*a, *b),
         TokenKind::For(a, b) => (*a, *b),
+        TokenKind::While(a, b) => (*a, *b),
         TokenKind::In(a, b) => (*a, *b),
         TokenKind::If(a, b) => (*a, *b),
         TokenKind::Else(a, b) => (*a, *b),
@@ -122,6 +126,7 @@ pub fn get_tok_len(token: &TokenKind) -> usize {
         TokenKind::Minus(_, _) => 1,
         TokenKind::Star(_, _) => 1,
         TokenKind::Slash(_, _) => 1,
+        TokenKind::Percent(_, _) => 1,
         TokenKind::True(_, _) => 4,
         TokenKind::False(_, _) => 5,
         TokenKind::Bang(_, _) => 1,
@@ -135,6 +140,7 @@ pub fn get_tok_len(token: &TokenKind) -> usize {
         TokenKind::And(_, _) => 2,
         TokenKind::Or(_, _) => 2,
         TokenKind::For(_, _) => 3,
+        TokenKind::While(_, _) => 5,
         TokenKind::In(_, _) => 2,
         TokenKind::If(_, _) => 2,
         TokenKind::Else(_, _) => 4,
@@ -170,12 +176,33 @@ fn is_alpha_opt(c: Option<char>) -> bool {
     false
 }
 
+/// Whether a statement can end with the token, a new line after such a token
+/// terminates the statement like a `;` would.
+fn can_end_statement(token: &TokenKind) -> bool {
+    std::matches!(
+        token,
+        TokenKind::IdenLiteral(..)
+            | TokenKind::NumberLiteral(..)
+            | TokenKind::StrLiteral(..)
+            | TokenKind::True(..)
+            | TokenKind::False(..)
+            | TokenKind::None(..)
+            | TokenKind::RightParen(..)
+            | TokenKind::RightBracket(..)
+            | TokenKind::Ret(..)
+    )
+}
+
 pub struct Tokenizer<'a> {
     current: usize,
     start: usize,
     line: usize,
     column: usize,
     source: &'a String,
+    /// How many parentheses and brackets are open, new lines inside them never end a statement.
+    nesting: usize,
+    /// Whether the last token can end a statement.
+    ends_statement: bool,
 }
 
 impl<'a> Tokenizer<'a> {
@@ -186,6 +213,8 @@ impl<'a> Tokenizer<'a> {
             line: 1,
             column: 1,
             source,
+            nesting: 0,
+            ends_statement: false,
         }
     }
 
@@ -227,37 +256,89 @@ impl<'a> Tokenizer<'a> {
         self.source.chars().nth(self.current + 1)
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skips whitespace and comments. Returns true if a block comment spanned a new line
+    /// that ends the statement before it, `next` turns that into a delimiter.
+    fn skip_whitespace(&mut self) -> Result<bool, String> {
         loop {
             let c = match self.peek() {
                 Some(c) => c,
-                None => return,
+                None => return Ok(false),
             };
             match c {
-                ' ' | '\r' | '\t' | '\n' => {
+                ' ' | '\r' | '\t' => {
+                    self.advance();
+                }
+                // the new line is left for `next` to turn into a delimiter
+                '\n' if self.nesting == 0 && self.ends_statement => return Ok(false),
+                '\n' => {
                     self.advance();
+                    self.line += 1;
+                    self.column = 1;
                 }
-                '/' => {
-                    if self.peek_next() == Some('/') {
+                '/' => match self.peek_next() {
+                    Some('/') => {
                         while self.peek() != Some('\n') && !self.is_at_end() {
                             self.advance();
                         }
-                    } else {
-                        return;
                     }
+                    Some('*') => {
+                        if self.block_comment()? && self.nesting == 0 && self.ends_statement {
+                            return Ok(true);
+                        }
+                    }
+                    _ => return Ok(false),
+                },
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Skips a `/* */` comment, block comments can be nested so `/* a /* b */ c */` is a
+    /// single comment. Returns whether the comment spanned a new line.
+    fn block_comment(&mut self) -> Result<bool, String> {
+        let (line, column) = (self.line, self.column);
+        let mut depth = 0;
+        let mut spans_lines = false;
+
+        loop {
+            match (self.peek(), self.peek_next()) {
+                (Some('/'), Some('*')) => {
+                    self.advance();
+                    self.advance();
+                    depth += 1;
+                }
+                (Some('*'), Some('/')) => {
+                    self.advance();
+                    self.advance();
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(spans_lines);
+                    }
+                }
+                (Some('\n'), _) => {
+                    self.advance();
+                    self.line += 1;
+                    self.column = 1;
+                    spans_lines = true;
+                }
+                (Some(_), _) => {
+                    self.advance();
+                }
+                (None, _) => {
+                    return Err(format!("Unterminated block comment at {}:{}", line, column))
                 }
-                _ => return,
             }
         }
     }
 
+    /// Strings may span multiple lines, the new lines are kept in the string.
     fn string(&mut self) -> Result<TokenKind, String> {
         while self.peek() != Some('"') && !self.is_at_end() {
-            if self.peek() == Some('\n') {
+            // the column is reset after the new line is consumed, like in `skip_whitespace`
+            if self.advance() == Some('\n') {
                 self.line += 1;
                 self.column = 1;
             }
-            self.advance();
         }
 
         if self.is_at_end() {
@@ -319,6 +400,7 @@ impl<'a> Tokenizer<'a> {
             "true" => return TokenKind::True(self.line, self.column),
             "false" => return TokenKind::False(self.line, self.column),
             "for" => return TokenKind::For(self.line, self.column),
+            "while" => return TokenKind::While(self.line, self.column),
             "in" => return TokenKind::In(self.line, self.column),
             "if" => return TokenKind::If(self.line, self.column),
             "else" => return TokenKind::Else(self.line, self.column),
@@ -336,12 +418,16 @@ impl<'a> Tokenizer<'a> {
         let current = self.current;
         let line = self.line;
         let column = self.column;
+        let nesting = self.nesting;
+        let ends_statement = self.ends_statement;
 
         let item = self.next();
         self.start = start;
         self.current = current;
         self.line = line;
         self.column = column;
+        self.nesting = nesting;
+        self.ends_statement = ends_statement;
 
         item
     }
@@ -351,7 +437,38 @@ impl<'a> Iterator for Tokenizer<'a> {
     type Item = Result<TokenKind, String>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.skip_whitespace();
+        let token = self.scan_token();
+
+        match &token {
+            Some(Ok(kind)) => {
+                match kind {
+                    TokenKind::LeftParen(..) | TokenKind::LeftBracket(..) => self.nesting += 1,
+                    TokenKind::RightParen(..) | TokenKind::RightBracket(..) => {
+                        self.nesting = self.nesting.saturating_sub(1)
+                    }
+                    _ => (),
+                }
+                self.ends_statement = can_end_statement(kind);
+            }
+            // the last statement doesn't need a new line or a `;` after it
+            None if self.ends_statement => {
+                self.ends_statement = false;
+                return Some(Ok(TokenKind::ExprDelimiter(self.line, self.column)));
+            }
+            _ => (),
+        }
+
+        token
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    fn scan_token(&mut self) -> Option<Result<TokenKind, String>> {
+        match self.skip_whitespace() {
+            Ok(true) => return Some(Ok(TokenKind::ExprDelimiter(self.line, self.column))),
+            Ok(false) => (),
+            Err(err) => return Some(Err(err)),
+        }
         self.start = self.current;
         if self.is_at_end() {
             return None;
@@ -389,6 +506,7 @@ impl<'a> Iterator for Tokenizer<'a> {
             '-' => TokenKind::Minus(self.line, self.column),
             '*' => TokenKind::Star(self.line, self.column),
             '/' => TokenKind::Slash(self.line, self.column),
+            '%' => TokenKind::Percent(self.line, self.column),
             '"' => return Some(self.string()),
             '!' => {
                 if self.matches('=') {
@@ -425,14 +543,182 @@ impl<'a> Iterator for Tokenizer<'a> {
                     return None;
                 }
             }
-            '&' => TokenKind::And(self.line, self.column),
+            '&' => {
+                // a single `&` has always been accepted, `&&` mirrors `||`
+                self.matches('&');
+                TokenKind::And(self.line, self.column)
+            }
             ';' => TokenKind::ExprDelimiter(self.line, self.column),
-            // '\n' => {
-            //     self.line += 1;
-            //     self.column = 1;
-            //     TokenKind::ExprDelimiter(self.line, self.column)
-            // }
+            '\n' => {
+                let delimiter = TokenKind::ExprDelimiter(self.line, self.column);
+                self.line += 1;
+                self.column = 1;
+                delimiter
+            }
             _ => return None,
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TokenKind::*;
+
+    fn tokenize(source: &str) -> Vec<TokenKind> {
+        let source = source.to_owned();
+        Tokenizer::new(&source)
+            .collect::<Result<Vec<TokenKind>, String>>()
+            .unwrap()
+    }
+
+    // the line and column of a token point just past its last character
+
+    #[test]
+    fn operators_and_delimiters() {
+        assert_eq!(
+            tokenize("( ) { } [ ] : := , . + - * / % ! != > >= < <= == = && || ;"),
+            vec![
+                LeftParen(1, 2),
+                RightParen(1, 4),
+                LeftBrace(1, 6),
+                RightBrace(1, 8),
+                LeftBracket(1, 10),
+                RightBracket(1, 12),
+                Colon(1, 14),
+                ColonEq(1, 17),
+                Comma(1, 19),
+                Dot(1, 21),
+                Plus(1, 23),
+                Minus(1, 25),
+                Star(1, 27),
+                Slash(1, 29),
+                Percent(1, 31),
+                Bang(1, 33),
+                NotEqual(1, 36),
+                Greater(1, 38),
+                GreaterEq(1, 41),
+                Less(1, 43),
+                LessEq(1, 46),
+                EqualEqual(1, 49),
+                Equal(1, 51),
+                And(1, 54),
+                Or(1, 57),
+                ExprDelimiter(1, 59),
+            ]
+        );
+    }
+
+    #[test]
+    fn keywords_and_identifiers() {
+        assert_eq!(
+            tokenize("func ret true false for while in if else var none foo_1"),
+            vec![
+                Func(1, 5),
+                Ret(1, 9),
+                True(1, 14),
+                False(1, 20),
+                For(1, 24),
+                While(1, 30),
+                In(1, 33),
+                If(1, 36),
+                Else(1, 41),
+                Var(1, 45),
+                None(1, 50),
+                IdenLiteral("foo_1".to_owned(), 1, 56),
+                ExprDelimiter(1, 56),
+            ]
+        );
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(
+            tokenize("12 3.5 4. \"hi\""),
+            vec![
+                NumberLiteral("12".to_owned(), 1, 3),
+                NumberLiteral("3.5".to_owned(), 1, 7),
+                NumberLiteral("4".to_owned(), 1, 9),
+                Dot(1, 10),
+                StrLiteral("hi".to_owned(), 1, 15),
+                ExprDelimiter(1, 15),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_ampersand_is_and() {
+        assert_eq!(tokenize("&"), vec![And(1, 2)]);
+    }
+
+    #[test]
+    fn multi_line_positions() {
+        let source = "var add = func(a, b) {\n    ret a + b\n}\nvar s = \"two\nlines\" // comment\nprint(add(\n  1,\n  2.5\n))\n";
+
+        assert_eq!(
+            tokenize(source),
+            vec![
+                Var(1, 4),
+                IdenLiteral("add".to_owned(), 1, 8),
+                Equal(1, 10),
+                Func(1, 15),
+                LeftParen(1, 16),
+                IdenLiteral("a".to_owned(), 1, 17),
+                Comma(1, 18),
+                IdenLiteral("b".to_owned(), 1, 20),
+                RightParen(1, 21),
+                LeftBrace(1, 23),
+                Ret(2, 8),
+                IdenLiteral("a".to_owned(), 2, 10),
+                Plus(2, 12),
+                IdenLiteral("b".to_owned(), 2, 14),
+                ExprDelimiter(2, 15),
+                RightBrace(3, 2),
+                Var(4, 4),
+                IdenLiteral("s".to_owned(), 4, 6),
+                Equal(4, 8),
+                StrLiteral("two\nlines".to_owned(), 5, 7),
+                ExprDelimiter(5, 19),
+                IdenLiteral("print".to_owned(), 6, 6),
+                LeftParen(6, 7),
+                IdenLiteral("add".to_owned(), 6, 10),
+                LeftParen(6, 11),
+                NumberLiteral("1".to_owned(), 7, 4),
+                Comma(7, 5),
+                NumberLiteral("2.5".to_owned(), 8, 6),
+                RightParen(9, 2),
+                RightParen(9, 3),
+                ExprDelimiter(9, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn block_comment_ends_statement() {
+        assert_eq!(
+            tokenize("a /* one\n/* two */ */ b"),
+            vec![
+                IdenLiteral("a".to_owned(), 1, 2),
+                ExprDelimiter(2, 13),
+                IdenLiteral("b".to_owned(), 2, 15),
+                ExprDelimiter(2, 15),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let source = "\"open".to_owned();
+        assert!(Tokenizer::new(&source).next().unwrap().is_err());
+
+        let source = "/* open".to_owned();
+        assert!(Tokenizer::new(&source).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn eof_has_no_location() {
+        assert!(get_tok_loc(&Eof).is_err());
+        assert_eq!(get_tok_len(&Eof), 0);
+        assert_eq!(get_tok_loc(&Var(3, 4)), Ok((3, 4)));
+    }
+}
@@ -3,27 +3,121 @@ use crate::{
     prelude::BuiltInMethod,
 };
 use std::{
+    cell::RefCell,
     collections::{HashMap, VecDeque},
-    rc::Rc,
+    fmt, io,
+    rc::{Rc, Weak},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
 pub enum CallResult {
     Ok,
     OkNative,
+    /// The arguments didn't match the arity or a built-in method failed, holds the VM error.
+    ErrMessage(String),
+    /// Calling the function would exceed the frame limit.
+    StackOverflow,
     Err,
 }
 
+/// How many calls may be nested by default, see `VirtualMachine::set_frame_limit`.
+pub const DEFAULT_FRAME_LIMIT: usize = 256;
+
+/// Where `print` and `println` write to, shared between the VM and the built-ins.
+#[derive(Clone)]
+pub struct Output(Rc<RefCell<Box<dyn io::Write>>>);
+
+impl Output {
+    pub fn new(writer: impl io::Write + 'static) -> Self {
+        Output(Rc::new(RefCell::new(Box::new(writer))))
+    }
+
+    fn write(&self, text: &str) -> Result<(), String> {
+        let mut writer = self.0.borrow_mut();
+        writer
+            .write_all(text.as_bytes())
+            .and_then(|_| writer.flush())
+            .map_err(|err| format!("failed to write the output: {}", err))
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Output")
+    }
+}
+
+/// Cancels a running VM from another thread, the VM halts with an error before
+/// its next instruction. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationHandle(Arc<AtomicBool>);
+
+impl CancellationHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Pops the top of the stack or returns a stack underflow error from `interpret`, a chunk
+/// from the compiler never underflows but hand-assembled ones can.
+macro_rules! pop {
+    ($self: ident) => {
+        match $self.stack.pop_back() {
+            Some(value) => value,
+            None => return Some($self.error("stack underflow")),
+        }
+    };
+}
+
+/// Byte offsets of the chars of the string indexed last, so indexing the same string again
+/// (e.g. `s[i]` in a loop) doesn't walk it from the start every time. The `Weak` keeps the
+/// allocation from being reused and `Rc::make_mut` moves a string out of an allocation that
+/// has one, so a string that was changed never matches.
+#[derive(Debug)]
+struct CharOffsets {
+    string: Weak<String>,
+    offsets: Vec<usize>,
+}
+
 #[derive(Debug)]
 pub struct VirtualMachine {
     stack: VecDeque<Constant>,
     globals: HashMap<String, Constant>,
     frames: Vec<CallFrame>,
+    frame_limit: usize,
+    instruction_limit: Option<usize>,
+    instructions_executed: usize,
+    debug_trace: bool,
+    output: Output,
+    cancellation: CancellationHandle,
+    char_offsets: Option<CharOffsets>,
 }
 
 impl VirtualMachine {
     pub fn new(script: Function) -> Self {
+        Self::with_tracing(script, false)
+    }
+
+    /// With `debug_trace` the stack and every instruction are printed as they're executed.
+    pub fn with_tracing(script: Function, debug_trace: bool) -> Self {
         let mut frames = Vec::with_capacity(8);
+        let mut stack = VecDeque::with_capacity(256);
 
+        // the script occupies slot 0 just like a called function does, so locals declared
+        // in top-level blocks resolve to the same slots as they do inside of functions.
+        let script = Rc::new(script);
+        stack.push_back(Constant::Function(Rc::clone(&script)));
         frames.push(CallFrame {
             ip: 0,
             function: script,
@@ -32,33 +126,110 @@ impl VirtualMachine {
 
         VirtualMachine {
             frames,
-            stack: VecDeque::with_capacity(256),
+            stack,
+            frame_limit: DEFAULT_FRAME_LIMIT,
             globals: HashMap::with_capacity(32),
+            instruction_limit: None,
+            instructions_executed: 0,
+            debug_trace,
+            output: Output::new(io::stdout()),
+            cancellation: CancellationHandle::new(),
+            char_offsets: None,
         }
     }
 
+    /// Limits the amount of instructions the VM may execute before halting with an error,
+    /// used to sandbox untrusted scripts.
+    pub fn set_instruction_limit(&mut self, limit: usize) {
+        self.instruction_limit = Some(limit);
+    }
+
+    /// Limits how many calls may be nested, the script included. Deeper recursion halts the VM
+    /// with a stack overflow error instead of growing the frames without bound.
+    pub fn set_frame_limit(&mut self, limit: usize) {
+        self.frame_limit = limit;
+    }
+
+    /// A handle that cancels this VM, can be moved to another thread.
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.cancellation.clone()
+    }
+
+    /// Makes the VM stop when the handle is cancelled, so one handle can cancel many VMs.
+    pub fn set_cancellation_handle(&mut self, handle: CancellationHandle) {
+        self.cancellation = handle;
+    }
+
+    /// Sets where `print` and `println` write to, stdout by default. Only affects the
+    /// built-ins defined by `define_std_io` after this call.
+    pub fn set_output(&mut self, writer: impl io::Write + 'static) {
+        self.output = Output::new(writer);
+    }
+
+    /// Defines `print(value)` and `println(value)` which write the value to the output.
+    pub fn define_std_io(&mut self) {
+        let output = self.output.clone();
+        self.define_built_in_fn(BuiltInMethod::new("print", 1, move |args| {
+            let text = args.first().map(Constant::get_string).unwrap_or_default();
+            output.write(&text).map(|_| Constant::None)
+        }));
+
+        let output = self.output.clone();
+        self.define_built_in_fn(BuiltInMethod::new("println", 1, move |args| {
+            let mut text = args.first().map(Constant::get_string).unwrap_or_default();
+            text.push('\n');
+            output.write(&text).map(|_| Constant::None)
+        }));
+    }
+
+    /// Defines `len(value)` which returns the length of a string (in chars) or an array.
+    pub fn define_std_collections(&mut self) {
+        self.define_built_in_fn(BuiltInMethod::new("len", 1, |args| match args.first() {
+            Some(value) => value
+                .get_len()
+                .map(|len| Constant::Number(len as f64))
+                .ok_or_else(|| {
+                    format!(
+                        "expected a string or an array, got: {}",
+                        value.get_pretty_type()
+                    )
+                }),
+            None => Err("expected a string or an array".to_owned()),
+        }));
+    }
+
     pub fn define_built_in_fn(&mut self, method: BuiltInMethod) {
         self.globals
             .insert(method.name.to_owned(), Constant::BuiltInMethod(method));
     }
 
+    /// The stack as it's traced, every value rendered with `Display` like in error messages,
+    /// e.g. `stack: [fn <'' 0>] [1] [[1, 2]]`. `None` if the stack is empty.
+    pub fn format_stack(&self) -> Option<String> {
+        if self.stack.is_empty() {
+            return None;
+        }
+
+        let values = self
+            .stack
+            .iter()
+            .map(|constant| format!("[{}]", constant))
+            .collect::<Vec<String>>();
+        Some(format!("stack: {}", values.join(" ")))
+    }
+
     pub fn print_stack(&self) {
-        if !self.stack.is_empty() {
-            print!("stack: ");
-            for constant in &self.stack {
-                if let Constant::Function(func) = constant {
-                    print!("fn '{}'", func.name);
-                } else {
-                    print!("[{constant:?}] ");
-                }
-            }
-            println!()
+        if let Some(stack) = self.format_stack() {
+            println!("{}", stack);
         }
     }
 
     fn error(&self, message: &str) -> String {
-        let frame = self.frames.last().unwrap();
-        self.error_ip(message, frame.ip)
+        match self.frames.last() {
+            Some(frame) => self.error_ip(message, frame.ip),
+            // outside of any function, e.g. a bad call from `run_function`
+            None => format!("VMerror: {message}"),
+        }
     }
 
     fn error_ip(&self, message: &str, ip: usize) -> String {
@@ -72,14 +243,56 @@ impl VirtualMachine {
         )
     }
 
-    fn call_value(&mut self, constant: Constant, arg_count: u8) -> CallResult {
+    /// `s = s + x` still holds the string in `s` while `Add` runs, so it could never append in
+    /// place. The variable is overwritten by the instruction right after `Add`, when it holds
+    /// the same string its copy is dropped early.
+    fn release_assigned_string(&mut self, string: &Rc<String>) {
+        let frame = self.frames.last().unwrap();
+        let target = match frame.function.chunk.code.get(frame.ip + 1) {
+            Some(Instruction::SetLocal(index)) => self.stack.get_mut(frame.slot_offset + *index),
+            Some(Instruction::SetGlobal(name)) => self.globals.get_mut(name),
+            _ => None,
+        };
+
+        if let Some(value) = target {
+            if matches!(value, Constant::String(s) if Rc::ptr_eq(s, string)) {
+                *value = Constant::None;
+            }
+        }
+    }
+
+    /// The char at the index, strings are indexed by char to match `Constant::get_len`.
+    fn char_at(&mut self, string: &Rc<String>, index: usize) -> Option<char> {
+        let cached = self
+            .char_offsets
+            .as_ref()
+            .is_some_and(|cache| Weak::ptr_eq(&cache.string, &Rc::downgrade(string)));
+
+        if !cached {
+            self.char_offsets = Some(CharOffsets {
+                string: Rc::downgrade(string),
+                offsets: string.char_indices().map(|(offset, _)| offset).collect(),
+            });
+        }
+
+        let offset = *self.char_offsets.as_ref()?.offsets.get(index)?;
+        string[offset..].chars().next()
+    }
+
+    /// Pushes the frame of a script function or calls a built-in. A tail call replaces the
+    /// caller's frame right after, so it's never refused by the frame limit.
+    fn call_value(&mut self, constant: Constant, arg_count: u8, is_tail_call: bool) -> CallResult {
         match constant {
             Constant::Function(func) => {
                 if func.arity != arg_count {
-                    self.error(&format!(
+                    return CallResult::ErrMessage(self.error(&format!(
                         "Function '{}' accepts {} arguments but {} were provided.",
                         func.name, func.arity, arg_count
-                    ));
+                    )));
+                }
+
+                if !is_tail_call && self.frames.len() >= self.frame_limit {
+                    return CallResult::StackOverflow;
                 }
 
                 let frame = CallFrame {
@@ -93,25 +306,28 @@ impl VirtualMachine {
             }
             Constant::BuiltInMethod(func) => {
                 if func.arity != 0 && func.arity != arg_count {
-                    self.error(&format!(
+                    return CallResult::ErrMessage(self.error(&format!(
                         "Function '{}' accepts {} arguments but {} were provided.",
                         func.name, func.arity, arg_count
-                    ));
+                    )));
                 }
 
-                println!("Arg count: {}", arg_count);
-
                 let removed = self
                     .stack
                     .range(self.stack.len() - arg_count as usize..)
                     .map(|c| c.to_owned())
                     .collect::<Vec<Constant>>();
 
-                println!("Built-in method called: {}", func.name);
-
                 // let result = func.func(removed);
                 let callable = func.func;
-                let result = callable(removed);
+                let result = match callable(removed) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        return CallResult::ErrMessage(
+                            self.error(&format!("'{}' failed: {}", func.name, err)),
+                        )
+                    }
+                };
 
                 // println!(
                 //     "result: {:#?}, stack before: {}, stack after: {}",
@@ -128,39 +344,112 @@ impl VirtualMachine {
         }
     }
 
-    fn peek_back(&self) -> &Constant {
-        self.stack.back().expect("VMError: failed to peek_back")
+    /// Only two numbers or two strings can be ordered, an error for anything else.
+    fn check_comparable(&self, a: &Constant, b: &Constant) -> Option<String> {
+        match (a, b) {
+            (Constant::Number(_), Constant::Number(_))
+            | (Constant::String(_), Constant::String(_)) => None,
+            _ => Some(self.error(&format!(
+                "cannot compare {} with {}",
+                a.get_pretty_type(),
+                b.get_pretty_type()
+            ))),
+        }
     }
 
-    fn peek(&self, distance: usize) -> &Constant {
-        return self
-            .stack
-            .get(self.stack.len() - 1 - distance)
-            .expect("Failed to peek");
+    fn peek(&self, distance: usize) -> Option<&Constant> {
+        self.stack
+            .len()
+            .checked_sub(distance + 1)
+            .and_then(|index| self.stack.get(index))
     }
 
+    /// Runs the script, the globals it defines stay around for `run_function`.
     pub fn interpret(&mut self) -> Option<String> {
+        self.run(0)
+    }
+
+    /// Calls the function in the global with the arguments and runs it to completion,
+    /// returns what it returned. Usually called after `interpret` defined the functions.
+    pub fn run_function(&mut self, name: &str, args: Vec<Constant>) -> Result<Constant, String> {
+        let function = match self.globals.get(name) {
+            Some(function) => function.clone(),
+            None => return Err(self.error(&format!("no global with name '{}' exists", name))),
+        };
+        let arg_count = u8::try_from(args.len())
+            .map_err(|_| self.error(&format!("too many arguments, {} exceeds 255", args.len())))?;
+
+        let depth = self.frames.len();
+        let base = self.stack.len();
+        self.stack.push_back(function.clone());
+        self.stack.extend(args);
+
+        let result = match self.call_value(function, arg_count, false) {
+            CallResult::Ok => match self.run(depth) {
+                Some(err) => Err(err),
+                None => Ok(()),
+            },
+            CallResult::OkNative => Ok(()),
+            CallResult::ErrMessage(err) => Err(err),
+            CallResult::StackOverflow => Err(self.error("stack overflow")),
+            CallResult::Err => Err(self.error(&format!("'{}' is not a function", name))),
+        };
+
+        // a failed call leaves its frames and values behind, the VM is reusable after it
+        if let Err(err) = result {
+            self.frames.truncate(depth);
+            self.stack.truncate(base);
+            return Err(err);
+        }
+
+        Ok(self.stack.pop_back().unwrap_or(Constant::None))
+    }
+
+    /// Executes until a `Return` leaves `return_depth` frames, the returned value is left on
+    /// the stack unless all frames are gone.
+    fn run(&mut self, return_depth: usize) -> Option<String> {
         loop {
+            if self.cancellation.is_cancelled() {
+                return Some(self.error("the script was cancelled"));
+            }
+
+            if let Some(limit) = self.instruction_limit {
+                if self.instructions_executed >= limit {
+                    return Some(self.error(&format!("instruction limit of {} exceeded", limit)));
+                }
+                self.instructions_executed += 1;
+            }
+
             let frame = self.frames.last().unwrap();
             let ins = &frame.function.chunk[frame.ip];
             let line = &frame.function.chunk.lines[frame.ip];
 
-            self.print_stack();
-            ins.print_ins(line, Some(&self.stack));
+            if self.debug_trace {
+                self.print_stack();
+                ins.print_ins(line, Some(&self.stack));
+            }
 
             match ins {
                 Instruction::Constant(constant) => {
                     self.stack.push_back(constant.clone());
                 }
                 Instruction::Add => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
 
                     if matches!(a, Constant::String(_)) || matches!(b, Constant::String(_)) {
-                        let mut a = a.get_string();
-                        let b = b.get_string();
+                        let mut a = match a {
+                            Constant::String(s) => s,
+                            other => Rc::new(other.get_string()),
+                        };
+                        self.release_assigned_string(&a);
 
-                        a.push_str(&b);
+                        // appends in place when nothing else holds the left-hand side string
+                        let buffer = Rc::make_mut(&mut a);
+                        match &b {
+                            Constant::String(s) => buffer.push_str(s),
+                            other => buffer.push_str(&other.get_string()),
+                        }
 
                         self.stack.push_back(Constant::String(a));
                     } else {
@@ -188,8 +477,8 @@ impl VirtualMachine {
                     }
                 }
                 Instruction::Subtract => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
 
                     let rhs = match b {
                         Constant::Number(number) => number,
@@ -214,8 +503,8 @@ impl VirtualMachine {
                     self.stack.push_back(Constant::Number(lhs - rhs));
                 }
                 Instruction::Divide => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
 
                     let rhs = match b {
                         Constant::Number(number) => {
@@ -244,9 +533,42 @@ impl VirtualMachine {
 
                     self.stack.push_back(Constant::Number(lhs / rhs));
                 }
+                Instruction::Modulo => {
+                    let b = pop!(self);
+                    let a = pop!(self);
+
+                    let rhs = match b {
+                        Constant::Number(number) => {
+                            if number == 0.0 {
+                                return Some(
+                                    self.error("cannot take the remainder of a division by zero"),
+                                );
+                            }
+                            number
+                        }
+                        _ => {
+                            return Some(self.error(&format!(
+                            "cannot take the remainder of non-numbers, right-hand side is not a number but a {}",
+                            b.get_pretty_type()
+                        )))
+                        }
+                    };
+
+                    let lhs = match a {
+                        Constant::Number(number) => number,
+                        _ => {
+                            return Some(self.error(&format!(
+                            "cannot take the remainder of non-numbers, left-hand side is not a number but a {}",
+                            a.get_pretty_type()
+                        )))
+                        }
+                    };
+
+                    self.stack.push_back(Constant::Number(lhs % rhs));
+                }
                 Instruction::Multiply => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
 
                     let rhs = match b {
                         Constant::Number(number) => {
@@ -283,13 +605,14 @@ impl VirtualMachine {
                     }
                 }
                 Instruction::DefineGlobal(name) => {
-                    let value = self.peek_back().clone();
-
-                    self.globals.insert(name.to_owned(), value.clone());
-                    self.stack.pop_back(); // we pop the value that we `peek_back()`'d
+                    let value = pop!(self);
+                    self.globals.insert(name.to_owned(), value);
                 }
                 Instruction::SetGlobal(name) => {
-                    let value = self.peek_back().clone();
+                    let value = match self.peek(0) {
+                        Some(value) => value.clone(),
+                        None => return Some(self.error("stack underflow")),
+                    };
                     self.globals.insert(name.to_owned(), value);
                     // we do not pop the value because `(x = 3) + 1` should be a valid expression
                     // where 3 will be on the stack therefore summing up with 1 and giving the result.
@@ -305,8 +628,9 @@ impl VirtualMachine {
                 Instruction::SetLocal(index) => {
                     let index = self.frames.last().unwrap().slot_offset + *index;
 
-                    let value = match self.stack.pop_back() {
-                        Some(d) => d,
+                    // the value stays on the stack like with `SetGlobal`
+                    let value = match self.stack.back() {
+                        Some(d) => d.clone(),
                         None => {
                             return Some(self.error("no value for local variable to set"));
                         }
@@ -316,18 +640,23 @@ impl VirtualMachine {
                     if let Some(local) = local {
                         *local = value;
                     } else {
-                        self.error("no such local variable in the scope");
+                        return Some(self.error("no such local variable in the scope"));
                     }
                 }
                 Instruction::Pop => {
-                    self.stack.pop_back();
+                    pop!(self);
                 }
                 Instruction::Call(arg_count) => {
-                    let function = self.peek(*arg_count as usize).to_owned();
-                    let value = self.call_value(function, *arg_count);
+                    let function = match self.peek(*arg_count as usize) {
+                        Some(function) => function.clone(),
+                        None => return Some(self.error("stack underflow")),
+                    };
+                    let value = self.call_value(function, *arg_count, false);
 
                     match value {
                         CallResult::Err => return Some(self.error("Cant call a non-function")),
+                        CallResult::ErrMessage(err) => return Some(err),
+                        CallResult::StackOverflow => return Some(self.error("stack overflow")),
                         CallResult::OkNative => {
                             // because native functions dont have RETURN
                             self.frames.last_mut().unwrap().ip += 1;
@@ -336,57 +665,115 @@ impl VirtualMachine {
                     }
                     continue;
                 }
+                Instruction::TailCall(arg_count) => {
+                    let function = match self.peek(*arg_count as usize) {
+                        Some(function) => function.clone(),
+                        None => return Some(self.error("stack underflow")),
+                    };
+                    let value = self.call_value(function, *arg_count, true);
+
+                    match value {
+                        CallResult::Err => return Some(self.error("Cant call a non-function")),
+                        CallResult::ErrMessage(err) => return Some(err),
+                        CallResult::StackOverflow => return Some(self.error("stack overflow")),
+                        CallResult::OkNative => {
+                            // the `Return` after the call hands the result back
+                            self.frames.last_mut().unwrap().ip += 1;
+                        }
+                        CallResult::Ok => {
+                            // move the callee and its arguments down into the caller's slots
+                            // and let the callee take over the caller's frame
+                            let callee = self.frames.pop().unwrap();
+                            let slot_offset = self.frames.last().unwrap().slot_offset;
+
+                            let window = self.stack.split_off(callee.slot_offset);
+                            self.stack.truncate(slot_offset);
+                            self.stack.extend(window);
+
+                            *self.frames.last_mut().unwrap() = CallFrame {
+                                slot_offset,
+                                ..callee
+                            };
+                        }
+                    }
+                    continue;
+                }
                 Instruction::JumpIfFalse(offset) => {
-                    if self.peek(0).is_falsey() {
+                    let condition = match self.peek(0) {
+                        Some(condition) => condition,
+                        None => return Some(self.error("stack underflow")),
+                    };
+
+                    if condition.is_falsey() {
                         self.frames.last_mut().unwrap().ip += *offset as usize;
                     }
                 }
                 Instruction::Jump(offset) => {
                     self.frames.last_mut().unwrap().ip += *offset as usize;
                 }
+                Instruction::Loop(offset) => {
+                    // lands right on the loop start, going one before it and stepping past
+                    // the `Loop` underflows when the loop is the first thing in the chunk
+                    let offset = *offset as usize;
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.ip = frame.ip + 1 - offset;
+                    continue;
+                }
                 Instruction::Equal => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
 
                     self.stack.push_back(Constant::Bool(a == b));
                 }
                 Instruction::NotEqual => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
 
                     self.stack.push_back(Constant::Bool(a != b));
                 }
                 Instruction::Greater => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
+                    if let Some(err) = self.check_comparable(&a, &b) {
+                        return Some(err);
+                    }
 
                     self.stack.push_back(Constant::Bool(a > b));
                 }
                 Instruction::GreaterEq => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
+                    if let Some(err) = self.check_comparable(&a, &b) {
+                        return Some(err);
+                    }
 
                     self.stack.push_back(Constant::Bool(a >= b));
                 }
                 Instruction::Lesser => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
+                    if let Some(err) = self.check_comparable(&a, &b) {
+                        return Some(err);
+                    }
 
                     self.stack.push_back(Constant::Bool(a < b));
                 }
                 Instruction::LesserEq => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
+                    let b = pop!(self);
+                    let a = pop!(self);
+                    if let Some(err) = self.check_comparable(&a, &b) {
+                        return Some(err);
+                    }
 
                     self.stack.push_back(Constant::Bool(a <= b));
                 }
                 Instruction::Not => {
-                    let value = self.stack.pop_back().unwrap();
+                    let value = pop!(self);
 
                     self.stack.push_back(Constant::Bool(value.is_falsey()));
                 }
                 Instruction::Negate => {
-                    let value = self.stack.pop_back().unwrap();
+                    let value = pop!(self);
 
                     match value {
                         Constant::Number(n) => self.stack.push_back(Constant::Number(-n)),
@@ -399,18 +786,21 @@ impl VirtualMachine {
                     }
                 }
                 Instruction::IndexInto => {
-                    let index = self.stack.pop_back().unwrap();
-                    let array_value = self.stack.pop_back().unwrap();
+                    let index = pop!(self);
+                    let array_value = pop!(self);
 
                     let index = match index {
-                        Constant::Number(n) => n as usize,
+                        Constant::Number(n) => match number_to_usize(n) {
+                            Ok(index) => index,
+                            Err(err) => return Some(self.error(&err)),
+                        },
                         _ => return Some(self.error("Invalid index")),
                     };
 
                     if let Constant::String(s) = array_value {
-                        let character = s.chars().nth(index);
+                        let character = self.char_at(&s, index);
                         self.stack.push_back(match character {
-                            Some(c) => Constant::String(String::from(c)),
+                            Some(c) => Constant::String(Rc::new(String::from(c))),
                             None => Constant::None,
                         });
                     } else if let Constant::Array(array) = array_value {
@@ -429,11 +819,81 @@ impl VirtualMachine {
 
                     // println!("Indexing: {:?}, into array: {:?}", index, array_value);
                 }
+                Instruction::IndexSetLocal(index) => {
+                    let value = pop!(self);
+                    let element_index = pop!(self);
+                    let index = self.frames.last().unwrap().slot_offset + *index;
+
+                    let result = match self.stack.get_mut(index) {
+                        Some(array) => set_element(array, &element_index, value.clone()),
+                        None => Err("no such local variable in the scope".to_owned()),
+                    };
+                    if let Err(err) = result {
+                        return Some(self.error(&err));
+                    }
+
+                    self.stack.push_back(value);
+                }
+                Instruction::IndexSetGlobal(name) => {
+                    let value = pop!(self);
+                    let element_index = pop!(self);
+
+                    let result = match self.globals.get_mut(name) {
+                        Some(array) => set_element(array, &element_index, value.clone()),
+                        None => Err(format!("no global with name '{}' exists", name)),
+                    };
+                    if let Err(err) = result {
+                        return Some(self.error(&err));
+                    }
+
+                    self.stack.push_back(value);
+                }
+                Instruction::ForIter(slot, exit_offset) => {
+                    let (slot, exit_offset) = (*slot, *exit_offset as usize);
+                    let base = self.frames.last().unwrap().slot_offset + slot;
+
+                    let index = match self.stack.get(base + 1) {
+                        Some(Constant::Number(n)) => *n as usize,
+                        _ => return Some(self.error("no loop index in the scope")),
+                    };
+
+                    // only the current element is cloned, the array itself stays behind the `Rc`
+                    // and its length is the one it had when the loop started. strings are
+                    // iterated by char, their index is the byte offset of the next char.
+                    let element = match self.stack.get(base) {
+                        Some(Constant::Array(array)) => {
+                            array.get(index).map(|element| (element.clone(), index + 1))
+                        }
+                        Some(Constant::String(s)) => s.get(index..).and_then(|rest| {
+                            rest.chars().next().map(|c| {
+                                (
+                                    Constant::String(Rc::new(String::from(c))),
+                                    index + c.len_utf8(),
+                                )
+                            })
+                        }),
+                        Some(value) => {
+                            return Some(self.error(&format!(
+                                "Can only iterate over a string or array, got: {}",
+                                value.get_pretty_type()
+                            )))
+                        }
+                        None => return Some(self.error("no loop target in the scope")),
+                    };
+
+                    match element {
+                        Some((element, next_index)) => {
+                            self.stack[base + 1] = Constant::Number(next_index as f64);
+                            self.stack.push_back(element);
+                        }
+                        None => self.frames.last_mut().unwrap().ip += exit_offset,
+                    }
+                }
                 Instruction::ArrayLiteral(offset) => {
                     let mut values = Vec::new();
 
                     for _ in 0..*offset {
-                        values.push(self.stack.pop_back().unwrap());
+                        values.push(pop!(self));
                     }
 
                     values.reverse();
@@ -441,17 +901,18 @@ impl VirtualMachine {
                 }
                 Instruction::Return => {
                     // self.stack.truncate(self.frames.last().unwrap().slot_offset);
-                    let ret_val = self.stack.pop_back().unwrap();
+                    let ret_val = pop!(self);
 
                     let offset = self.frames.last().unwrap().slot_offset;
                     self.frames.pop();
 
-                    if self.frames.is_empty() {
-                        return None;
-                    }
-
                     self.stack.truncate(offset);
                     self.stack.push_back(ret_val);
+
+                    // returning from the script itself empties the frames
+                    if self.frames.len() == return_depth {
+                        return None;
+                    }
                 }
             }
 
@@ -459,3 +920,670 @@ impl VirtualMachine {
         }
     }
 }
+
+/// Converts a number used as an index, `as` would silently truncate fractions and saturate
+/// negative, NaN and huge values.
+fn number_to_usize(n: f64) -> Result<usize, String> {
+    if n.is_nan() || n.fract() != 0.0 {
+        return Err(format!("index must be an integer, got: {}", n));
+    }
+    if n < 0.0 {
+        return Err(format!("index must not be negative, got: {}", n));
+    }
+    // `usize::MAX as f64` rounds up to 2^64 which doesn't fit anymore
+    if n >= usize::MAX as f64 {
+        return Err(format!("index is too large, got: {:e}", n));
+    }
+
+    Ok(n as usize)
+}
+
+/// Sets the element of the array, the array is cloned first if it's shared.
+fn set_element(array: &mut Constant, index: &Constant, value: Constant) -> Result<(), String> {
+    let array = match array {
+        Constant::Array(array) => array,
+        other => {
+            return Err(format!(
+                "can only assign to an element of an array, got: {}",
+                other.get_pretty_type()
+            ))
+        }
+    };
+
+    let index = match index {
+        Constant::Number(n) => number_to_usize(*n)?,
+        other => {
+            return Err(format!(
+                "index must be a non-negative integer, got: {}",
+                other
+            ))
+        }
+    };
+
+    if index >= array.len() {
+        return Err(format!(
+            "index {} is out of bounds for an array of length {}",
+            index,
+            array.len()
+        ));
+    }
+
+    Rc::make_mut(array)[index] = value;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bytecode::{Chunk, Function, FunctionType},
+        compile,
+    };
+
+    /// A VM that already ran the top-level statements of the source.
+    fn interpreted(source: &str) -> VirtualMachine {
+        let chunk = compile(source).unwrap();
+        let mut vm =
+            VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+        assert_eq!(vm.interpret(), None);
+        vm
+    }
+
+    const COUNTDOWN: &str = "func count(n) {\nif n == 0 {\nret 0\n}\nret count(n - 1)\n}\nfunc sum(n) {\nif n == 0 {\nret 0\n}\nret n + sum(n - 1)\n}";
+
+    #[test]
+    fn tail_calls_run_deeper_than_the_frame_limit() {
+        let mut vm = interpreted(COUNTDOWN);
+        assert_eq!(
+            vm.run_function("count", vec![Constant::Number(100_000.0)]),
+            Ok(Constant::Number(0.0))
+        );
+    }
+
+    #[test]
+    fn tail_call_at_the_frame_limit() {
+        let mut vm = interpreted(COUNTDOWN);
+        // the frame of `count` is the only one allowed, every tail call reuses it
+        vm.set_frame_limit(1);
+        assert_eq!(
+            vm.run_function("count", vec![Constant::Number(3.0)]),
+            Ok(Constant::Number(0.0))
+        );
+
+        // `sum` adds to the result of the call so it needs a new frame every time
+        assert!(vm.run_function("sum", vec![Constant::Number(3.0)]).is_err());
+    }
+
+    #[test]
+    fn else_branch_is_taken() {
+        let mut vm = interpreted(
+            "func pick(n) {\nif n > 1 {\nret \"then\"\n} else {\nret \"else\"\n}\nret \"after\"\n}",
+        );
+
+        assert_eq!(
+            vm.run_function("pick", vec![Constant::Number(0.0)]),
+            Ok(Constant::String(Rc::new("else".to_owned())))
+        );
+        assert_eq!(
+            vm.run_function("pick", vec![Constant::Number(2.0)]),
+            Ok(Constant::String(Rc::new("then".to_owned())))
+        );
+    }
+
+    fn string(s: &str) -> Constant {
+        Constant::String(Rc::new(s.to_owned()))
+    }
+
+    #[test]
+    fn strings_are_indexed_by_char() {
+        let mut vm = interpreted("func check(s) {\nret [len(s), s[0], s[1], s[5], s[6]]\n}");
+        vm.define_std_collections();
+
+        assert_eq!(
+            vm.run_function("check", vec![string("héllo😀")]),
+            Ok(Constant::Array(Rc::new(vec![
+                Constant::Number(6.0),
+                string("h"),
+                string("é"),
+                string("😀"),
+                Constant::None,
+            ])))
+        );
+    }
+
+    #[test]
+    fn indexing_different_strings_in_turn() {
+        let mut vm = interpreted(
+            "func pairs(a, b) {\nret [a[1], b[1], a[2], b[2]]\n}\nfunc grow(s) {\nvar first = s[1]\ns = s + \"ü\"\nret [first, s[3], len(s)]\n}",
+        );
+        vm.define_std_collections();
+
+        assert_eq!(
+            vm.run_function("pairs", vec![string("äöü"), string("abc")]),
+            Ok(Constant::Array(Rc::new(vec![
+                string("ö"),
+                string("b"),
+                string("ü"),
+                string("c"),
+            ])))
+        );
+
+        // the string changed after it was indexed, its old offsets must not be used
+        assert_eq!(
+            vm.run_function("grow", vec![string("aßc")]),
+            Ok(Constant::Array(Rc::new(vec![
+                string("ß"),
+                string("ü"),
+                Constant::Number(4.0),
+            ])))
+        );
+    }
+
+    #[test]
+    fn concatenating_into_a_variable() {
+        let mut vm = interpreted(
+            "var g = \"a\"\nfunc build(n) {\nvar s = \"\"\nvar kept = s\nvar i = 0\nwhile i < n {\ns = s + i\nkept = s\ni = i + 1\n}\ng = g + s\ng = g + g\nret [s, kept, g]\n}",
+        );
+
+        assert_eq!(
+            vm.run_function("build", vec![Constant::Number(4.0)]),
+            Ok(Constant::Array(Rc::new(vec![
+                string("0123"),
+                string("0123"),
+                string("a0123a0123"),
+            ])))
+        );
+    }
+
+    #[test]
+    fn concatenation_leaves_other_holders_alone() {
+        let mut vm =
+            interpreted("func build {\nvar s = \"ab\"\nvar t = s\ns = s + \"c\"\nret [s, t]\n}");
+
+        assert_eq!(
+            vm.run_function("build", vec![]),
+            Ok(Constant::Array(Rc::new(vec![string("abc"), string("ab")])))
+        );
+    }
+
+    #[test]
+    fn strings_do_not_index_arrays() {
+        let mut vm = interpreted("func get(a) {\nret a[\"b\"]\n}");
+
+        let array = Constant::Array(Rc::new(vec![Constant::Number(1.0)]));
+        let err = vm.run_function("get", vec![array]).unwrap_err();
+        assert!(err.contains("Invalid index"), "{}", err);
+    }
+
+    #[test]
+    fn else_if_chains_take_the_first_match() {
+        let mut vm = interpreted(
+            "func pick(n) {\nif n > 2 {\nret \"big\"\n} else if n > 1 {\nret \"two\"\n} else if n > 0 {\nret \"one\"\n} else {\nret \"none\"\n}\n}",
+        );
+
+        for (n, picked) in [(3.0, "big"), (2.0, "two"), (1.0, "one"), (0.0, "none")] {
+            assert_eq!(
+                vm.run_function("pick", vec![Constant::Number(n)]),
+                Ok(string(picked))
+            );
+        }
+    }
+
+    #[test]
+    fn else_if_without_else_falls_through() {
+        let mut vm = interpreted(
+            "func pick(n) {\nvar x = \"none\"\nif n == 1 {\nx = \"one\"\n} else if n == 2 {\nx = \"two\"\n}\nret x\n}",
+        );
+
+        assert_eq!(
+            vm.run_function("pick", vec![Constant::Number(2.0)]),
+            Ok(string("two"))
+        );
+        assert_eq!(
+            vm.run_function("pick", vec![Constant::Number(5.0)]),
+            Ok(string("none"))
+        );
+    }
+
+    #[test]
+    fn continued_lines_are_one_expression() {
+        let mut vm = interpreted("func f {\nret 1 +\n2\n}\n\n\nfunc g {\n\nret [1,\n2]\n}");
+
+        assert_eq!(vm.run_function("f", vec![]), Ok(Constant::Number(3.0)));
+        assert_eq!(
+            vm.run_function("g", vec![]),
+            Ok(Constant::Array(Rc::new(vec![
+                Constant::Number(1.0),
+                Constant::Number(2.0)
+            ])))
+        );
+    }
+
+    #[test]
+    fn negation() {
+        let mut vm = interpreted(
+            "func neg(x) {\nret -x\n}\nfunc twice(x) {\nret --x\n}\nfunc sum {\nret -5 + 2\n}",
+        );
+
+        assert_eq!(
+            vm.run_function("neg", vec![Constant::Number(4.0)]),
+            Ok(Constant::Number(-4.0))
+        );
+        assert_eq!(
+            vm.run_function("twice", vec![Constant::Number(4.0)]),
+            Ok(Constant::Number(4.0))
+        );
+        assert_eq!(vm.run_function("sum", vec![]), Ok(Constant::Number(-3.0)));
+
+        let err = vm.run_function("neg", vec![string("4")]).unwrap_err();
+        assert!(err.contains("Can only negate a number, got:"), "{}", err);
+    }
+
+    #[test]
+    fn while_counts_down() {
+        let mut vm = interpreted(
+            "func countdown(n) {\nvar iterations = 0\nwhile n > 0 {\nn = n - 1\niterations = iterations + 1\n}\nret iterations\n}",
+        );
+
+        assert_eq!(
+            vm.run_function("countdown", vec![Constant::Number(3.0)]),
+            Ok(Constant::Number(3.0))
+        );
+        assert_eq!(
+            vm.run_function("countdown", vec![Constant::Number(0.0)]),
+            Ok(Constant::Number(0.0))
+        );
+    }
+
+    #[test]
+    fn loops_can_start_the_chunk() {
+        // the loop start is the first instruction of the function
+        let mut vm =
+            interpreted("func drain(a) {\nwhile a[0] > 0 {\na[0] = a[0] - 1\n}\nret a[0]\n}");
+
+        let array = Constant::Array(Rc::new(vec![Constant::Number(2.0)]));
+        assert_eq!(
+            vm.run_function("drain", vec![array]),
+            Ok(Constant::Number(0.0))
+        );
+    }
+
+    #[test]
+    fn cancelling_stops_an_endless_loop() {
+        let chunk = compile("while true {\n}").unwrap();
+        let mut vm =
+            VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+
+        let handle = vm.cancellation_handle();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            handle.cancel();
+        });
+
+        let err = vm.interpret().unwrap();
+        assert!(err.contains("the script was cancelled"), "{}", err);
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn one_handle_cancels_many_vms() {
+        let handle = CancellationHandle::new();
+        let mut vms = ["while true {\n}", "var a = 1"].map(|source| {
+            let chunk = compile(source).unwrap();
+            let mut vm =
+                VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+            vm.set_cancellation_handle(handle.clone());
+            vm
+        });
+
+        handle.cancel();
+        for vm in &mut vms {
+            let err = vm.interpret().unwrap();
+            assert!(err.contains("the script was cancelled"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn unbounded_recursion_hits_the_frame_limit() {
+        let mut vm = interpreted("func f(n) {\nret 1 + f(n + 1)\n}");
+
+        let err = vm
+            .run_function("f", vec![Constant::Number(0.0)])
+            .unwrap_err();
+        assert!(err.contains("stack overflow"), "{}", err);
+
+        // the whole script overflows the same way instead of aborting the process
+        let chunk = compile("func f {\nret 1 + f()\n}\nf()").unwrap();
+        let mut vm =
+            VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+        let err = vm.interpret().unwrap();
+        assert!(err.contains("stack overflow"), "{}", err);
+    }
+
+    #[test]
+    fn tail_calls_do_not_use_up_frames() {
+        let mut vm = interpreted(COUNTDOWN);
+
+        // far more calls than frames, each one replaces the frame of the last
+        vm.set_frame_limit(2);
+        assert_eq!(
+            vm.run_function(
+                "count",
+                vec![Constant::Number((DEFAULT_FRAME_LIMIT * 10) as f64)]
+            ),
+            Ok(Constant::Number(0.0))
+        );
+        assert!(vm
+            .run_function("sum", vec![Constant::Number(3.0)])
+            .unwrap_err()
+            .contains("stack overflow"));
+    }
+
+    #[test]
+    fn bare_instructions_underflow_cleanly() {
+        // the script itself takes the first slot so it takes two pops to run out
+        for code in [
+            vec![Instruction::Add],
+            vec![Instruction::Subtract],
+            vec![Instruction::Equal],
+            vec![Instruction::Pop, Instruction::Pop],
+        ] {
+            let mut chunk = Chunk::default();
+            for instruction in code {
+                chunk.add_instruction(instruction, 1);
+            }
+            chunk.add_instruction(Instruction::Return, 1);
+
+            let mut vm =
+                VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+            let err = vm.interpret().unwrap();
+            assert!(err.contains("stack underflow"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn numbers_become_indices_only_when_exact() {
+        assert_eq!(number_to_usize(0.0), Ok(0));
+        assert_eq!(number_to_usize(3.0), Ok(3));
+
+        for (n, message) in [
+            (f64::NAN, "index must be an integer"),
+            (f64::INFINITY, "index must be an integer"),
+            (1.5, "index must be an integer"),
+            (-1.0, "index must not be negative"),
+            (f64::NEG_INFINITY, "index must be an integer"),
+            (1e20, "index is too large"),
+            (usize::MAX as f64, "index is too large"),
+        ] {
+            let err = number_to_usize(n).unwrap_err();
+            assert!(err.starts_with(message), "{}: {}", n, err);
+        }
+    }
+
+    #[test]
+    fn bad_indices_are_errors() {
+        let mut vm = interpreted(
+            "func get(i) {\nret [1, 2][i]\n}\nfunc set(i) {\nvar a = [1, 2]\na[i] = 3\nret a\n}",
+        );
+
+        for index in [f64::NAN, 0.5, -1.0, 1e20] {
+            for function in ["get", "set"] {
+                let err = vm
+                    .run_function(function, vec![Constant::Number(index)])
+                    .unwrap_err();
+                assert!(err.contains("index"), "{}({}): {}", function, index, err);
+            }
+        }
+        assert_eq!(
+            vm.run_function("get", vec![Constant::Number(1.0)]),
+            Ok(Constant::Number(2.0))
+        );
+    }
+
+    #[test]
+    fn arity_mismatches_are_errors() {
+        let mut vm = interpreted(
+            "func add(a, b) {\nret a + b\n}\nfunc tail {\nret add(1)\n}\nfunc call {\nret add(1) + 1\n}\nfunc three {\nret add(1, 2, 3)\n}",
+        );
+
+        // checked for tail calls and plain calls alike
+        for caller in ["tail", "call"] {
+            let err = vm.run_function(caller, vec![]).unwrap_err();
+            assert!(
+                err.contains("Function 'add' accepts 2 arguments but 1 were provided."),
+                "{}",
+                err
+            );
+        }
+        let err = vm.run_function("three", vec![]).unwrap_err();
+        assert!(
+            err.contains("Function 'add' accepts 2 arguments but 3 were provided."),
+            "{}",
+            err
+        );
+
+        // the VM is still usable after the error
+        assert_eq!(
+            vm.run_function("add", vec![Constant::Number(1.0), Constant::Number(2.0)]),
+            Ok(Constant::Number(3.0))
+        );
+    }
+
+    fn numbers(values: &[f64]) -> Constant {
+        Constant::Array(Rc::new(
+            values.iter().map(|n| Constant::Number(*n)).collect(),
+        ))
+    }
+
+    #[test]
+    fn index_assignment() {
+        let mut vm = interpreted(
+            "var g = [1, 2, 3]\ng[0] = 7\nfunc local {\nvar a = [1, 2, 3]\na[1] = 9\nret a\n}\nfunc global {\nret g\n}\nfunc copy {\nvar a = [1, 2]\nvar b = a\nb[0] = 5\nret [a, b]\n}",
+        );
+
+        assert_eq!(
+            vm.run_function("local", vec![]),
+            Ok(numbers(&[1.0, 9.0, 3.0]))
+        );
+        assert_eq!(
+            vm.run_function("global", vec![]),
+            Ok(numbers(&[7.0, 2.0, 3.0]))
+        );
+
+        // arrays are values, assigning through one name leaves the other alone
+        assert_eq!(
+            vm.run_function("copy", vec![]),
+            Ok(Constant::Array(Rc::new(vec![
+                numbers(&[1.0, 2.0]),
+                numbers(&[5.0, 2.0])
+            ])))
+        );
+    }
+
+    #[test]
+    fn index_assignment_errors() {
+        let mut vm = interpreted("func set(a, i) {\na[i] = 0\nret a\n}");
+
+        let err = vm
+            .run_function("set", vec![numbers(&[1.0]), Constant::Number(1.0)])
+            .unwrap_err();
+        assert!(
+            err.contains("index 1 is out of bounds for an array of length 1"),
+            "{}",
+            err
+        );
+
+        let err = vm
+            .run_function("set", vec![string("a"), Constant::Number(0.0)])
+            .unwrap_err();
+        assert!(
+            err.contains("can only assign to an element of an array"),
+            "{}",
+            err
+        );
+
+        let err = vm
+            .run_function("set", vec![numbers(&[1.0]), string("0")])
+            .unwrap_err();
+        assert!(
+            err.contains("index must be a non-negative integer"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn runtime_errors_report_their_line() {
+        let mut vm = interpreted("func f(x) {\nvar a = 1\n\nvar b = x + a\nret -x\n}");
+
+        let err = vm.run_function("f", vec![Constant::None]).unwrap_err();
+        assert!(err.contains("at line '4'"), "{}", err);
+
+        let err = vm.run_function("f", vec![string("1")]).unwrap_err();
+        assert!(err.contains("at line '5'"), "{}", err);
+
+        // the top-level statements report their own lines too
+        let chunk = compile("var a = 1\n\n\nvar b = a[0]").unwrap();
+        let mut vm =
+            VirtualMachine::new(Function::new(0, chunk, "".to_owned(), FunctionType::Script));
+        let err = vm.interpret().unwrap();
+        assert!(err.contains("at line '4'"), "{}", err);
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let mut vm = interpreted(
+            "func and {\nret true && false\n}\nfunc or {\nret false || 5\n}\nfunc chained {\nret false || none || 1 && \"x\"\n}\nfunc skipped {\nret [false && missing(), true || missing()]\n}",
+        );
+
+        assert_eq!(vm.run_function("and", vec![]), Ok(Constant::Bool(false)));
+        assert_eq!(vm.run_function("or", vec![]), Ok(Constant::Number(5.0)));
+        assert_eq!(vm.run_function("chained", vec![]), Ok(string("x")));
+
+        // the right-hand side would fail, it isn't evaluated once the left decides
+        assert_eq!(
+            vm.run_function("skipped", vec![]),
+            Ok(Constant::Array(Rc::new(vec![
+                Constant::Bool(false),
+                Constant::Bool(true)
+            ])))
+        );
+    }
+
+    #[test]
+    fn assignment() {
+        let mut vm = interpreted(
+            "var g = 1\ng = g + 2\nfunc global {\nret g\n}\nfunc local {\nvar x = 1\nx = x + 2\nret x\n}\nfunc value {\nvar x = 0\nvar y = x = 4\nret [x, y]\n}",
+        );
+
+        assert_eq!(vm.run_function("global", vec![]), Ok(Constant::Number(3.0)));
+        assert_eq!(vm.run_function("local", vec![]), Ok(Constant::Number(3.0)));
+
+        // an assignment is an expression with the assigned value
+        assert_eq!(
+            vm.run_function("value", vec![]),
+            Ok(Constant::Array(Rc::new(vec![
+                Constant::Number(4.0),
+                Constant::Number(4.0)
+            ])))
+        );
+    }
+
+    #[test]
+    fn for_loops_visit_every_element() {
+        let mut vm = interpreted(
+            "func sum(a) {\nvar total = 0\nfor x in a {\ntotal = total + x\n}\nret total\n}\nfunc chars(s) {\nvar out = \"\"\nfor c in s {\nout = c + out\n}\nret out\n}",
+        );
+
+        assert_eq!(
+            vm.run_function("sum", vec![numbers(&[1.0, 2.0, 3.0])]),
+            Ok(Constant::Number(6.0))
+        );
+        assert_eq!(
+            vm.run_function("sum", vec![numbers(&[])]),
+            Ok(Constant::Number(0.0))
+        );
+        assert_eq!(
+            vm.run_function("chars", vec![string("abc")]),
+            Ok(string("cba"))
+        );
+
+        let err = vm
+            .run_function("sum", vec![Constant::Number(3.0)])
+            .unwrap_err();
+        assert!(
+            err.contains("Can only iterate over a string or array"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut vm = interpreted("func cmp(a, b) {\nret [a < b, a <= b, a > b, a >= b]\n}");
+        let compared = |results: [bool; 4]| {
+            Ok(Constant::Array(Rc::new(
+                results.into_iter().map(Constant::Bool).collect(),
+            )))
+        };
+
+        for (a, b, results) in [
+            (3.0, 5.0, [true, true, false, false]),
+            (5.0, 3.0, [false, false, true, true]),
+            (5.0, 5.0, [false, true, false, true]),
+            (-1.5, -1.0, [true, true, false, false]),
+        ] {
+            assert_eq!(
+                vm.run_function("cmp", vec![Constant::Number(a), Constant::Number(b)]),
+                compared(results),
+                "{} {}",
+                a,
+                b
+            );
+        }
+
+        assert_eq!(
+            vm.run_function("cmp", vec![string("a"), string("b")]),
+            compared([true, true, false, false])
+        );
+
+        let err = vm
+            .run_function("cmp", vec![Constant::Number(1.0), string("1")])
+            .unwrap_err();
+        assert!(err.contains("cannot compare"), "{}", err);
+    }
+
+    #[test]
+    fn modulo() {
+        let mut vm =
+            interpreted("func rem(a, b) {\nret a % b\n}\nfunc order {\nret 1 + 10 % 3 * 2\n}");
+        let rem = |vm: &mut VirtualMachine, a: f64, b: f64| {
+            vm.run_function("rem", vec![Constant::Number(a), Constant::Number(b)])
+        };
+
+        assert_eq!(rem(&mut vm, 10.0, 3.0), Ok(Constant::Number(1.0)));
+        assert_eq!(rem(&mut vm, 9.0, 3.0), Ok(Constant::Number(0.0)));
+        assert_eq!(rem(&mut vm, 5.5, 2.0), Ok(Constant::Number(1.5)));
+        // the sign follows the left-hand side
+        assert_eq!(rem(&mut vm, -7.0, 3.0), Ok(Constant::Number(-1.0)));
+
+        // binds like `*` and `/`
+        assert_eq!(vm.run_function("order", vec![]), Ok(Constant::Number(3.0)));
+
+        let err = rem(&mut vm, 10.0, 0.0).unwrap_err();
+        assert!(
+            err.contains("cannot take the remainder of a division by zero"),
+            "{}",
+            err
+        );
+
+        let err = vm
+            .run_function("rem", vec![string("10"), Constant::Number(3.0)])
+            .unwrap_err();
+        assert!(
+            err.contains("cannot take the remainder of non-numbers"),
+            "{}",
+            err
+        );
+    }
+}